@@ -0,0 +1,37 @@
+//! Encode and decode throughput of every codec on a synthetic recording
+//! shaped like the dataset (five seconds of 19531 Hz mono on a 64-step grid).
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use smallbrain::codec::{decompress, Codec};
+use smallbrain::synth::{generate, SynthConfig};
+use std::hint::black_box;
+
+fn codecs(c: &mut Criterion) {
+    let (samples, spec) = generate(&SynthConfig::default());
+    let throughput = Throughput::ElementsAndBytes {
+        elements: samples.len() as u64,
+        bytes: samples.len() as u64 * 2,
+    };
+
+    let mut encode = c.benchmark_group("encode");
+    encode.throughput(throughput.clone()).sample_size(20);
+    for &codec in Codec::ALL {
+        encode.bench_function(BenchmarkId::from_parameter(codec.name()), |b| {
+            b.iter(|| codec.compress(black_box(&samples), &spec).unwrap())
+        });
+    }
+    encode.finish();
+
+    let mut decode = c.benchmark_group("decode");
+    decode.throughput(throughput).sample_size(20);
+    for &codec in Codec::ALL {
+        let compressed = codec.compress(&samples, &spec).unwrap();
+        decode.bench_function(BenchmarkId::from_parameter(codec.name()), |b| {
+            b.iter(|| decompress(black_box(&compressed)).unwrap())
+        });
+    }
+    decode.finish();
+}
+
+criterion_group!(benches, codecs);
+criterion_main!(benches);
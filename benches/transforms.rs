@@ -0,0 +1,159 @@
+//! Throughput of the building blocks behind the codecs: 10-bit packing,
+//! prediction and the residual entropy coders, on the same synthetic
+//! recording as the codec benches.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use smallbrain::ans::{
+    decode_residuals_adaptive, decode_residuals_static, encode_residuals_adaptive,
+    encode_residuals_static,
+};
+use smallbrain::arith::{ArithmeticDecoder, ArithmeticEncoder, BitModel};
+use smallbrain::bitpack::{BitOrder, BitReader, BitWriter};
+use smallbrain::lms::{LmsCascade, DEFAULT_STAGES};
+use smallbrain::predictor::{fixed_block_residuals, restore_fixed_blocks};
+use smallbrain::rice::{decode_residuals, encode_residuals};
+use smallbrain::synth::{generate, SynthConfig};
+use smallbrain::tenbit::{
+    pack_10_bit_values, scale_from_10_bits, scale_to_10_bits, unpack_10_bit_values,
+};
+use std::hint::black_box;
+
+fn throughput(samples: usize) -> Throughput {
+    Throughput::ElementsAndBytes {
+        elements: samples as u64,
+        bytes: samples as u64 * 2,
+    }
+}
+
+fn tenbit(c: &mut Criterion) {
+    let (samples, _) = generate(&SynthConfig::default());
+    let scaled = scale_to_10_bits(&samples);
+    let packed = pack_10_bit_values(&scaled).unwrap();
+
+    let mut group = c.benchmark_group("tenbit");
+    group.throughput(throughput(samples.len()));
+    group.bench_function("scale", |b| {
+        b.iter(|| scale_to_10_bits(black_box(&samples)))
+    });
+    group.bench_function("unscale", |b| {
+        b.iter(|| scale_from_10_bits(black_box(&scaled)))
+    });
+    group.bench_function("pack", |b| {
+        b.iter(|| pack_10_bit_values(black_box(&scaled)).unwrap())
+    });
+    group.bench_function("unpack", |b| {
+        b.iter(|| unpack_10_bit_values(black_box(&packed)).unwrap())
+    });
+    group.finish();
+}
+
+fn predictors(c: &mut Criterion) {
+    let (samples, _) = generate(&SynthConfig::default());
+    let values: Vec<i32> = samples.iter().map(|&s| s as i32).collect();
+    let (orders, residuals) = fixed_block_residuals(&values);
+    let lms_residuals = LmsCascade::new(DEFAULT_STAGES).encode(&values);
+
+    let mut group = c.benchmark_group("predictor");
+    group.throughput(throughput(values.len()));
+    group.bench_function("fixed/encode", |b| {
+        b.iter(|| fixed_block_residuals(black_box(&values)))
+    });
+    group.bench_function("fixed/decode", |b| {
+        b.iter(|| restore_fixed_blocks(black_box(&orders), black_box(&residuals)))
+    });
+    group.sample_size(20);
+    group.bench_function("lms/encode", |b| {
+        b.iter(|| LmsCascade::new(DEFAULT_STAGES).encode(black_box(&values)))
+    });
+    group.bench_function("lms/decode", |b| {
+        b.iter(|| LmsCascade::new(DEFAULT_STAGES).decode(black_box(&lms_residuals)))
+    });
+    group.finish();
+}
+
+type Encoder = fn(&mut BitWriter, &[i32]);
+type Decoder =
+    fn(&mut BitReader, usize) -> Result<Vec<i32>, Box<dyn std::error::Error + Send + Sync>>;
+
+fn entropy_coders(c: &mut Criterion) {
+    let (samples, _) = generate(&SynthConfig::default());
+    let values: Vec<i32> = samples.iter().map(|&s| s as i32).collect();
+    let residuals = fixed_block_residuals(&values).1;
+
+    let coders: [(&str, Encoder, Decoder); 3] = [
+        ("rice", encode_residuals, decode_residuals),
+        ("ans", encode_residuals_static, decode_residuals_static),
+        (
+            "ans-adaptive",
+            encode_residuals_adaptive,
+            decode_residuals_adaptive,
+        ),
+    ];
+
+    let mut group = c.benchmark_group("entropy");
+    group.throughput(throughput(residuals.len()));
+    for (name, encode, decode) in coders {
+        let mut writer = BitWriter::new(BitOrder::Msb);
+        encode(&mut writer, &residuals);
+        let encoded = writer.finish();
+
+        group.bench_function(format!("{}/encode", name), |b| {
+            b.iter(|| {
+                let mut writer = BitWriter::new(BitOrder::Msb);
+                encode(&mut writer, black_box(&residuals));
+                writer.finish()
+            })
+        });
+        group.bench_function(format!("{}/decode", name), |b| {
+            b.iter(|| {
+                let mut reader = BitReader::new(black_box(&encoded), BitOrder::Msb);
+                decode(&mut reader, residuals.len()).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+/// The binary arithmetic coder on the sign bits of the residuals through a
+/// single adaptive model; throughput counts bits, not samples.
+fn arithmetic(c: &mut Criterion) {
+    let (samples, _) = generate(&SynthConfig::default());
+    let values: Vec<i32> = samples.iter().map(|&s| s as i32).collect();
+    let bits: Vec<bool> = fixed_block_residuals(&values)
+        .1
+        .iter()
+        .map(|&r| r < 0)
+        .collect();
+    let mut encoder = ArithmeticEncoder::new();
+    let mut model = BitModel::default();
+    for &bit in &bits {
+        encoder.encode(&mut model, bit);
+    }
+    let encoded = encoder.finish();
+
+    let mut group = c.benchmark_group("arith");
+    group.throughput(Throughput::Elements(bits.len() as u64));
+    group.bench_function("encode", |b| {
+        b.iter(|| {
+            let mut encoder = ArithmeticEncoder::new();
+            let mut model = BitModel::default();
+            for &bit in black_box(&bits) {
+                encoder.encode(&mut model, bit);
+            }
+            encoder.finish()
+        })
+    });
+    group.bench_function("decode", |b| {
+        b.iter(|| {
+            let mut decoder = ArithmeticDecoder::new(black_box(&encoded));
+            let mut model = BitModel::default();
+            (0..bits.len())
+                .filter(|_| decoder.decode(&mut model))
+                .count()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, tenbit, predictors, entropy_coders, arithmetic);
+criterion_main!(benches);
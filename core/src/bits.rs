@@ -0,0 +1,105 @@
+/// Where the coders write their bits: [`BitSink`] on a device, the host
+/// crate's `BitWriter` on a computer, so both share one implementation.
+pub trait BitWrite {
+    /// Writes the low `width` bits of `value`, `width` in `1..=32`.
+    fn write_bits(&mut self, value: u32, width: u32);
+
+    fn write_bit(&mut self, bit: bool) {
+        self.write_bits(bit as u32, 1);
+    }
+}
+
+/// MSB-first bit writer over a caller-provided buffer. Writes past the end
+/// are dropped and remembered, so callers check once in [`BitSink::finish`].
+pub struct BitSink<'a> {
+    buffer: &'a mut [u8],
+    position: usize,
+    accumulator: u64,
+    bits: u32,
+    overflowed: bool,
+}
+
+impl<'a> BitSink<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        BitSink {
+            buffer,
+            position: 0,
+            accumulator: 0,
+            bits: 0,
+            overflowed: false,
+        }
+    }
+
+    /// Writes the low `width` bits of `value`, `width` in `1..=32`.
+    pub fn write_bits(&mut self, value: u32, width: u32) {
+        debug_assert!((1..=32).contains(&width));
+        self.accumulator = (self.accumulator << width) | (value as u64 & ((1u64 << width) - 1));
+        self.bits += width;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.push((self.accumulator >> self.bits) as u8);
+        }
+        self.accumulator &= (1u64 << self.bits) - 1;
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits(bit as u32, 1);
+    }
+
+    fn push(&mut self, byte: u8) {
+        match self.buffer.get_mut(self.position) {
+            Some(slot) => *slot = byte,
+            None => self.overflowed = true,
+        }
+        self.position += 1;
+    }
+
+    /// Pads the last byte with zero bits and returns the number of bytes
+    /// written, or `None` if the buffer was too small.
+    pub fn finish(mut self) -> Option<usize> {
+        if self.bits > 0 {
+            self.write_bits(0, 8 - self.bits);
+        }
+        (!self.overflowed).then_some(self.position)
+    }
+}
+
+impl BitWrite for BitSink<'_> {
+    fn write_bits(&mut self, value: u32, width: u32) {
+        BitSink::write_bits(self, value, width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packs_msb_first_and_pads_the_last_byte() {
+        let mut buffer = [0u8; 4];
+        let mut sink = BitSink::new(&mut buffer);
+        sink.write_bits(0b101, 3);
+        sink.write_bit(true);
+        sink.write_bits(0xABCD, 16);
+        assert_eq!(sink.finish(), Some(3));
+        assert_eq!(buffer, [0b1011_1010, 0xBC, 0xD0, 0]);
+    }
+
+    #[test]
+    fn overflow_is_reported_once_at_the_end() {
+        let mut buffer = [0u8; 2];
+        let mut sink = BitSink::new(&mut buffer);
+        sink.write_bits(0x1234, 16);
+        sink.write_bits(0x56, 8);
+        sink.write_bit(true);
+        assert_eq!(sink.finish(), None);
+        // What fit is kept; nothing is written past the end.
+        assert_eq!(buffer, [0x12, 0x34]);
+
+        let mut exact = [0u8; 2];
+        let mut sink = BitSink::new(&mut exact);
+        sink.write_bits(0x3FFF, 14);
+        assert_eq!(sink.finish(), Some(2));
+        assert_eq!(exact, [0xFF, 0xFC]);
+    }
+}
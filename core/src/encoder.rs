@@ -0,0 +1,260 @@
+use crate::bits::BitSink;
+use crate::predictor::Predictor;
+use crate::rice::{unzigzag, write_value, zigzag, AdaptiveRice, MAX_VALUE_BITS};
+use core::fmt;
+
+/// Bytes of the stream header written by [`Encoder::write_header`].
+pub const HEADER_LEN: usize = 12;
+
+/// Bytes of the frame count in front of every chunk.
+const CHUNK_HEADER_LEN: usize = 2;
+
+/// Most frames one chunk can hold.
+pub const MAX_CHUNK_FRAMES: usize = u16::MAX as usize;
+
+/// Output buffer size that always fits a chunk of `frames` frames of
+/// `channels` channels, whatever the samples.
+pub const fn max_chunk_len(frames: usize, channels: usize) -> usize {
+    CHUNK_HEADER_LEN + (frames * channels * MAX_VALUE_BITS).div_ceil(8)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The output buffer is smaller than [`max_chunk_len`] or [`HEADER_LEN`].
+    BufferTooSmall,
+    /// A sample is not on the configured grid.
+    OffGrid(i16),
+    /// The samples are not a whole number of frames, or too many for a chunk.
+    InvalidChunk,
+    /// Zero channels or step, an offset outside `0..step`, or a channel
+    /// state slice of the wrong length.
+    InvalidConfig,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::BufferTooSmall => write!(f, "output buffer too small"),
+            Error::OffGrid(sample) => write!(f, "sample {} is not on the grid", sample),
+            Error::InvalidChunk => write!(f, "chunk is not a whole number of frames"),
+            Error::InvalidConfig => write!(f, "invalid encoder configuration"),
+        }
+    }
+}
+
+/// Stream parameters. Samples must lie on the grid `offset + k * step`;
+/// a step of 1 accepts any sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub bits_per_sample: u16,
+    pub step: u16,
+    pub offset: u16,
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), Error> {
+        if self.channels == 0 || self.step == 0 || self.offset >= self.step {
+            return Err(Error::InvalidConfig);
+        }
+        Ok(())
+    }
+
+    /// Big-endian header: sample rate, channels, bits per sample, step, offset.
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0; HEADER_LEN];
+        bytes[0..4].copy_from_slice(&self.sample_rate.to_be_bytes());
+        bytes[4..6].copy_from_slice(&self.channels.to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.bits_per_sample.to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.step.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.offset.to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Config, Error> {
+        let header = bytes.get(..HEADER_LEN).ok_or(Error::BufferTooSmall)?;
+        let u16_at = |i: usize| u16::from_be_bytes([header[i], header[i + 1]]);
+        let config = Config {
+            sample_rate: u32::from_be_bytes([header[0], header[1], header[2], header[3]]),
+            channels: u16_at(4),
+            bits_per_sample: u16_at(6),
+            step: u16_at(8),
+            offset: u16_at(10),
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Grid index of `sample`, if it is on the grid.
+    fn index(&self, sample: i16) -> Option<i32> {
+        let shifted = sample as i32 - self.offset as i32;
+        (shifted % self.step as i32 == 0).then_some(shifted / self.step as i32)
+    }
+}
+
+/// Per-channel coder state: the predictor and the Rice statistics.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChannelState {
+    predictor: Predictor,
+    rice: AdaptiveRice,
+}
+
+impl ChannelState {
+    pub const fn new() -> Self {
+        ChannelState {
+            predictor: Predictor::new(),
+            rice: AdaptiveRice::new(),
+        }
+    }
+
+    /// Rice parameter of the next value.
+    pub fn parameter(&self) -> u32 {
+        self.rice.parameter()
+    }
+
+    /// Codes grid index `index`, returning the zigzagged residual to write
+    /// with [`ChannelState::parameter`] as read before the call.
+    pub fn encode(&mut self, index: i32) -> u32 {
+        let value = zigzag(index.wrapping_sub(self.predictor.predict()));
+        self.rice.update(value);
+        self.predictor.update(index);
+        value
+    }
+
+    /// Inverse of [`ChannelState::encode`]: the grid index coded as `value`.
+    pub fn decode(&mut self, value: u32) -> i32 {
+        let index = self.predictor.predict().wrapping_add(unzigzag(value));
+        self.rice.update(value);
+        self.predictor.update(index);
+        index
+    }
+}
+
+/// Encodes interleaved 16-bit samples chunk by chunk into caller-provided
+/// buffers, keeping its state in a caller-provided slice of one
+/// [`ChannelState`] per channel.
+///
+/// Stream layout: the [`HEADER_LEN`]-byte header, then chunks of a 16-bit
+/// frame count and the Rice-coded residuals of each frame in turn, padded to
+/// a byte. State carries over between chunks, so they must be decoded in order.
+pub struct Encoder<'a> {
+    config: Config,
+    channels: &'a mut [ChannelState],
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new(config: Config, channels: &'a mut [ChannelState]) -> Result<Self, Error> {
+        config.validate()?;
+        if channels.len() != config.channels as usize {
+            return Err(Error::InvalidConfig);
+        }
+        channels.fill(ChannelState::new());
+        Ok(Encoder { config, channels })
+    }
+
+    pub fn write_header(&self, output: &mut [u8]) -> Result<usize, Error> {
+        output
+            .get_mut(..HEADER_LEN)
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(&self.config.to_bytes());
+        Ok(HEADER_LEN)
+    }
+
+    /// Encodes whole frames of interleaved samples as one chunk and returns
+    /// the bytes written. `output` must hold [`max_chunk_len`] bytes. On
+    /// error nothing is written and the state is unchanged.
+    pub fn encode_chunk(&mut self, samples: &[i16], output: &mut [u8]) -> Result<usize, Error> {
+        let channels = self.channels.len();
+        let frames = samples.len() / channels;
+        if frames == 0 || frames > MAX_CHUNK_FRAMES || !samples.len().is_multiple_of(channels) {
+            return Err(Error::InvalidChunk);
+        }
+        if output.len() < max_chunk_len(frames, channels) {
+            return Err(Error::BufferTooSmall);
+        }
+        if let Some(&sample) = samples.iter().find(|&&s| self.config.index(s).is_none()) {
+            return Err(Error::OffGrid(sample));
+        }
+
+        let mut sink = BitSink::new(output);
+        sink.write_bits(frames as u32, 16);
+        for frame in samples.chunks_exact(channels) {
+            for (state, &sample) in self.channels.iter_mut().zip(frame) {
+                let k = state.parameter();
+                let index = self.config.index(sample).unwrap_or_default();
+                write_value(&mut sink, state.encode(index), k);
+            }
+        }
+        sink.finish().ok_or(Error::BufferTooSmall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: Config = Config {
+        sample_rate: 19531,
+        channels: 2,
+        bits_per_sample: 16,
+        step: 64,
+        offset: 31,
+    };
+
+    fn sample(i: i32) -> i16 {
+        (((i % 40) - 20) * 64 + 31) as i16
+    }
+
+    #[test]
+    fn encodes_into_fixed_buffers() {
+        let mut states = [ChannelState::new(); 2];
+        let mut encoder = Encoder::new(CONFIG, &mut states).unwrap();
+        let mut samples = [0i16; 200];
+        for (i, s) in samples.iter_mut().enumerate() {
+            *s = sample(i as i32 / 2);
+        }
+        let mut output = [0u8; max_chunk_len(100, 2)];
+
+        let mut header = [0u8; HEADER_LEN];
+        encoder.write_header(&mut header).unwrap();
+        assert_eq!(Config::from_bytes(&header), Ok(CONFIG));
+
+        let written = encoder.encode_chunk(&samples, &mut output).unwrap();
+        assert_eq!(&output[..2], &100u16.to_be_bytes());
+        // 64-step grid indices cost far less than the raw 16 bits.
+        assert!(written < samples.len(), "{} bytes", written);
+    }
+
+    #[test]
+    fn rejects_bad_input_without_writing() {
+        let mut states = [ChannelState::new(); 2];
+        let mut encoder = Encoder::new(CONFIG, &mut states).unwrap();
+        let mut output = [0u8; 64];
+
+        assert_eq!(
+            encoder.encode_chunk(&[31, 95, 32, 31], &mut output),
+            Err(Error::OffGrid(32))
+        );
+        assert_eq!(
+            encoder.encode_chunk(&[31, 95, 31], &mut output),
+            Err(Error::InvalidChunk)
+        );
+        assert_eq!(
+            encoder.encode_chunk(&[31; 40], &mut output),
+            Err(Error::BufferTooSmall)
+        );
+        assert_eq!(output, [0; 64]);
+        assert_eq!(
+            Encoder::new(
+                Config {
+                    offset: 64,
+                    ..CONFIG
+                },
+                &mut states
+            )
+            .err(),
+            Some(Error::InvalidConfig)
+        );
+    }
+}
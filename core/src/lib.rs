@@ -0,0 +1,19 @@
+//! Heap-free encoder core for embedded targets.
+//!
+//! Everything here is `no_std` and allocation free: the caller provides the
+//! per-channel state and the output buffers, so the memory footprint is
+//! fixed at compile time (a few hundred bytes per channel). Samples are
+//! mapped onto a known quantization grid, predicted by a small integer LMS
+//! cascade and coded with an adaptive Rice code whose parameter follows a
+//! running mean, so no lookahead buffer is needed.
+//!
+//! The matching decoder lives in the `smallbrain` crate (`embedded` module).
+
+#![no_std]
+
+pub mod bits;
+pub mod encoder;
+pub mod predictor;
+pub mod rice;
+
+pub use encoder::{max_chunk_len, ChannelState, Config, Encoder, Error, HEADER_LEN};
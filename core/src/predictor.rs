@@ -0,0 +1,191 @@
+/// Fractional bits of the filter weights.
+const WEIGHT_SHIFT: u32 = 14;
+
+/// Weight step of the sign-sign update, in units of `2^-WEIGHT_SHIFT`.
+const SIGN_STEP: i32 = 8;
+
+/// The normalized update moves weights by `2^-NLMS_RATE_SHIFT` of the full NLMS step.
+const NLMS_RATE_SHIFT: u32 = 5;
+
+/// Inputs, errors and weights are clamped to this magnitude when adapting, so
+/// no input can overflow the fixed-point arithmetic.
+const LIMIT: i64 = 1 << 20;
+
+/// Taps of the normalized LMS stage.
+pub const NLMS_ORDER: usize = 16;
+
+/// Taps of the sign-sign LMS stage.
+pub const SIGN_ORDER: usize = 8;
+
+/// Prediction of an adaptive FIR stage: its history weighted by its weights.
+pub fn stage_predict(weights: &[i32], history: &[i32]) -> i32 {
+    let sum: i64 = weights
+        .iter()
+        .zip(history)
+        .map(|(&w, &h)| w as i64 * h as i64)
+        .sum();
+    (sum >> WEIGHT_SHIFT) as i32
+}
+
+/// Adapts a stage's weights, normalized or sign-sign, given its `input` and
+/// the `error` it left, then shifts `input` into the history. Shared with the
+/// host crate's `LmsStage`, so both run the same fixed-point arithmetic.
+pub fn stage_update(
+    normalized: bool,
+    weights: &mut [i32],
+    history: &mut [i32],
+    input: i32,
+    error: i32,
+) {
+    let error = (error as i64).clamp(-LIMIT, LIMIT);
+    if normalized {
+        let norm: i64 = history.iter().map(|&h| h as i64 * h as i64).sum();
+        for (w, &h) in weights.iter_mut().zip(history.iter()) {
+            let step = ((error * h as i64) << (WEIGHT_SHIFT - NLMS_RATE_SHIFT)) / (norm + 1);
+            *w = (*w as i64 + step).clamp(-LIMIT, LIMIT) as i32;
+        }
+    } else {
+        let direction = error.signum() as i32;
+        for (w, &h) in weights.iter_mut().zip(history.iter()) {
+            *w = (*w + SIGN_STEP * direction * h.signum()).clamp(-LIMIT as i32, LIMIT as i32);
+        }
+    }
+    history.rotate_right(1);
+    if let Some(first) = history.first_mut() {
+        *first = (input as i64).clamp(-LIMIT, LIMIT) as i32;
+    }
+}
+
+/// The fixed first stage's prediction, `31/32 x[n-1]`: subtracting it leaves
+/// `x[n] - 31/32 x[n-1]`, which whitens most of the low-frequency energy.
+pub fn first_order(previous: i32) -> i32 {
+    ((previous as i64 * 31) >> 5) as i32
+}
+
+/// A fixed-size adaptive FIR stage, the array counterpart of the host
+/// crate's `LmsStage`.
+#[derive(Clone, Copy, Debug)]
+struct Stage<const N: usize> {
+    normalized: bool,
+    weights: [i32; N],
+    history: [i32; N],
+}
+
+impl<const N: usize> Stage<N> {
+    const fn new(normalized: bool) -> Self {
+        Stage {
+            normalized,
+            weights: [0; N],
+            history: [0; N],
+        }
+    }
+
+    fn predict(&self) -> i32 {
+        stage_predict(&self.weights, &self.history)
+    }
+
+    fn update(&mut self, input: i32, error: i32) {
+        stage_update(
+            self.normalized,
+            &mut self.weights,
+            &mut self.history,
+            input,
+            error,
+        );
+    }
+}
+
+/// A scaled first-order difference followed by a normalized and a sign-sign
+/// LMS stage. Encoder and decoder run it in lockstep on grid indices.
+#[derive(Clone, Copy, Debug)]
+pub struct Predictor {
+    previous: i32,
+    nlms: Stage<NLMS_ORDER>,
+    sign: Stage<SIGN_ORDER>,
+}
+
+impl Default for Predictor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Predictor {
+    pub const fn new() -> Self {
+        Predictor {
+            previous: 0,
+            nlms: Stage::new(true),
+            sign: Stage::new(false),
+        }
+    }
+
+    /// Prediction of the next value.
+    pub fn predict(&self) -> i32 {
+        first_order(self.previous)
+            .wrapping_add(self.nlms.predict())
+            .wrapping_add(self.sign.predict())
+    }
+
+    /// Feeds the actual value through the stages.
+    pub fn update(&mut self, value: i32) {
+        let input = value.wrapping_sub(first_order(self.previous));
+        self.previous = value;
+        let error = input.wrapping_sub(self.nlms.predict());
+        self.nlms.update(input, error);
+        let residual = error.wrapping_sub(self.sign.predict());
+        self.sign.update(error, residual);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_predicts_with_fixed_point_weights() {
+        // Weights of 1 and 1/2.
+        let weights = [1 << WEIGHT_SHIFT, 1 << (WEIGHT_SHIFT - 1)];
+        assert_eq!(stage_predict(&weights, &[10, 4]), 12);
+        assert_eq!(stage_predict(&weights, &[-10, -4]), -12);
+    }
+
+    #[test]
+    fn normalized_update_steps_by_the_scaled_error() {
+        let mut weights = [0; 3];
+        let mut history = [100, 0, 0];
+        stage_update(true, &mut weights, &mut history, 7, 50);
+        // (50 * 100 << 9) / (100^2 + 1)
+        assert_eq!(weights, [255, 0, 0]);
+        assert_eq!(history, [7, 100, 0]);
+
+        // With no history there is nothing to adapt.
+        let mut weights = [0; 2];
+        stage_update(true, &mut weights, &mut [0, 0], 1, 1000);
+        assert_eq!(weights, [0, 0]);
+    }
+
+    #[test]
+    fn sign_update_moves_by_a_fixed_step_and_clamps() {
+        let mut weights = [0, 0, LIMIT as i32];
+        let mut history = [5, -3, 9];
+        stage_update(false, &mut weights, &mut history, i32::MAX, 2);
+        assert_eq!(weights, [SIGN_STEP, -SIGN_STEP, LIMIT as i32]);
+        // Inputs are clamped before they enter the history.
+        assert_eq!(history, [LIMIT as i32, 5, -3]);
+
+        stage_update(false, &mut weights, &mut history, 0, -2);
+        assert_eq!(weights, [0, -2 * SIGN_STEP, LIMIT as i32]);
+    }
+
+    #[test]
+    fn predictor_learns_a_steady_ramp() {
+        let mut predictor = Predictor::new();
+        let mut last_error = 0;
+        for i in 0..4000 {
+            let value = i * 10;
+            last_error = (value - predictor.predict()).abs();
+            predictor.update(value);
+        }
+        assert!(last_error <= 2, "error {}", last_error);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::bits::BitWrite;
+
+/// Quotients at or above this value are replaced by an escape followed by the
+/// value's bit width and the value itself, so a single outlier cannot cost
+/// thousands of bits.
+pub const ESCAPE_QUOTIENT: u32 = 24;
+
+/// Largest Rice parameter the adaptation can pick.
+pub const MAX_PARAMETER: u32 = 30;
+
+/// Most bits one coded value can take: the escape, a 5-bit width and 32 bits.
+pub const MAX_VALUE_BITS: usize = ESCAPE_QUOTIENT as usize + 5 + 32;
+
+/// Statistics are halved after this many values, so the parameter tracks
+/// changes in signal level.
+const RESET_COUNT: u32 = 64;
+
+/// Maps signed residuals onto unsigned values: 0, -1, 1, -2, 2, ...
+pub fn zigzag(value: i32) -> u32 {
+    ((value << 1) ^ (value >> 31)) as u32
+}
+
+pub fn unzigzag(value: u32) -> i32 {
+    ((value >> 1) as i32) ^ -((value & 1) as i32)
+}
+
+/// Running-mean Rice parameter estimate (the LOCO-I rule): the smallest `k`
+/// with `count * 2^k >= sum`.
+#[derive(Clone, Copy, Debug)]
+pub struct AdaptiveRice {
+    sum: u64,
+    count: u32,
+}
+
+impl Default for AdaptiveRice {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AdaptiveRice {
+    pub const fn new() -> Self {
+        AdaptiveRice { sum: 4, count: 1 }
+    }
+
+    pub fn parameter(&self) -> u32 {
+        let mut k = 0;
+        while k < MAX_PARAMETER && (self.count as u64) << k < self.sum {
+            k += 1;
+        }
+        k
+    }
+
+    pub fn update(&mut self, value: u32) {
+        self.sum += value as u64;
+        self.count += 1;
+        if self.count == RESET_COUNT {
+            self.sum >>= 1;
+            self.count >>= 1;
+        }
+    }
+}
+
+/// Number of bits needed to represent `value` (at least 1).
+pub fn bit_width(value: u32) -> u32 {
+    (32 - value.leading_zeros()).max(1)
+}
+
+/// Writes `value` with parameter `k`: a unary quotient of zeros ended by a
+/// one, then `k` remainder bits, or an escape for large quotients.
+pub fn write_value<S: BitWrite + ?Sized>(sink: &mut S, value: u32, k: u32) {
+    let quotient = value >> k;
+    if quotient < ESCAPE_QUOTIENT {
+        if quotient > 0 {
+            sink.write_bits(0, quotient);
+        }
+        sink.write_bit(true);
+        if k > 0 {
+            sink.write_bits(value, k);
+        }
+    } else {
+        let width = bit_width(value);
+        sink.write_bits(0, ESCAPE_QUOTIENT);
+        sink.write_bits(width - 1, 5);
+        sink.write_bits(value, width);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records bits as they are written.
+    struct Bits {
+        bits: [bool; 128],
+        len: usize,
+    }
+
+    impl Bits {
+        fn new() -> Self {
+            Bits {
+                bits: [false; 128],
+                len: 0,
+            }
+        }
+
+        /// The bits from `start` on, read as a number.
+        fn value(&self, start: usize, width: usize) -> u32 {
+            self.bits[start..start + width]
+                .iter()
+                .fold(0, |value, &bit| (value << 1) | bit as u32)
+        }
+    }
+
+    impl BitWrite for Bits {
+        fn write_bits(&mut self, value: u32, width: u32) {
+            for i in (0..width).rev() {
+                self.bits[self.len] = (value >> i) & 1 == 1;
+                self.len += 1;
+            }
+        }
+    }
+
+    #[test]
+    fn zigzag_covers_the_whole_range() {
+        for value in [0, -1, 1, -2, 2, i32::MIN, i32::MAX] {
+            assert_eq!(unzigzag(zigzag(value)), value);
+        }
+        assert_eq!([zigzag(0), zigzag(-1), zigzag(1)], [0, 1, 2]);
+        assert_eq!(zigzag(i32::MIN), u32::MAX);
+    }
+
+    #[test]
+    fn quotients_from_the_escape_on_are_written_verbatim() {
+        // The longest quotient still written in unary.
+        let mut bits = Bits::new();
+        write_value(&mut bits, (ESCAPE_QUOTIENT - 1) << 3 | 5, 3);
+        assert_eq!(bits.len, ESCAPE_QUOTIENT as usize + 3);
+        assert_eq!(bits.value(ESCAPE_QUOTIENT as usize - 1, 4), 0b1101);
+
+        let mut bits = Bits::new();
+        write_value(&mut bits, ESCAPE_QUOTIENT << 3, 3);
+        let escape = ESCAPE_QUOTIENT as usize;
+        assert_eq!(bits.value(0, escape), 0);
+        assert_eq!(bits.value(escape, 5) + 1, 8);
+        assert_eq!(bits.value(escape + 5, 8), ESCAPE_QUOTIENT << 3);
+
+        // The widest value is the bound the encoder sizes its buffers by.
+        let mut bits = Bits::new();
+        write_value(&mut bits, u32::MAX, 0);
+        assert_eq!(bits.len, MAX_VALUE_BITS);
+        assert_eq!(bits.value(escape + 5, 32), u32::MAX);
+    }
+
+    #[test]
+    fn parameter_follows_the_running_mean_within_bounds() {
+        let mut rice = AdaptiveRice::new();
+        assert_eq!(rice.parameter(), 2);
+        for _ in 0..200 {
+            rice.update(0);
+        }
+        assert_eq!(rice.parameter(), 0);
+        for _ in 0..200 {
+            rice.update(u32::MAX);
+        }
+        assert_eq!(rice.parameter(), MAX_PARAMETER);
+        // Halving at every reset lets old values fade out.
+        for _ in 0..2000 {
+            rice.update(1000);
+        }
+        // 2^10 is the first power of two at or above the mean.
+        assert_eq!(rice.parameter(), 10);
+    }
+}
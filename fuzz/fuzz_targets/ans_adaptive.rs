@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::ans::decompress_ans_adaptive;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_ans_adaptive(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::brotli_sb::decompress_brotli;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_brotli(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::cm::decompress_cm;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_cm(data);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+// Container entry point: codec detection, the blocked container and every codec behind it.
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::codec::decompress;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress(data);
+});
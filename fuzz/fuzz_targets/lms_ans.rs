@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::lms::decompress_lms_ans;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_lms_ans(data);
+});
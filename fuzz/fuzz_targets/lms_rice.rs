@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::lms::decompress_lms_rice;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_lms_rice(data);
+});
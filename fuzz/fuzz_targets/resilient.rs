@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::resilient::{decompress_stream, GapFill};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_stream(data, GapFill::Hold);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::spikes::decompress_spike;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_spike(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::zlib::decompress_zlib;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_zlib(data);
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use smallbrain::zstd::decompress_zstd;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decompress_zstd(data);
+});
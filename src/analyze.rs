@@ -0,0 +1,354 @@
+use crate::predictor::detect_grid;
+use crate::wav::deinterleave;
+use hound::WavSpec;
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::fmt;
+
+/// Lags reported by the autocorrelation statistic.
+pub const AUTOCORRELATION_LAGS: [usize; 4] = [1, 2, 4, 8];
+
+/// Frame length of the averaged periodogram behind spectral flatness.
+const SPECTRUM_SIZE: usize = 1024;
+
+/// Compression-relevant statistics of one recording. Entropies are in bits
+/// per sample; conditional entropies condition on the previous value in the
+/// same channel and are empirical, so on short files they underestimate the
+/// true rate.
+#[derive(Clone, Debug)]
+pub struct SignalStats {
+    pub samples: usize,
+    pub channels: u16,
+    pub distinct_values: usize,
+    pub step: i32,
+    pub offset: i32,
+    pub entropy: f64,
+    pub conditional_entropy: f64,
+    pub diff_entropy: f64,
+    pub diff_conditional_entropy: f64,
+    pub autocorrelation: [f64; AUTOCORRELATION_LAGS.len()],
+    pub spectral_flatness: f64,
+}
+
+impl SignalStats {
+    /// Size of the samples as 16-bit PCM, without any container header.
+    pub fn raw_bytes(&self) -> u64 {
+        self.samples as u64 * 2
+    }
+
+    /// Smallest of the entropy estimates times the sample count: roughly
+    /// what a coder using at most one previous value as context could reach.
+    pub fn lower_bound_bytes(&self) -> u64 {
+        let rate = self
+            .entropy
+            .min(self.conditional_entropy)
+            .min(self.diff_entropy)
+            .min(self.diff_conditional_entropy);
+        (rate * self.samples as f64 / 8.0).ceil() as u64
+    }
+}
+
+impl fmt::Display for SignalStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let autocorrelation: Vec<String> = self
+            .autocorrelation
+            .iter()
+            .map(|r| format!("{:.3}", r))
+            .collect();
+        write!(
+            f,
+            "samples {}, channels {}, distinct {}, step {} (offset {}), \
+             H0 {:.3}, H(x|x-1) {:.3}, H0(d) {:.3}, H(d|d-1) {:.3} bits/sample, \
+             autocorrelation {}, spectral flatness {:.4}, bound {} bytes (ratio {:.2})",
+            self.samples,
+            self.channels,
+            self.distinct_values,
+            self.step,
+            self.offset,
+            self.entropy,
+            self.conditional_entropy,
+            self.diff_entropy,
+            self.diff_conditional_entropy,
+            autocorrelation.join("/"),
+            self.spectral_flatness,
+            self.lower_bound_bytes(),
+            self.raw_bytes() as f64 / self.lower_bound_bytes().max(1) as f64
+        )
+    }
+}
+
+/// Totals over many recordings, with per-sample statistics weighted by
+/// each recording's length.
+#[derive(Clone, Debug, Default)]
+pub struct Summary {
+    pub files: usize,
+    pub samples: usize,
+    pub raw_bytes: u64,
+    pub lower_bound_bytes: u64,
+    pub entropy: f64,
+    pub conditional_entropy: f64,
+    pub diff_entropy: f64,
+    pub diff_conditional_entropy: f64,
+    pub spectral_flatness: f64,
+}
+
+impl Summary {
+    pub fn from_stats(stats: &[SignalStats]) -> Summary {
+        let samples: usize = stats.iter().map(|s| s.samples).sum();
+        let weighted = |field: fn(&SignalStats) -> f64| {
+            stats
+                .iter()
+                .map(|s| field(s) * s.samples as f64)
+                .sum::<f64>()
+                / samples.max(1) as f64
+        };
+        Summary {
+            files: stats.len(),
+            samples,
+            raw_bytes: stats.iter().map(SignalStats::raw_bytes).sum(),
+            lower_bound_bytes: stats.iter().map(SignalStats::lower_bound_bytes).sum(),
+            entropy: weighted(|s| s.entropy),
+            conditional_entropy: weighted(|s| s.conditional_entropy),
+            diff_entropy: weighted(|s| s.diff_entropy),
+            diff_conditional_entropy: weighted(|s| s.diff_conditional_entropy),
+            spectral_flatness: weighted(|s| s.spectral_flatness),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "files {}, samples {}, H0 {:.3}, H(x|x-1) {:.3}, H0(d) {:.3}, H(d|d-1) {:.3} bits/sample, \
+             spectral flatness {:.4}, raw {} bytes, bound {} bytes (ratio {:.2})",
+            self.files,
+            self.samples,
+            self.entropy,
+            self.conditional_entropy,
+            self.diff_entropy,
+            self.diff_conditional_entropy,
+            self.spectral_flatness,
+            self.raw_bytes,
+            self.lower_bound_bytes,
+            self.raw_bytes as f64 / self.lower_bound_bytes.max(1) as f64
+        )
+    }
+}
+
+/// Zeroth-order and first-order (previous value as context) symbol counts.
+#[derive(Default)]
+struct Counts {
+    values: BTreeMap<i32, u64>,
+    pairs: BTreeMap<(i32, i32), u64>,
+    contexts: BTreeMap<i32, u64>,
+}
+
+impl Counts {
+    fn add(&mut self, sequence: &[i32]) {
+        for &v in sequence {
+            *self.values.entry(v).or_default() += 1;
+        }
+        for pair in sequence.windows(2) {
+            *self.pairs.entry((pair[0], pair[1])).or_default() += 1;
+            *self.contexts.entry(pair[0]).or_default() += 1;
+        }
+    }
+
+    fn entropy(&self) -> f64 {
+        entropy(self.values.values())
+    }
+
+    /// `H(X | X_prev) = H(X_prev, X) - H(X_prev)`.
+    fn conditional_entropy(&self) -> f64 {
+        entropy(self.pairs.values()) - entropy(self.contexts.values())
+    }
+}
+
+fn entropy<'a>(counts: impl Iterator<Item = &'a u64> + Clone) -> f64 {
+    let total: u64 = counts.clone().sum();
+    if total == 0 {
+        return 0.0;
+    }
+    counts
+        .map(|&c| {
+            let p = c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+pub fn analyze_samples(samples: &[i16], spec: &WavSpec) -> SignalStats {
+    let channels = deinterleave(samples, spec.channels.max(1) as usize);
+    let all: Vec<i32> = samples.iter().map(|&s| s as i32).collect();
+    let (step, offset) = detect_grid(&all);
+
+    let mut values = Counts::default();
+    let mut diffs = Counts::default();
+    for channel in &channels {
+        values.add(channel);
+        let differences: Vec<i32> = channel.windows(2).map(|w| w[1] - w[0]).collect();
+        diffs.add(&differences);
+    }
+
+    let mut autocorrelation = [0.0; AUTOCORRELATION_LAGS.len()];
+    for (r, &lag) in autocorrelation.iter_mut().zip(&AUTOCORRELATION_LAGS) {
+        *r = channels
+            .iter()
+            .map(|c| autocorrelation_at(c, lag))
+            .sum::<f64>()
+            / channels.len() as f64;
+    }
+    let spectral_flatness =
+        channels.iter().map(|c| spectral_flatness(c)).sum::<f64>() / channels.len() as f64;
+
+    SignalStats {
+        samples: samples.len(),
+        channels: spec.channels,
+        distinct_values: values.values.len(),
+        step,
+        offset,
+        entropy: values.entropy(),
+        conditional_entropy: values.conditional_entropy(),
+        diff_entropy: diffs.entropy(),
+        diff_conditional_entropy: diffs.conditional_entropy(),
+        autocorrelation,
+        spectral_flatness,
+    }
+}
+
+/// Normalized autocorrelation of the mean-removed signal at `lag`.
+fn autocorrelation_at(samples: &[i32], lag: usize) -> f64 {
+    if samples.len() <= lag {
+        return 0.0;
+    }
+    let mean = samples.iter().map(|&s| s as f64).sum::<f64>() / samples.len() as f64;
+    let centred: Vec<f64> = samples.iter().map(|&s| s as f64 - mean).collect();
+    let variance: f64 = centred.iter().map(|x| x * x).sum();
+    if variance == 0.0 {
+        return 0.0;
+    }
+    centred
+        .iter()
+        .zip(&centred[lag..])
+        .map(|(a, b)| a * b)
+        .sum::<f64>()
+        / variance
+}
+
+/// Geometric over arithmetic mean of the Welch power spectrum (DC excluded):
+/// 1 for white noise, near 0 for strongly coloured signals.
+fn spectral_flatness(samples: &[i32]) -> f64 {
+    let window: Vec<f64> = (0..SPECTRUM_SIZE)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / SPECTRUM_SIZE as f64).cos())
+        .collect();
+    let mut power = vec![0.0; SPECTRUM_SIZE / 2];
+
+    let mut start = 0;
+    loop {
+        let frame = &samples[start.min(samples.len())..(start + SPECTRUM_SIZE).min(samples.len())];
+        let mean = frame.iter().map(|&s| s as f64).sum::<f64>() / frame.len().max(1) as f64;
+        let mut re: Vec<f64> = (0..SPECTRUM_SIZE)
+            .map(|i| frame.get(i).map_or(0.0, |&s| (s as f64 - mean) * window[i]))
+            .collect();
+        let mut im = vec![0.0; SPECTRUM_SIZE];
+        fft(&mut re, &mut im);
+        for (k, p) in power.iter_mut().enumerate() {
+            *p += re[k] * re[k] + im[k] * im[k];
+        }
+
+        start += SPECTRUM_SIZE / 2;
+        if start + SPECTRUM_SIZE > samples.len() {
+            break;
+        }
+    }
+
+    let bins = &power[1..];
+    let arithmetic = bins.iter().sum::<f64>() / bins.len() as f64;
+    if arithmetic == 0.0 {
+        return 0.0;
+    }
+    let geometric = (bins.iter().map(|p| (p + 1e-12).ln()).sum::<f64>() / bins.len() as f64).exp();
+    geometric / arithmetic
+}
+
+/// In-place iterative radix-2 FFT; the length must be a power of two.
+fn fft(re: &mut [f64], im: &mut [f64]) {
+    let n = re.len();
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let angle = -2.0 * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (sin, cos) = (angle * k as f64).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+                let t_re = re[b] * cos - im[b] * sin;
+                let t_im = re[b] * sin + im[b] * cos;
+                re[b] = re[a] - t_re;
+                im[b] = im[a] - t_im;
+                re[a] += t_re;
+                im[a] += t_im;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> WavSpec {
+        WavSpec {
+            channels: 1,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        }
+    }
+
+    #[test]
+    fn noise_is_flat_and_uncorrelated() {
+        let mut state = 1u32;
+        let samples: Vec<i16> = (0..65_536)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                ((state >> 24) as i16 - 128) * 4
+            })
+            .collect();
+        let stats = analyze_samples(&samples, &spec());
+
+        assert_eq!(stats.distinct_values, 256);
+        assert_eq!(stats.step, 4);
+        assert!((stats.entropy - 8.0).abs() < 0.01);
+        assert!(stats.autocorrelation[0].abs() < 0.05);
+        assert!(stats.spectral_flatness > 0.5);
+        assert!(stats.lower_bound_bytes() < stats.raw_bytes() / 2 + 1000);
+    }
+
+    #[test]
+    fn sine_is_coloured_and_predictable() {
+        let samples: Vec<i16> = (0..65_536)
+            .map(|i| ((i as f64 / 50.0).sin() * 8000.0) as i16)
+            .collect();
+        let stats = analyze_samples(&samples, &spec());
+
+        assert!(stats.autocorrelation[0] > 0.99);
+        assert!(stats.spectral_flatness < 0.1);
+        assert!(stats.diff_entropy < stats.entropy);
+    }
+}
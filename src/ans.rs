@@ -0,0 +1,571 @@
+use crate::bitpack::{BitOrder, BitReader, BitWriter};
+use crate::cost::CostModel;
+use crate::predictor::{
+    fixed_block_residuals, fixed_cost, read_fixed_orders, restore_fixed_blocks, write_fixed_orders,
+};
+use crate::rice::{unzigzag, zigzag};
+use crate::wav::{deinterleave, interleave, read_spec_header, write_spec_header};
+use hound::WavSpec;
+use std::error::Error;
+use tracing::debug;
+
+/// Frequencies are normalized to sum to `1 << PROB_BITS`.
+const PROB_BITS: u32 = 14;
+const PROB_SCALE: u32 = 1 << PROB_BITS;
+
+/// Lower bound of the rANS state; renormalization keeps it in `[RANS_L, RANS_L << 8)`.
+const RANS_L: u32 = 1 << 23;
+
+/// Zigzagged residuals below this value are their own symbol.
+const DIRECT_SYMBOLS: u32 = 64;
+
+/// Larger values are coded as a token for their bit width and the two bits
+/// below the leading one, followed by the remaining bits verbatim.
+const MANTISSA_BITS: u32 = 2;
+
+/// Size of the symbol alphabet: direct symbols plus four tokens for each bit
+/// width from 7 to 32.
+pub const ALPHABET_SIZE: usize = DIRECT_SYMBOLS as usize + 26 * (1 << MANTISSA_BITS);
+
+/// Residuals per block in the static variant; each block carries its own table.
+pub const TABLE_BLOCK_SIZE: usize = 16384;
+
+/// The adaptive model rebuilds its table after this many symbols once warmed up.
+const ADAPT_INTERVAL: usize = 1024;
+
+/// Count added per observed symbol in the adaptive model; counts are halved
+/// once their total passes `ADAPT_LIMIT`, so old statistics fade out.
+const ADAPT_INCREMENT: u32 = 32;
+const ADAPT_LIMIT: u32 = 1 << 16;
+
+/// Splits a zigzagged residual into an alphabet symbol plus raw extra bits.
+fn tokenize(value: u32) -> (u8, u32, u32) {
+    if value < DIRECT_SYMBOLS {
+        return (value as u8, 0, 0);
+    }
+    let width = 32 - value.leading_zeros();
+    let extra_bits = width - 1 - MANTISSA_BITS;
+    let mantissa = (value >> extra_bits) & ((1 << MANTISSA_BITS) - 1);
+    let symbol = DIRECT_SYMBOLS + ((width - 7) << MANTISSA_BITS) + mantissa;
+    (symbol as u8, value, extra_bits)
+}
+
+fn detokenize(symbol: u8, extra: &mut BitReader) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    let symbol = symbol as u32;
+    if symbol < DIRECT_SYMBOLS {
+        return Ok(symbol);
+    }
+    let width = 7 + ((symbol - DIRECT_SYMBOLS) >> MANTISSA_BITS);
+    let extra_bits = width - 1 - MANTISSA_BITS;
+    let top = (1 << MANTISSA_BITS) | ((symbol - DIRECT_SYMBOLS) & ((1 << MANTISSA_BITS) - 1));
+    Ok((top << extra_bits) | extra.read_bits(extra_bits)?)
+}
+
+/// Symbol frequencies normalized to [`PROB_SCALE`], with cumulative starts.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrequencyTable {
+    freqs: Vec<u32>,
+    starts: Vec<u32>,
+}
+
+impl FrequencyTable {
+    /// Normalizes raw counts so they sum to [`PROB_SCALE`], keeping every
+    /// observed symbol at a frequency of at least one.
+    pub fn from_counts(counts: &[u32]) -> Self {
+        let total: u64 = counts.iter().map(|&c| c as u64).sum();
+        let mut freqs: Vec<u32> = counts
+            .iter()
+            .map(|&c| match c {
+                0 => 0,
+                _ => ((c as u64 * PROB_SCALE as u64 / total.max(1)) as u32).max(1),
+            })
+            .collect();
+
+        let mut sum: u32 = freqs.iter().sum();
+        if sum == 0 {
+            freqs[0] = PROB_SCALE;
+            sum = PROB_SCALE;
+        }
+        // Ties go to the lowest symbol so encoder and decoder agree on every platform.
+        let largest = |freqs: &[u32]| {
+            (0..freqs.len())
+                .max_by_key(|&i| (freqs[i], std::cmp::Reverse(i)))
+                .unwrap()
+        };
+        if sum < PROB_SCALE {
+            let i = largest(&freqs);
+            freqs[i] += PROB_SCALE - sum;
+        }
+        while sum > PROB_SCALE {
+            let i = largest(&freqs);
+            let take = (sum - PROB_SCALE).min(freqs[i] - 1);
+            freqs[i] -= take;
+            sum -= take;
+        }
+
+        Self::from_freqs(freqs)
+    }
+
+    fn from_freqs(freqs: Vec<u32>) -> Self {
+        let starts = freqs
+            .iter()
+            .scan(0, |start, &freq| {
+                let current = *start;
+                *start += freq;
+                Some(current)
+            })
+            .collect();
+        FrequencyTable { freqs, starts }
+    }
+
+    /// Serializes the table: the alphabet length, then each frequency as a
+    /// 4-bit bit length followed by the bits below its leading one.
+    pub fn write(&self, writer: &mut BitWriter) {
+        let len = self.freqs.iter().rposition(|&f| f > 0).map_or(0, |i| i + 1);
+        writer.write_bits(len as u32, 8);
+        for &freq in &self.freqs[..len] {
+            let width = 32 - freq.leading_zeros();
+            writer.write_bits(width, 4);
+            if width > 1 {
+                writer.write_bits(freq, width - 1);
+            }
+        }
+    }
+
+    pub fn read(reader: &mut BitReader) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let len = reader.read_bits(8)? as usize;
+        if len > ALPHABET_SIZE {
+            return Err(Box::from("Invalid ANS frequency table"));
+        }
+        let mut freqs = vec![0; ALPHABET_SIZE];
+        for freq in freqs.iter_mut().take(len) {
+            let width = reader.read_bits(4)?;
+            *freq = match width {
+                0 => 0,
+                1 => 1,
+                _ => (1 << (width - 1)) | reader.read_bits(width - 1)?,
+            };
+        }
+        if freqs.iter().sum::<u32>() != PROB_SCALE {
+            return Err(Box::from("Invalid ANS frequency table"));
+        }
+        Ok(Self::from_freqs(freqs))
+    }
+
+    /// Maps every slot in `[0, PROB_SCALE)` to the symbol that owns it.
+    fn slot_table(&self) -> Vec<u8> {
+        let mut slots = Vec::with_capacity(PROB_SCALE as usize);
+        for (symbol, &freq) in self.freqs.iter().enumerate() {
+            slots.extend(std::iter::repeat_n(symbol as u8, freq as usize));
+        }
+        slots
+    }
+}
+
+/// Encodes `(start, freq)` pairs with rANS. Symbols are processed in reverse
+/// so the decoder can emit them in forward order.
+fn rans_encode(symbols: &[(u32, u32)]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(symbols.len() / 2 + 4);
+    let mut state = RANS_L;
+    for &(start, freq) in symbols.iter().rev() {
+        let limit = ((RANS_L >> PROB_BITS) << 8) * freq;
+        while state >= limit {
+            output.push(state as u8);
+            state >>= 8;
+        }
+        state = ((state / freq) << PROB_BITS) + state % freq + start;
+    }
+    output.extend_from_slice(&state.to_le_bytes());
+    output.reverse();
+    output
+}
+
+/// Encoder cost per residual of tokenizing it and coding its symbol with
+/// [`rans_encode`]: the renormalization limit, the division and the remainder.
+fn rans_cost() -> CostModel {
+    CostModel {
+        multiplies: 3.0,
+        // Zigzag, tokenize, extra bits, then the renormalization check and state update.
+        additions: 2.0 + 6.0 + 4.0 + 6.0,
+        state_bytes: 0,
+        buffer_bytes_per_sample: 0.0,
+    }
+}
+
+struct RansDecoder<'a> {
+    input: &'a [u8],
+    position: usize,
+    state: u32,
+}
+
+impl<'a> RansDecoder<'a> {
+    fn new(input: &'a [u8]) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let head: [u8; 4] = input
+            .get(..4)
+            .and_then(|head| head.try_into().ok())
+            .ok_or("ANS stream too short")?;
+        let state = u32::from_be_bytes(head);
+        if !(RANS_L..RANS_L << 8).contains(&state) {
+            return Err(Box::from("Invalid ANS state"));
+        }
+        Ok(RansDecoder {
+            input,
+            position: 4,
+            state,
+        })
+    }
+
+    fn slot(&self) -> u32 {
+        self.state & (PROB_SCALE - 1)
+    }
+
+    fn advance(&mut self, start: u32, freq: u32) -> Result<(), Box<dyn Error + Send + Sync>> {
+        self.state = freq * (self.state >> PROB_BITS) + self.slot() - start;
+        while self.state < RANS_L {
+            let byte = *self
+                .input
+                .get(self.position)
+                .ok_or("Unexpected end of ANS stream")?;
+            self.state = (self.state << 8) | byte as u32;
+            self.position += 1;
+        }
+        Ok(())
+    }
+}
+
+fn write_section(writer: &mut BitWriter, bytes: &[u8]) {
+    writer.write_bits(bytes.len() as u32, 32);
+    writer.write_bytes(bytes);
+}
+
+fn read_section<'a>(reader: &mut BitReader<'a>) -> Result<&'a [u8], Box<dyn Error + Send + Sync>> {
+    let len = reader.read_bits(32)? as usize;
+    reader.read_bytes(len)
+}
+
+/// ANS-codes residuals in blocks of [`TABLE_BLOCK_SIZE`], each with its own
+/// serialized frequency table. The count is not stored.
+pub fn encode_residuals_static(writer: &mut BitWriter, residuals: &[i32]) {
+    for block in residuals.chunks(TABLE_BLOCK_SIZE) {
+        let mut extra = BitWriter::new(BitOrder::Msb);
+        let mut counts = vec![0u32; ALPHABET_SIZE];
+        let symbols: Vec<u8> = block
+            .iter()
+            .map(|&r| {
+                let (symbol, value, bits) = tokenize(zigzag(r));
+                if bits > 0 {
+                    extra.write_bits(value, bits);
+                }
+                counts[symbol as usize] += 1;
+                symbol
+            })
+            .collect();
+
+        let table = FrequencyTable::from_counts(&counts);
+        let pairs: Vec<(u32, u32)> = symbols
+            .iter()
+            .map(|&s| (table.starts[s as usize], table.freqs[s as usize]))
+            .collect();
+
+        table.write(writer);
+        write_section(writer, &rans_encode(&pairs));
+        write_section(writer, &extra.finish());
+    }
+}
+
+/// Inverse of [`encode_residuals_static`].
+pub fn decode_residuals_static(
+    reader: &mut BitReader,
+    count: usize,
+) -> Result<Vec<i32>, Box<dyn Error + Send + Sync>> {
+    let mut residuals = Vec::with_capacity(count.min(reader.bits_remaining() as usize));
+    while residuals.len() < count {
+        let table = FrequencyTable::read(reader)?;
+        let slots = table.slot_table();
+        let mut decoder = RansDecoder::new(read_section(reader)?)?;
+        let mut extra = BitReader::new(read_section(reader)?, BitOrder::Msb);
+
+        for _ in 0..TABLE_BLOCK_SIZE.min(count - residuals.len()) {
+            let symbol = slots[decoder.slot() as usize];
+            decoder.advance(table.starts[symbol as usize], table.freqs[symbol as usize])?;
+            residuals.push(unzigzag(detokenize(symbol, &mut extra)?));
+        }
+    }
+    Ok(residuals)
+}
+
+/// Encoder cost of [`encode_residuals_static`]: counting, the coding, and
+/// the table build and write once per [`TABLE_BLOCK_SIZE`] residuals.
+pub fn encode_residuals_static_cost() -> CostModel {
+    let table = CostModel {
+        additions: 1.0 + (ALPHABET_SIZE * 8) as f64 / TABLE_BLOCK_SIZE as f64,
+        // Counts, frequencies and starts, plus one block of symbols and coding pairs.
+        state_bytes: ALPHABET_SIZE * 3 * 4 + TABLE_BLOCK_SIZE * (1 + 8),
+        ..CostModel::default()
+    };
+    rans_cost() + table
+}
+
+/// Frequency model that encoder and decoder update in lockstep, so no table
+/// needs to be transmitted.
+struct AdaptiveModel {
+    counts: Vec<u32>,
+    table: FrequencyTable,
+    slots: Vec<u8>,
+    since_rebuild: usize,
+    interval: usize,
+}
+
+impl AdaptiveModel {
+    fn new() -> Self {
+        let counts = vec![1; ALPHABET_SIZE];
+        let table = FrequencyTable::from_counts(&counts);
+        let slots = table.slot_table();
+        AdaptiveModel {
+            counts,
+            table,
+            slots,
+            since_rebuild: 0,
+            interval: 32,
+        }
+    }
+
+    /// Records `symbol`; the table is rebuilt on a schedule that starts short
+    /// and doubles up to [`ADAPT_INTERVAL`] so early statistics are picked up fast.
+    fn update(&mut self, symbol: u8) {
+        self.counts[symbol as usize] += ADAPT_INCREMENT;
+        self.since_rebuild += 1;
+        if self.since_rebuild < self.interval {
+            return;
+        }
+
+        if self.counts.iter().sum::<u32>() > ADAPT_LIMIT {
+            for count in &mut self.counts {
+                *count = (*count / 2).max(1);
+            }
+        }
+        self.table = FrequencyTable::from_counts(&self.counts);
+        self.slots = self.table.slot_table();
+        self.since_rebuild = 0;
+        self.interval = (self.interval * 2).min(ADAPT_INTERVAL);
+    }
+
+    fn pair(&self, symbol: u8) -> (u32, u32) {
+        (
+            self.table.starts[symbol as usize],
+            self.table.freqs[symbol as usize],
+        )
+    }
+}
+
+/// ANS-codes residuals with a model adapted as symbols are coded. The count is not stored.
+pub fn encode_residuals_adaptive(writer: &mut BitWriter, residuals: &[i32]) {
+    let mut extra = BitWriter::new(BitOrder::Msb);
+    let mut model = AdaptiveModel::new();
+    let pairs: Vec<(u32, u32)> = residuals
+        .iter()
+        .map(|&r| {
+            let (symbol, value, bits) = tokenize(zigzag(r));
+            if bits > 0 {
+                extra.write_bits(value, bits);
+            }
+            let pair = model.pair(symbol);
+            model.update(symbol);
+            pair
+        })
+        .collect();
+
+    write_section(writer, &rans_encode(&pairs));
+    write_section(writer, &extra.finish());
+}
+
+/// Encoder cost of [`encode_residuals_adaptive`]: the coding plus the model
+/// update, whose table and slot rebuild is counted at the steady-state
+/// interval of [`ADAPT_INTERVAL`] symbols.
+pub fn encode_residuals_adaptive_cost() -> CostModel {
+    let rebuild = (PROB_SCALE as usize + ALPHABET_SIZE * 8) as f64 / ADAPT_INTERVAL as f64;
+    let model = CostModel {
+        additions: 3.0 + rebuild,
+        state_bytes: ALPHABET_SIZE * 3 * 4 + PROB_SCALE as usize,
+        // The coding pairs of the whole input, encoded in reverse at the end.
+        buffer_bytes_per_sample: 8.0,
+        ..CostModel::default()
+    };
+    rans_cost() + model
+}
+
+/// Inverse of [`encode_residuals_adaptive`].
+pub fn decode_residuals_adaptive(
+    reader: &mut BitReader,
+    count: usize,
+) -> Result<Vec<i32>, Box<dyn Error + Send + Sync>> {
+    let mut decoder = RansDecoder::new(read_section(reader)?)?;
+    let mut extra = BitReader::new(read_section(reader)?, BitOrder::Msb);
+    let mut model = AdaptiveModel::new();
+
+    let mut residuals = Vec::with_capacity(count.min(decoder.input.len() * 8));
+    for _ in 0..count {
+        let symbol = model.slots[decoder.slot() as usize];
+        let (start, freq) = model.pair(symbol);
+        decoder.advance(start, freq)?;
+        model.update(symbol);
+        residuals.push(unzigzag(detokenize(symbol, &mut extra)?));
+    }
+    Ok(residuals)
+}
+
+fn compress_with(
+    samples: &[i16],
+    spec: &WavSpec,
+    encode_residuals: fn(&mut BitWriter, &[i32]),
+) -> Vec<u8> {
+    let mut writer = BitWriter::with_capacity(BitOrder::Msb, samples.len());
+    write_spec_header(&mut writer, spec, samples.len());
+    for channel in deinterleave(samples, spec.channels as usize) {
+        let (orders, residuals) = fixed_block_residuals(&channel);
+        write_fixed_orders(&mut writer, &orders);
+        encode_residuals(&mut writer, &residuals);
+    }
+    writer.finish()
+}
+
+/// Signature shared by the residual entropy decoders, so codecs can pick one.
+pub type ResidualDecoder =
+    fn(&mut BitReader, usize) -> Result<Vec<i32>, Box<dyn Error + Send + Sync>>;
+
+fn decompress_with(
+    buffer: &[u8],
+    decode_residuals: ResidualDecoder,
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    let mut reader = BitReader::new(buffer, BitOrder::Msb);
+    let (spec, sample_count) = read_spec_header(&mut reader)?;
+    let frames = sample_count / spec.channels as usize;
+
+    let mut channels = Vec::with_capacity(spec.channels as usize);
+    for _ in 0..spec.channels {
+        let orders = read_fixed_orders(&mut reader, frames)?;
+        let residuals = decode_residuals(&mut reader, frames)?;
+        channels.push(restore_fixed_blocks(&orders, &residuals));
+    }
+    Ok((interleave(&channels), spec))
+}
+
+/// Compresses samples with per-block fixed prediction and static rANS tables.
+pub fn compress_ans(
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into ANS format...");
+    let compressed = compress_with(samples, spec, encode_residuals_static);
+    debug!("Finished compressing data into ANS format");
+    Ok(compressed)
+}
+
+/// Estimated encoder cost of [`compress_ans`].
+pub fn cost_ans() -> CostModel {
+    // The deinterleaved channels are held as i32.
+    CostModel::buffer(4.0) + fixed_cost() + encode_residuals_static_cost()
+}
+
+pub fn decompress_ans(buffer: &[u8]) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from ANS format...");
+    let decompressed = decompress_with(buffer, decode_residuals_static)?;
+    debug!("Finished decompressing data from ANS format");
+    Ok(decompressed)
+}
+
+/// Compresses samples with per-block fixed prediction and an adaptive rANS model.
+pub fn compress_ans_adaptive(
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into adaptive ANS format...");
+    let compressed = compress_with(samples, spec, encode_residuals_adaptive);
+    debug!("Finished compressing data into adaptive ANS format");
+    Ok(compressed)
+}
+
+/// Estimated encoder cost of [`compress_ans_adaptive`].
+pub fn cost_ans_adaptive() -> CostModel {
+    CostModel::buffer(4.0) + fixed_cost() + encode_residuals_adaptive_cost()
+}
+
+pub fn decompress_ans_adaptive(
+    buffer: &[u8],
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from adaptive ANS format...");
+    let decompressed = decompress_with(buffer, decode_residuals_adaptive)?;
+    debug!("Finished decompressing data from adaptive ANS format");
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_residuals() -> Vec<i32> {
+        let mut residuals: Vec<i32> = (0..40_000).map(|i| ((i * 7919) % 301) - 150).collect();
+        residuals[3] = i32::MIN;
+        residuals[20_000] = i32::MAX;
+        residuals[30_000] = 1 << 17;
+        residuals
+    }
+
+    #[test]
+    fn tokens_roundtrip() {
+        for value in [0, 1, 63, 64, 65, 127, 128, 1000, 1 << 20, u32::MAX] {
+            let (symbol, extra_value, bits) = tokenize(value);
+            assert!((symbol as usize) < ALPHABET_SIZE);
+            let mut writer = BitWriter::new(BitOrder::Msb);
+            if bits > 0 {
+                writer.write_bits(extra_value, bits);
+            }
+            let bytes = writer.finish();
+            let mut reader = BitReader::new(&bytes, BitOrder::Msb);
+            assert_eq!(detokenize(symbol, &mut reader).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn static_residuals_roundtrip() {
+        let residuals = test_residuals();
+        let mut writer = BitWriter::new(BitOrder::Msb);
+        encode_residuals_static(&mut writer, &residuals);
+        let bytes = writer.finish();
+        let mut reader = BitReader::new(&bytes, BitOrder::Msb);
+        assert_eq!(
+            decode_residuals_static(&mut reader, residuals.len()).unwrap(),
+            residuals
+        );
+    }
+
+    #[test]
+    fn adaptive_residuals_roundtrip() {
+        let residuals = test_residuals();
+        let mut writer = BitWriter::new(BitOrder::Msb);
+        encode_residuals_adaptive(&mut writer, &residuals);
+        let bytes = writer.finish();
+        let mut reader = BitReader::new(&bytes, BitOrder::Msb);
+        assert_eq!(
+            decode_residuals_adaptive(&mut reader, residuals.len()).unwrap(),
+            residuals
+        );
+    }
+
+    #[test]
+    fn frequency_table_roundtrip() {
+        let mut counts = vec![0; ALPHABET_SIZE];
+        counts[0] = 100_000;
+        counts[5] = 1;
+        counts[70] = 3;
+        let table = FrequencyTable::from_counts(&counts);
+        assert_eq!(table.freqs.iter().sum::<u32>(), PROB_SCALE);
+        assert!(table.freqs[5] >= 1);
+
+        let mut writer = BitWriter::new(BitOrder::Msb);
+        table.write(&mut writer);
+        let bytes = writer.finish();
+        let mut reader = BitReader::new(&bytes, BitOrder::Msb);
+        assert_eq!(FrequencyTable::read(&mut reader).unwrap(), table);
+    }
+}
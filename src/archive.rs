@@ -0,0 +1,630 @@
+use crate::cm::SharedStats;
+use crate::codec::{decompress_with, Codec};
+use crate::model::{reference_model, train_on_files};
+use crate::recording::{
+    decompress_recording_with, is_sample_file, read_sample_file, wrap_recording,
+    write_recording_parts, RecordingFormat, SampleFile,
+};
+use crate::wav::{read_to_end_limited, write_wav};
+use hound::WavSpec;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use tracing::debug;
+
+/// First bytes of every archive.
+const MAGIC: &[u8; 4] = b"SBA1";
+
+/// How many recordings, spread evenly over the dataset, the shared
+/// statistics are learned from. More barely changes them and costs a full
+/// encode each.
+const TRAINING_FILES: usize = 16;
+
+/// zstd level for the shared statistics and for files stored as they are.
+const STORE_LEVEL: i32 = 19;
+
+/// How an entry's payload is decoded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    /// A codec stream: a WAV file's samples, or an acquisition recording
+    /// wrapped by [`wrap_recording`].
+    Samples,
+    /// Any other file, such as notes or an Open Ephys `structure.oebin`,
+    /// compressed with zstd.
+    File,
+}
+
+impl EntryKind {
+    fn id(self) -> u8 {
+        match self {
+            EntryKind::Samples => 0,
+            EntryKind::File => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<EntryKind, Box<dyn Error + Send + Sync>> {
+        match id {
+            0 => Ok(EntryKind::Samples),
+            1 => Ok(EntryKind::File),
+            _ => Err(Box::from(format!("Unknown archive entry kind {}", id))),
+        }
+    }
+}
+
+/// One file of an archive's table.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Relative path with `/` separators.
+    pub path: String,
+    pub kind: EntryKind,
+    /// Size of the original file.
+    pub size: u64,
+    /// Where the payload starts, counted from the end of the table.
+    offset: u64,
+    /// Size of the payload.
+    pub length: u64,
+}
+
+/// What [`pack_directory`] wrote.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PackSummary {
+    pub files: usize,
+    /// Files whose samples went through the codec rather than zstd.
+    pub sample_files: usize,
+    pub input_bytes: u64,
+    pub archive_bytes: u64,
+    /// Compressed size of the shared statistics, zero if none were stored.
+    pub stats_bytes: u64,
+}
+
+/// Every file below `dir`, sorted. Symlinked directories are skipped, so a
+/// link back up the tree cannot recurse forever.
+fn dataset_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            files.extend(dataset_files(&path)?);
+        } else if file_type.is_symlink() && path.is_dir() {
+            debug!("Skipping symlinked directory {}", path.display());
+        } else {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// `path` relative to `dir` with `/` separators, as stored in the table.
+fn entry_path(dir: &Path, path: &Path) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let components = path
+        .strip_prefix(dir)?
+        .components()
+        .map(|c| {
+            c.as_os_str()
+                .to_str()
+                .ok_or_else(|| format!("Path is not valid UTF-8: {}", path.display()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(components.join("/"))
+}
+
+/// Where an entry is extracted to, refusing paths that would leave
+/// `output_dir`.
+fn output_path(output_dir: &Path, entry: &str) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+    let relative = Path::new(entry);
+    if entry.is_empty()
+        || !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(Box::from(format!("Unsafe path in archive: {}", entry)));
+    }
+    Ok(output_dir.join(relative))
+}
+
+/// Reads the samples of a file the codecs take, or `None` for any other
+/// file and for one that fails to read, which is stored with zstd instead.
+fn read_samples(path: &Path) -> Option<SampleFile> {
+    match read_sample_file(path) {
+        Ok(file) => file,
+        Err(e) => {
+            debug!("Storing {} as a plain file: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// The file a sample entry is written back as: a WAV with the canonical
+/// 44-byte header, or the recording in its own format.
+fn sample_file_bytes(
+    samples: &[i16],
+    spec: WavSpec,
+    original: Option<(RecordingFormat, &[u8])>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut bytes = Vec::new();
+    match original {
+        Some((format, metadata)) => write_recording_parts(&mut bytes, format, samples, metadata)?,
+        None => write_wav(&mut bytes, samples, spec)?,
+    }
+    Ok(bytes)
+}
+
+/// Compresses one file into its entry kind, original size and payload.
+///
+/// A file only goes through the codec if writing its samples back gives the
+/// same bytes; a WAV with chunks besides `fmt ` and `data`, for one, would
+/// lose them, and is stored with zstd instead.
+fn encode_file(
+    path: &Path,
+    codec: Codec,
+    stats: Option<&SharedStats>,
+) -> Result<(EntryKind, u64, Vec<u8>), Box<dyn Error + Send + Sync>> {
+    let contents = fs::read(path)?;
+    let size = contents.len() as u64;
+    let sample_file = read_samples(path).filter(|(samples, spec, original)| {
+        let original = original
+            .as_ref()
+            .map(|(format, metadata)| (*format, metadata.as_slice()));
+        let restored = sample_file_bytes(samples, *spec, original).ok();
+        if restored.as_ref() != Some(&contents) {
+            debug!(
+                "Storing {} as a plain file: its samples do not restore it",
+                path.display()
+            );
+            return false;
+        }
+        true
+    });
+    let payload = match sample_file {
+        Some((samples, spec, original)) => {
+            let stream = match stats {
+                Some(stats) => {
+                    reference_model(stats, &codec.compress_with(&samples, &spec, Some(stats))?)
+                }
+                None => codec.compress(&samples, &spec)?,
+            };
+            let payload = match original {
+                Some((format, metadata)) => wrap_recording(format, &metadata, &stream)?,
+                None => stream,
+            };
+            return Ok((EntryKind::Samples, size, payload));
+        }
+        None => zstd::encode_all(contents.as_slice(), STORE_LEVEL)?,
+    };
+    Ok((EntryKind::File, size, payload))
+}
+
+/// Compresses every file below `input_dir` into one archive at `output`.
+///
+/// WAV files and acquisition recordings go through `codec`; everything else,
+/// and any of those that the codec path would not restore byte for byte, is
+/// kept with zstd, so unpacking restores the whole directory. With `cm`,
+/// statistics learned from up to [`TRAINING_FILES`] recordings are stored
+/// once and every recording is coded against them.
+///
+/// Layout, integers big-endian:
+///
+/// - `SBA1`, then the length of the zstd-compressed shared statistics (0 if
+///   there are none) and the statistics themselves;
+/// - the entry count, then per entry the path length (16 bits), the UTF-8
+///   path, the [`EntryKind`] (8 bits), and the original size, payload offset
+///   and payload length (64 bits each);
+/// - the payloads, in table order.
+pub fn pack_directory(
+    input_dir: &Path,
+    output: &Path,
+    codec: Codec,
+) -> Result<PackSummary, Box<dyn Error + Send + Sync>> {
+    // An archive written into the directory it packs is left out, so a
+    // re-run does not pack the previous archive into the new one.
+    let archive = fs::canonicalize(output).ok();
+    let paths: Vec<PathBuf> = dataset_files(input_dir)?
+        .into_iter()
+        .filter(|path| archive.is_none() || fs::canonicalize(path).ok() != archive)
+        .collect();
+    let names = paths
+        .iter()
+        .map(|path| entry_path(input_dir, path))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(name) = names.iter().find(|name| name.len() > u16::MAX as usize) {
+        return Err(Box::from(format!("Path too long for an archive: {}", name)));
+    }
+
+    let stats = if codec.uses_shared_stats() {
+        let candidates: Vec<PathBuf> = paths
+            .iter()
+            .filter(|path| is_sample_file(path))
+            .cloned()
+            .collect();
+        train_on_files(&candidates, TRAINING_FILES)
+    } else {
+        None
+    };
+    let stats_bytes = match &stats {
+        Some(stats) => zstd::encode_all(stats.to_bytes().as_slice(), STORE_LEVEL)?,
+        None => Vec::new(),
+    };
+
+    let encoded = paths
+        .par_iter()
+        .map(|path| encode_file(path, codec, stats.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut writer = BufWriter::new(File::create(output)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&(stats_bytes.len() as u32).to_be_bytes())?;
+    writer.write_all(&stats_bytes)?;
+    writer.write_all(&(encoded.len() as u32).to_be_bytes())?;
+    let mut offset = 0u64;
+    for (name, (kind, size, payload)) in names.iter().zip(&encoded) {
+        writer.write_all(&(name.len() as u16).to_be_bytes())?;
+        writer.write_all(name.as_bytes())?;
+        writer.write_all(&[kind.id()])?;
+        writer.write_all(&size.to_be_bytes())?;
+        writer.write_all(&offset.to_be_bytes())?;
+        writer.write_all(&(payload.len() as u64).to_be_bytes())?;
+        offset += payload.len() as u64;
+    }
+    for (_, _, payload) in &encoded {
+        writer.write_all(payload)?;
+    }
+    writer.flush()?;
+
+    let summary = PackSummary {
+        files: encoded.len(),
+        sample_files: encoded
+            .iter()
+            .filter(|(kind, _, _)| *kind == EntryKind::Samples)
+            .count(),
+        input_bytes: encoded.iter().map(|(_, size, _)| size).sum(),
+        archive_bytes: fs::metadata(output)?.len(),
+        stats_bytes: stats_bytes.len() as u64,
+    };
+    debug!("Packed {:?}", summary);
+    Ok(summary)
+}
+
+fn read_u8<R: Read>(reader: &mut R) -> io::Result<u8> {
+    let mut bytes = [0; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u16<R: Read>(reader: &mut R) -> io::Result<u16> {
+    let mut bytes = [0; 2];
+    reader.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+/// An archive written by [`pack_directory`], opened for extraction. Only
+/// the table and shared statistics are read up front; each entry's payload
+/// is read and decoded on its own.
+pub struct Archive<R> {
+    reader: R,
+    stats: Option<SharedStats>,
+    entries: Vec<ArchiveEntry>,
+    /// Position of the first payload.
+    payload_start: u64,
+}
+
+impl<R: Read + Seek> Archive<R> {
+    pub fn open(mut reader: R) -> Result<Archive<R>, Box<dyn Error + Send + Sync>> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(Box::from("Not a smallbrain archive"));
+        }
+        let stats_len = read_u32(&mut reader)? as u64;
+        let stats = match stats_len {
+            0 => None,
+            _ => {
+                let bytes =
+                    read_to_end_limited(zstd::Decoder::new((&mut reader).take(stats_len))?)?;
+                Some(SharedStats::from_bytes(&bytes)?)
+            }
+        };
+        reader.seek(SeekFrom::Start(8 + stats_len))?;
+
+        let count = read_u32(&mut reader)?;
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let mut path = vec![0; read_u16(&mut reader)? as usize];
+            reader.read_exact(&mut path)?;
+            entries.push(ArchiveEntry {
+                path: String::from_utf8(path)?,
+                kind: EntryKind::from_id(read_u8(&mut reader)?)?,
+                size: read_u64(&mut reader)?,
+                offset: read_u64(&mut reader)?,
+                length: read_u64(&mut reader)?,
+            });
+        }
+        let payload_start = reader.stream_position()?;
+        let payload_len = reader.seek(SeekFrom::End(0))? - payload_start;
+        for entry in &entries {
+            if entry
+                .offset
+                .checked_add(entry.length)
+                .is_none_or(|end| end > payload_len)
+            {
+                return Err(Box::from(format!(
+                    "Archive entry {} runs past the end of the archive",
+                    entry.path
+                )));
+            }
+        }
+        debug!(
+            "Opened archive of {} entries, shared statistics: {}",
+            entries.len(),
+            stats.is_some()
+        );
+        Ok(Archive {
+            reader,
+            stats,
+            entries,
+            payload_start,
+        })
+    }
+
+    pub fn entries(&self) -> &[ArchiveEntry] {
+        &self.entries
+    }
+
+    /// Reads the still-compressed payload of entry `index`.
+    pub fn read_payload(&mut self, index: usize) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        let entry = self.entries.get(index).ok_or("No such archive entry")?;
+        self.reader
+            .seek(SeekFrom::Start(self.payload_start + entry.offset))?;
+        let mut payload = Vec::new();
+        (&mut self.reader)
+            .take(entry.length)
+            .read_to_end(&mut payload)?;
+        Ok(payload)
+    }
+
+    /// Decodes entry `index` and writes it below `output_dir` as the file
+    /// it was packed from, returning where it went.
+    pub fn extract(
+        &mut self,
+        index: usize,
+        output_dir: &Path,
+    ) -> Result<PathBuf, Box<dyn Error + Send + Sync>> {
+        let payload = self.read_payload(index)?;
+        let entry = &self.entries[index];
+        let path = output_path(output_dir, &entry.path)?;
+        debug!("Extracting {} to {}", entry.path, path.display());
+        let bytes = match entry.kind {
+            EntryKind::Samples => match decompress_recording_with(&payload, self.stats.as_ref())? {
+                Some(recording) => sample_file_bytes(
+                    &recording.samples,
+                    recording.spec,
+                    Some((recording.format, &recording.metadata)),
+                )?,
+                None => {
+                    let (samples, spec) = decompress_with(&payload, self.stats.as_ref())?;
+                    sample_file_bytes(&samples, spec, None)?
+                }
+            },
+            EntryKind::File => {
+                // The table's size bounds what the payload may inflate to; one
+                // byte more shows a payload that does not match it.
+                let mut bytes = Vec::new();
+                zstd::Decoder::new(payload.as_slice())?
+                    .take(entry.size + 1)
+                    .read_to_end(&mut bytes)?;
+                bytes
+            }
+        };
+        // Checked before anything is written, so a bad entry leaves no file.
+        if bytes.len() as u64 != entry.size {
+            return Err(size_mismatch(entry, bytes.len() as u64));
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, &bytes)?;
+        Ok(path)
+    }
+}
+
+fn size_mismatch(entry: &ArchiveEntry, size: u64) -> Box<dyn Error + Send + Sync> {
+    Box::from(format!(
+        "{} decodes to {} bytes, but the archive lists {}",
+        entry.path, size, entry.size
+    ))
+}
+
+/// Extracts every entry of the archive at `archive` into `output_dir`, or
+/// only the one whose path is `only`. Returns the number of files written.
+pub fn unpack_archive(
+    archive: &Path,
+    output_dir: &Path,
+    only: Option<&str>,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let mut archive = Archive::open(BufReader::new(File::open(archive)?))?;
+    let indices: Vec<usize> = match only {
+        Some(name) => vec![archive
+            .entries()
+            .iter()
+            .position(|entry| entry.path == name)
+            .ok_or_else(|| format!("{} is not in the archive", name))?],
+        None => (0..archive.entries().len()).collect(),
+    };
+    for &index in &indices {
+        archive.extract(index, output_dir)?;
+    }
+    Ok(indices.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nsx::tests::nsx_file;
+    use crate::openephys::tests::openephys_recording;
+    use crate::wav::write_wav_file;
+    use hound::SampleFormat;
+
+    fn dataset(dir: &Path) {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        for file in 0..5 {
+            let samples: Vec<i16> = (0..3000)
+                .map(|i| ((i * (file + 3)) % 40 - 20) as i16 * 64)
+                .collect();
+            let path = dir.join(format!("data/{}.wav", file));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            write_wav_file(path.to_str().unwrap(), &samples, spec).unwrap();
+        }
+        // A LIST chunk that writing the samples back would drop.
+        let mut tagged = Vec::new();
+        write_wav(&mut tagged, &[5, -5, 64, 0, 128], spec).unwrap();
+        let list = b"LIST\x0e\x00\x00\x00INFOICMT\x02\x00\x00\x00a\x00";
+        tagged.splice(36..36, list.iter().copied());
+        let riff_len = (tagged.len() - 8) as u32;
+        tagged[4..8].copy_from_slice(&riff_len.to_le_bytes());
+        fs::write(dir.join("data/tagged.wav"), tagged).unwrap();
+
+        fs::write(dir.join("data/session.ns5"), nsx_file(2)).unwrap();
+        openephys_recording(&dir.join("Record Node 101/recording1"), 2, 500);
+        fs::write(dir.join("metadata.txt"), "subject: 7\n".repeat(20)).unwrap();
+    }
+
+    #[test]
+    fn pack_and_unpack_restore_the_directory() {
+        let base = std::env::temp_dir().join(format!("smallbrain-archive-{}", std::process::id()));
+        let input = base.join("input");
+        dataset(&input);
+        let archive_path = base.join("dataset.sba");
+
+        for codec in [Codec::Cm, Codec::Rice] {
+            let summary = pack_directory(&input, &archive_path, codec).unwrap();
+            assert_eq!(summary.files, 10);
+            assert_eq!(summary.sample_files, 7);
+            assert_eq!(summary.stats_bytes > 0, codec == Codec::Cm);
+
+            let output = base.join(format!("output-{}", codec.name()));
+            assert_eq!(unpack_archive(&archive_path, &output, None).unwrap(), 10);
+            for path in dataset_files(&input).unwrap() {
+                let restored = output.join(path.strip_prefix(&input).unwrap());
+                assert_eq!(fs::read(&restored).unwrap(), fs::read(&path).unwrap());
+            }
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_and_the_archive_itself_are_not_packed() {
+        let base =
+            std::env::temp_dir().join(format!("smallbrain-archive-links-{}", std::process::id()));
+        let input = base.join("input");
+        dataset(&input);
+        std::os::unix::fs::symlink(&input, input.join("data/loop")).unwrap();
+
+        // Packing twice into the directory itself finds the same files.
+        let archive_path = input.join("dataset.sba");
+        for _ in 0..2 {
+            let summary = pack_directory(&input, &archive_path, Codec::Rice).unwrap();
+            assert_eq!(summary.files, 10);
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn one_entry_extracts_without_the_rest() {
+        let base =
+            std::env::temp_dir().join(format!("smallbrain-archive-one-{}", std::process::id()));
+        let input = base.join("input");
+        dataset(&input);
+        let archive_path = base.join("dataset.sba");
+        pack_directory(&input, &archive_path, Codec::Cm).unwrap();
+
+        // Damage every payload but that of data/3.wav.
+        let mut bytes = fs::read(&archive_path).unwrap();
+        let archive = Archive::open(io::Cursor::new(bytes.clone())).unwrap();
+        let start = archive.payload_start as usize;
+        let kept = archive
+            .entries()
+            .iter()
+            .find(|entry| entry.path == "data/3.wav")
+            .unwrap()
+            .clone();
+        for entry in archive.entries().iter().filter(|entry| **entry != kept) {
+            let payload = start + entry.offset as usize;
+            bytes[payload..payload + entry.length as usize].fill(0xAA);
+        }
+        fs::write(&archive_path, &bytes).unwrap();
+
+        let output = base.join("output");
+        assert!(unpack_archive(&archive_path, &output, None).is_err());
+        assert_eq!(
+            unpack_archive(&archive_path, &output, Some("data/3.wav")).unwrap(),
+            1
+        );
+        assert_eq!(
+            fs::read(output.join("data/3.wav")).unwrap(),
+            fs::read(input.join("data/3.wav")).unwrap()
+        );
+        assert!(unpack_archive(&archive_path, &output, Some("data/9.wav")).is_err());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn paths_stay_inside_the_output_directory() {
+        let dir = Path::new("out");
+        assert_eq!(output_path(dir, "a/b.wav").unwrap(), dir.join("a/b.wav"));
+        for unsafe_path in ["", "../b.wav", "/etc/passwd", "a/../../b"] {
+            assert!(output_path(dir, unsafe_path).is_err());
+        }
+    }
+
+    #[test]
+    fn listed_sizes_are_checked_on_extract() {
+        let base =
+            std::env::temp_dir().join(format!("smallbrain-archive-size-{}", std::process::id()));
+        let input = base.join("input");
+        dataset(&input);
+        let archive_path = base.join("dataset.sba");
+        pack_directory(&input, &archive_path, Codec::Rice).unwrap();
+
+        let mut archive =
+            Archive::open(BufReader::new(File::open(&archive_path).unwrap())).unwrap();
+        let output = base.join("output");
+        for name in ["data/1.wav", "metadata.txt"] {
+            let index = archive
+                .entries()
+                .iter()
+                .position(|entry| entry.path == name)
+                .unwrap();
+            let size = archive.entries[index].size;
+            for wrong in [size - 1, size + 1] {
+                archive.entries[index].size = wrong;
+                assert!(archive.extract(index, &output).is_err(), "{}", name);
+                assert!(!output.join(name).exists(), "{}", name);
+            }
+            archive.entries[index].size = size;
+            archive.extract(index, &output).unwrap();
+        }
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
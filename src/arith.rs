@@ -0,0 +1,204 @@
+use std::error::Error;
+
+/// Probabilities are 16-bit fixed point: `p / 65536` is the chance of a one.
+const PROB_BITS: u32 = 16;
+
+/// Adaptive rate limit; after this many observations a model settles on a
+/// fixed exponential decay.
+const MAX_COUNT: u32 = 60;
+
+/// Adaptive probability of a single binary event.
+#[derive(Clone, Copy, Debug)]
+pub struct BitModel {
+    p: u32,
+    count: u32,
+}
+
+impl Default for BitModel {
+    fn default() -> Self {
+        BitModel {
+            p: 1 << (PROB_BITS - 1),
+            count: 0,
+        }
+    }
+}
+
+impl BitModel {
+    /// A model that starts at probability `p / 65536` of a one, as if it had
+    /// already seen `count` observations.
+    pub fn with_probability(p: u32, count: u32) -> Self {
+        BitModel {
+            p: p.clamp(32, (1 << PROB_BITS) - 32),
+            count: count.min(MAX_COUNT),
+        }
+    }
+
+    /// Current probability of a one, out of 65536.
+    pub fn probability(&self) -> u32 {
+        self.p
+    }
+
+    /// Whether the model has seen any observation since it was created.
+    pub fn is_trained(&self) -> bool {
+        self.count > 0
+    }
+
+    /// Moves the probability towards `bit`, quickly at first and more slowly
+    /// as observations accumulate.
+    fn update(&mut self, bit: bool) {
+        let target = if bit { (1 << PROB_BITS) - 1 } else { 0 };
+        let delta = (target - self.p as i32) / (self.count as i32 + 2);
+        self.p = (self.p as i32 + delta).clamp(32, (1 << PROB_BITS) - 32) as u32;
+        self.count = (self.count + 1).min(MAX_COUNT);
+    }
+}
+
+/// Carry-less binary arithmetic coder over a 32-bit range.
+pub struct ArithmeticEncoder {
+    low: u32,
+    high: u32,
+    output: Vec<u8>,
+}
+
+impl Default for ArithmeticEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArithmeticEncoder {
+    pub fn new() -> Self {
+        ArithmeticEncoder {
+            low: 0,
+            high: u32::MAX,
+            output: Vec::new(),
+        }
+    }
+
+    /// Codes `bit` with the model's current probability, then adapts the model.
+    pub fn encode(&mut self, model: &mut BitModel, bit: bool) {
+        let mid = split(self.low, self.high, model.p);
+        if bit {
+            self.high = mid;
+        } else {
+            self.low = mid + 1;
+        }
+        model.update(bit);
+
+        while (self.low ^ self.high) & 0xFF00_0000 == 0 {
+            self.output.push((self.high >> 24) as u8);
+            self.low <<= 8;
+            self.high = (self.high << 8) | 0xFF;
+        }
+    }
+
+    /// Codes the low `width` bits of `value` with fixed probability one half.
+    pub fn encode_raw(&mut self, value: u32, width: u32) {
+        for shift in (0..width).rev() {
+            self.encode(&mut BitModel::default(), (value >> shift) & 1 == 1);
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.output.extend_from_slice(&self.low.to_be_bytes());
+        self.output
+    }
+}
+
+pub struct ArithmeticDecoder<'a> {
+    low: u32,
+    high: u32,
+    code: u32,
+    input: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ArithmeticDecoder<'a> {
+    pub fn new(input: &'a [u8]) -> Self {
+        let mut decoder = ArithmeticDecoder {
+            low: 0,
+            high: u32::MAX,
+            code: 0,
+            input,
+            position: 0,
+        };
+        for _ in 0..4 {
+            decoder.code = (decoder.code << 8) | decoder.next_byte() as u32;
+        }
+        decoder
+    }
+
+    /// Reads past the end yield zeros, matching the encoder's final flush.
+    fn next_byte(&mut self) -> u8 {
+        let byte = self.input.get(self.position).copied().unwrap_or(0);
+        self.position += 1;
+        byte
+    }
+
+    pub fn decode(&mut self, model: &mut BitModel) -> bool {
+        let mid = split(self.low, self.high, model.p);
+        let bit = self.code <= mid;
+        if bit {
+            self.high = mid;
+        } else {
+            self.low = mid + 1;
+        }
+        model.update(bit);
+
+        while (self.low ^ self.high) & 0xFF00_0000 == 0 {
+            self.low <<= 8;
+            self.high = (self.high << 8) | 0xFF;
+            self.code = (self.code << 8) | self.next_byte() as u32;
+        }
+        bit
+    }
+
+    pub fn decode_raw(&mut self, width: u32) -> u32 {
+        (0..width).fold(0, |value, _| {
+            (value << 1) | self.decode(&mut BitModel::default()) as u32
+        })
+    }
+
+    /// Fails once the decoder has consumed well past the end of its input,
+    /// which only happens on corrupt data.
+    pub fn check_overrun(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if self.position > self.input.len() + 4 {
+            return Err(Box::from("Unexpected end of arithmetic-coded stream"));
+        }
+        Ok(())
+    }
+}
+
+/// Point inside `[low, high]` that separates a one (below) from a zero (above).
+fn split(low: u32, high: u32, p: u32) -> u32 {
+    low + (((high - low) as u64 * p as u64) >> PROB_BITS) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip_skewed_bits() {
+        let bits: Vec<bool> = (0..20_000u32)
+            .map(|i| i.wrapping_mul(2_654_435_761) % 97 < 9)
+            .collect();
+
+        let mut encoder = ArithmeticEncoder::new();
+        let mut model = BitModel::default();
+        for &bit in &bits {
+            encoder.encode(&mut model, bit);
+        }
+        encoder.encode_raw(0xABCDE, 20);
+        let bytes = encoder.finish();
+        assert!(bytes.len() < bits.len() / 8 / 2);
+
+        let mut decoder = ArithmeticDecoder::new(&bytes);
+        let mut model = BitModel::default();
+        for &bit in &bits {
+            assert_eq!(decoder.decode(&mut model), bit);
+        }
+        assert_eq!(decoder.decode_raw(20), 0xABCDE);
+        decoder.check_overrun().unwrap();
+    }
+}
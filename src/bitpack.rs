@@ -0,0 +1,327 @@
+use smallbrain_core::bits::BitWrite;
+use std::error::Error;
+
+/// Order in which bits are laid out inside each output byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BitOrder {
+    /// The first bit written lands in the most significant bit of a byte, and
+    /// multi-bit values are written most significant bit first (FLAC style).
+    Msb,
+    /// The first bit written lands in the least significant bit of a byte, and
+    /// multi-bit values are written least significant bit first (the layout
+    /// the original 10-bit packer used).
+    Lsb,
+}
+
+/// Accumulates values of arbitrary width (1 to 32 bits) into a byte buffer.
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    bits_in_buffer: u32,
+    order: BitOrder,
+}
+
+impl BitWriter {
+    pub fn new(order: BitOrder) -> Self {
+        Self::with_capacity(order, 0)
+    }
+
+    pub fn with_capacity(order: BitOrder, bytes: usize) -> Self {
+        BitWriter {
+            bytes: Vec::with_capacity(bytes),
+            buffer: 0,
+            bits_in_buffer: 0,
+            order,
+        }
+    }
+
+    /// Writes the low `width` bits of `value`. Panics if `width` is not in `1..=32`.
+    pub fn write_bits(&mut self, value: u32, width: u32) {
+        assert!(
+            (1..=32).contains(&width),
+            "bit width must be between 1 and 32, got {}",
+            width
+        );
+        let value = (value as u64) & mask(width);
+
+        match self.order {
+            BitOrder::Lsb => {
+                self.buffer |= value << self.bits_in_buffer;
+                self.bits_in_buffer += width;
+                while self.bits_in_buffer >= 8 {
+                    self.bytes.push(self.buffer as u8);
+                    self.buffer >>= 8;
+                    self.bits_in_buffer -= 8;
+                }
+            }
+            BitOrder::Msb => {
+                self.buffer = (self.buffer << width) | value;
+                self.bits_in_buffer += width;
+                while self.bits_in_buffer >= 8 {
+                    self.bits_in_buffer -= 8;
+                    self.bytes.push((self.buffer >> self.bits_in_buffer) as u8);
+                }
+                self.buffer &= mask(self.bits_in_buffer);
+            }
+        }
+    }
+
+    pub fn write_bit(&mut self, bit: bool) {
+        self.write_bits(bit as u32, 1);
+    }
+
+    /// Pads the current byte with zero bits so the next write starts on a byte boundary.
+    pub fn align(&mut self) {
+        if self.bits_in_buffer > 0 {
+            self.write_bits(0, 8 - self.bits_in_buffer);
+        }
+    }
+
+    /// Appends whole bytes, aligning first.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.align();
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    /// Number of bits written so far.
+    pub fn bit_len(&self) -> u64 {
+        self.bytes.len() as u64 * 8 + self.bits_in_buffer as u64
+    }
+
+    /// Flushes any partial byte (zero padded) and returns the buffer.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+impl BitWrite for BitWriter {
+    fn write_bits(&mut self, value: u32, width: u32) {
+        BitWriter::write_bits(self, value, width);
+    }
+}
+
+/// Reads values of arbitrary width back out of a buffer produced by [`BitWriter`].
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    position: u64,
+    order: BitOrder,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8], order: BitOrder) -> Self {
+        BitReader {
+            data,
+            position: 0,
+            order,
+        }
+    }
+
+    /// Reads `width` bits, failing if the buffer runs out. Panics if `width` is not in `1..=32`.
+    pub fn read_bits(&mut self, width: u32) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        assert!(
+            (1..=32).contains(&width),
+            "bit width must be between 1 and 32, got {}",
+            width
+        );
+        if (width as u64) > self.bits_remaining() {
+            return Err(Box::from(format!(
+                "Unexpected end of bit stream: wanted {} bits, {} left",
+                width,
+                self.bits_remaining()
+            )));
+        }
+
+        let mut value = 0u64;
+        let mut read = 0;
+        while read < width {
+            let byte = self.data[(self.position / 8) as usize] as u64;
+            let offset = (self.position % 8) as u32;
+            let take = (8 - offset).min(width - read);
+
+            match self.order {
+                BitOrder::Lsb => {
+                    value |= ((byte >> offset) & mask(take)) << read;
+                }
+                BitOrder::Msb => {
+                    value = (value << take) | ((byte >> (8 - offset - take)) & mask(take));
+                }
+            }
+
+            read += take;
+            self.position += take as u64;
+        }
+
+        Ok(value as u32)
+    }
+
+    pub fn read_bit(&mut self) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        Ok(self.read_bits(1)? == 1)
+    }
+
+    /// Skips to the next byte boundary.
+    pub fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+
+    /// Reads `len` whole bytes, aligning first.
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error + Send + Sync>> {
+        self.align();
+        let start = (self.position / 8) as usize;
+        if len > self.data.len().saturating_sub(start) {
+            return Err(Box::from(format!(
+                "Unexpected end of bit stream: wanted {} bytes, {} left",
+                len,
+                self.data.len().saturating_sub(start)
+            )));
+        }
+        self.position += len as u64 * 8;
+        Ok(&self.data[start..start + len])
+    }
+
+    pub fn bits_remaining(&self) -> u64 {
+        (self.data.len() as u64 * 8).saturating_sub(self.position)
+    }
+
+    /// Checks a decoded count of `what` against the bits left. Every value
+    /// costs at least one bit, so this bounds what a corrupt count can
+    /// make the caller allocate.
+    pub fn check_count(
+        &self,
+        count: usize,
+        what: &str,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if count as u64 > self.bits_remaining() {
+            return Err(Box::from(format!(
+                "Bit stream too short for {} {}: {} bits left",
+                count,
+                what,
+                self.bits_remaining()
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn mask(width: u32) -> u64 {
+    (1u64 << width) - 1
+}
+
+/// Packs `values` at `width` bits each, prefixed with the value count so the
+/// exact length survives the trip through [`unpack_values`]. Fails if the
+/// count does not fit the 32-bit prefix.
+pub fn pack_values(
+    values: &[u32],
+    width: u32,
+    order: BitOrder,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    if values.len() > u32::MAX as usize {
+        return Err(Box::from(format!(
+            "Cannot pack {} values: the count prefix holds at most {}",
+            values.len(),
+            u32::MAX
+        )));
+    }
+    let payload_bytes = (values.len() as u64 * width as u64).div_ceil(8) as usize;
+    let mut writer = BitWriter::with_capacity(order, 4 + payload_bytes);
+    writer.write_bits(values.len() as u32, 32);
+    for &value in values {
+        writer.write_bits(value, width);
+    }
+    Ok(writer.finish())
+}
+
+/// Inverse of [`pack_values`]. Fails if the buffer is shorter than its length prefix claims.
+pub fn unpack_values(
+    packed: &[u8],
+    width: u32,
+    order: BitOrder,
+) -> Result<Vec<u32>, Box<dyn Error + Send + Sync>> {
+    let mut reader = BitReader::new(packed, order);
+    let count = reader.read_bits(32)? as u64;
+    if count * width as u64 > reader.bits_remaining() {
+        return Err(Box::from(format!(
+            "Packed buffer holds {} bits, not enough for {} values of {} bits",
+            reader.bits_remaining(),
+            count,
+            width
+        )));
+    }
+
+    let mut values = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        values.push(reader.read_bits(width)?);
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn order_strategy() -> impl Strategy<Value = BitOrder> {
+        prop_oneof![Just(BitOrder::Msb), Just(BitOrder::Lsb)]
+    }
+
+    fn values_strategy() -> impl Strategy<Value = (u32, Vec<u32>)> {
+        (1u32..=32).prop_flat_map(|width| {
+            let max = if width == 32 {
+                u32::MAX
+            } else {
+                (1 << width) - 1
+            };
+            (Just(width), prop::collection::vec(0..=max, 0..200))
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn pack_unpack_roundtrip((width, values) in values_strategy(), order in order_strategy()) {
+            let packed = pack_values(&values, width, order).unwrap();
+            prop_assert_eq!(packed.len() as u64, 4 + (values.len() as u64 * width as u64).div_ceil(8));
+            prop_assert_eq!(unpack_values(&packed, width, order).unwrap(), values);
+        }
+
+        #[test]
+        fn mixed_width_roundtrip(
+            fields in prop::collection::vec((1u32..=32, any::<u32>()), 0..100),
+            order in order_strategy(),
+        ) {
+            let mut writer = BitWriter::new(order);
+            for &(width, value) in &fields {
+                writer.write_bits(value, width);
+            }
+            let bits = writer.bit_len();
+            let bytes = writer.finish();
+            prop_assert_eq!(bytes.len() as u64, bits.div_ceil(8));
+
+            let mut reader = BitReader::new(&bytes, order);
+            for &(width, value) in &fields {
+                let expected = (value as u64 & mask(width)) as u32;
+                prop_assert_eq!(reader.read_bits(width).unwrap(), expected);
+            }
+            prop_assert!(reader.bits_remaining() < 8);
+        }
+
+        #[test]
+        fn truncated_input_is_an_error((width, values) in values_strategy(), order in order_strategy()) {
+            prop_assume!(!values.is_empty());
+            let packed = pack_values(&values, width, order).unwrap();
+            let truncated = &packed[..packed.len() - 1];
+            prop_assert!(unpack_values(truncated, width, order).is_err());
+        }
+    }
+
+    #[test]
+    fn bit_orders_lay_out_bytes_differently() {
+        let mut msb = BitWriter::new(BitOrder::Msb);
+        let mut lsb = BitWriter::new(BitOrder::Lsb);
+        for writer in [&mut msb, &mut lsb] {
+            writer.write_bits(0b101, 3);
+            writer.write_bits(0b11, 2);
+        }
+        assert_eq!(msb.finish(), vec![0b1011_1000]);
+        assert_eq!(lsb.finish(), vec![0b0001_1101]);
+    }
+}
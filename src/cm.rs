@@ -0,0 +1,843 @@
+use crate::arith::{ArithmeticDecoder, ArithmeticEncoder, BitModel};
+use crate::bitpack::{BitOrder, BitReader, BitWriter};
+use crate::cost::CostModel;
+use crate::predictor::detect_grid;
+use crate::rice::{estimate_parameter, read_value, unzigzag, write_value, zigzag, MAX_PARAMETER};
+use crate::wav::{deinterleave, interleave, read_spec_header, write_spec_header};
+use hound::WavSpec;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::error::Error;
+use tracing::debug;
+
+/// Samples per block; each block carries its own LPC coefficients.
+const BLOCK_SIZE: usize = 8192;
+
+const LPC_ORDER: usize = 16;
+/// Fractional bits of the quantized LPC coefficients.
+const LPC_SHIFT: u32 = 14;
+/// Bits used to store each quantized coefficient (two's complement).
+const LPC_COEFF_BITS: u32 = 20;
+
+/// Taps of the sign-sign LMS filter that refines the LPC residual.
+const LMS_ORDER: usize = 16;
+/// Fractional bits of the LMS weights, and the weight step per sample.
+const LMS_SHIFT: u32 = 12;
+const LMS_STEP: i32 = 2;
+
+/// Size (log2) of the table remembering where each recent context last occurred.
+const MATCH_HASH_BITS: u32 = 16;
+
+/// Predictions are carried with this many fractional bits.
+const FRAC_BITS: u32 = 8;
+
+/// Number of predictions fed to the mixer, as deltas from the previous sample.
+const INPUTS: usize = 4;
+/// Fractional bits of the mixer weights, and the NLMS step size (as a right shift).
+const MIXER_SHIFT: u32 = 16;
+const MIXER_RATE_SHIFT: u32 = 3;
+
+/// Residual magnitudes are coded as a unary bit width (at most this) plus mantissa bits.
+const MAX_WIDTH: usize = 24;
+/// Number of contexts derived from the running residual magnitude.
+const MAGNITUDE_CONTEXTS: usize = 21;
+
+/// Clamp applied to predictions and mixer inputs so corrupt streams cannot overflow.
+const VALUE_LIMIT: i64 = 1 << 20;
+
+/// Binary decisions per residual assumed by [`cost_cm`]: zero, sign, and a
+/// width and mantissa for a residual about three bits wide.
+const COST_CODED_BITS: f64 = 8.0;
+
+/// Observations a model primed from [`SharedStats`] counts as having seen, so
+/// the shared probability is refined rather than overwritten by the first
+/// few residuals of a recording.
+const SHARED_COUNT: u32 = 12;
+
+fn bit_width(value: u64) -> usize {
+    (64 - value.leading_zeros()) as usize
+}
+
+/// Computes LPC coefficients for a block with a Welch window and Levinson-Durbin
+/// recursion, quantized to [`LPC_SHIFT`] fractional bits. Only the encoder
+/// runs this; the decoder reads the quantized result.
+fn compute_lpc(block: &[i32]) -> [i32; LPC_ORDER] {
+    let mut coefficients = [0; LPC_ORDER];
+    let n = block.len();
+    if n <= LPC_ORDER {
+        return coefficients;
+    }
+
+    let windowed: Vec<f64> = block
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let t = 2.0 * i as f64 / (n - 1) as f64 - 1.0;
+            x as f64 * (1.0 - t * t)
+        })
+        .collect();
+    let mut autocorrelation = [0.0; LPC_ORDER + 1];
+    for (lag, r) in autocorrelation.iter_mut().enumerate() {
+        *r = (lag..n).map(|i| windowed[i] * windowed[i - lag]).sum();
+    }
+    if autocorrelation[0] <= 0.0 {
+        return coefficients;
+    }
+    // A touch of white noise keeps the recursion well conditioned.
+    autocorrelation[0] *= 1.0 + 1e-6;
+
+    let mut a = [0.0f64; LPC_ORDER];
+    let mut error = autocorrelation[0];
+    for i in 0..LPC_ORDER {
+        let mut acc = autocorrelation[i + 1];
+        for j in 0..i {
+            acc -= a[j] * autocorrelation[i - j];
+        }
+        let k = acc / error;
+        let previous = a;
+        a[i] = k;
+        for j in 0..i {
+            a[j] = previous[j] - k * previous[i - 1 - j];
+        }
+        error *= 1.0 - k * k;
+        if error <= 0.0 {
+            break;
+        }
+    }
+
+    let limit = (1 << (LPC_COEFF_BITS - 1)) - 1;
+    for (q, &c) in coefficients.iter_mut().zip(&a) {
+        *q = ((c * (1 << LPC_SHIFT) as f64).round() as i32).clamp(-limit, limit);
+    }
+    coefficients
+}
+
+/// Writes quantized coefficients verbatim, or with [`SharedStats`] as
+/// Rice-coded differences from the shared mean coefficients.
+fn write_lpc(writer: &mut BitWriter, coefficients: &[i32; LPC_ORDER], stats: Option<&SharedStats>) {
+    match stats {
+        None => {
+            for &c in coefficients {
+                writer.write_bits(c as u32, LPC_COEFF_BITS);
+            }
+        }
+        Some(stats) => {
+            for ((&c, &mean), &k) in coefficients
+                .iter()
+                .zip(&stats.lpc_mean)
+                .zip(&stats.lpc_parameters)
+            {
+                write_value(writer, zigzag(c.wrapping_sub(mean)), k);
+            }
+        }
+    }
+}
+
+fn read_lpc(
+    reader: &mut BitReader,
+    stats: Option<&SharedStats>,
+) -> Result<[i32; LPC_ORDER], Box<dyn Error + Send + Sync>> {
+    let mut coefficients = [0; LPC_ORDER];
+    match stats {
+        None => {
+            for c in &mut coefficients {
+                let raw = reader.read_bits(LPC_COEFF_BITS)?;
+                // Sign-extend from LPC_COEFF_BITS
+                *c = ((raw << (32 - LPC_COEFF_BITS)) as i32) >> (32 - LPC_COEFF_BITS);
+            }
+        }
+        Some(stats) => {
+            for (j, c) in coefficients.iter_mut().enumerate() {
+                let difference = unzigzag(read_value(reader, stats.lpc_parameters[j])?);
+                *c = stats.lpc_mean[j].wrapping_add(difference);
+            }
+        }
+    }
+    Ok(coefficients)
+}
+
+/// What the mixer sees for one sample.
+struct Prediction {
+    /// Candidate predictions in fixed point, as deltas from the previous sample.
+    deltas: [i64; INPUTS],
+    /// LPC prediction in fixed point, needed again when updating the LMS stage.
+    lpc: i64,
+    /// Mixed prediction in fixed point, and rounded to an integer.
+    mixed: i64,
+    value: i64,
+}
+
+/// Predictor state for one channel, updated identically by encoder and decoder.
+struct ChannelModel {
+    values: Vec<i32>,
+    lpc_residuals: [i32; LMS_ORDER],
+    lms_weights: [i32; LMS_ORDER],
+    match_table: Vec<u32>,
+    mixer_weights: [i64; INPUTS],
+}
+
+impl ChannelModel {
+    fn new(capacity: usize) -> Self {
+        let mut mixer_weights = [0; INPUTS];
+        // Start by trusting LPC alone; the mixer learns the rest.
+        mixer_weights[1] = 1 << MIXER_SHIFT;
+        ChannelModel {
+            values: Vec::with_capacity(capacity),
+            lpc_residuals: [0; LMS_ORDER],
+            lms_weights: [0; LMS_ORDER],
+            match_table: vec![0; 1 << MATCH_HASH_BITS],
+            mixer_weights,
+        }
+    }
+
+    fn history(&self, k: usize) -> i64 {
+        let i = self.values.len();
+        if i >= k {
+            self.values[i - k] as i64
+        } else {
+            0
+        }
+    }
+
+    /// Hash of the last three first differences: the local waveform shape.
+    fn context_hash(&self) -> usize {
+        let d1 = (self.history(1) - self.history(2)) as u32;
+        let d2 = (self.history(2) - self.history(3)) as u32;
+        let d3 = (self.history(3) - self.history(4)) as u32;
+        let h = d1.wrapping_mul(0x9E37_79B1)
+            ^ d2.wrapping_mul(0x85EB_CA77)
+            ^ d3.wrapping_mul(0xC2B2_AE3D);
+        (h >> (32 - MATCH_HASH_BITS)) as usize
+    }
+
+    fn predict(&self, lpc: &[i32; LPC_ORDER]) -> Prediction {
+        let clamp = |v: i64| v.clamp(-VALUE_LIMIT << FRAC_BITS, VALUE_LIMIT << FRAC_BITS);
+        let base = clamp(self.history(1) << FRAC_BITS);
+
+        let linear = (2 * self.history(1) - self.history(2)) << FRAC_BITS;
+
+        let lpc_sum: i64 = lpc
+            .iter()
+            .enumerate()
+            .map(|(j, &c)| c as i64 * self.history(j + 1))
+            .sum();
+        let lpc_prediction = clamp(lpc_sum >> (LPC_SHIFT - FRAC_BITS));
+
+        let lms_sum: i64 = self
+            .lms_weights
+            .iter()
+            .zip(&self.lpc_residuals)
+            .map(|(&w, &e)| w as i64 * e as i64)
+            .sum();
+        let lms = lpc_prediction + (lms_sum >> (LMS_SHIFT - FRAC_BITS));
+
+        // Replay the step that followed the last occurrence of this waveform shape.
+        let position = self.match_table[self.context_hash()] as usize;
+        let matched = if position > 0 {
+            (self.history(1) + self.values[position] as i64 - self.values[position - 1] as i64)
+                << FRAC_BITS
+        } else {
+            lpc_prediction
+        };
+
+        let deltas = [linear, lpc_prediction, lms, matched].map(|p| clamp(clamp(p) - base));
+        let mixed_delta = deltas
+            .iter()
+            .zip(&self.mixer_weights)
+            .map(|(&d, &w)| d * w)
+            .sum::<i64>()
+            >> MIXER_SHIFT;
+        let mixed = clamp(base + mixed_delta);
+
+        Prediction {
+            deltas,
+            lpc: lpc_prediction,
+            mixed,
+            value: (mixed + (1 << (FRAC_BITS - 1))) >> FRAC_BITS,
+        }
+    }
+
+    fn update(&mut self, prediction: &Prediction, value: i32) {
+        let target = (value as i64) << FRAC_BITS;
+
+        // Normalized LMS step on the mixer weights.
+        let error = (target - prediction.mixed) as i128;
+        let norm = prediction
+            .deltas
+            .iter()
+            .map(|&d| (d * d) as i128)
+            .sum::<i128>()
+            + (1 << 16);
+        for (w, &d) in self.mixer_weights.iter_mut().zip(&prediction.deltas) {
+            let step = ((error * d as i128) << (MIXER_SHIFT - MIXER_RATE_SHIFT)) / norm;
+            *w = (*w + step as i64).clamp(-(1 << 20), 1 << 20);
+        }
+
+        // Sign-sign LMS on the LPC residual.
+        let lpc_residual = value as i64 - ((prediction.lpc + (1 << (FRAC_BITS - 1))) >> FRAC_BITS);
+        let lms_sum: i64 = self
+            .lms_weights
+            .iter()
+            .zip(&self.lpc_residuals)
+            .map(|(&w, &e)| w as i64 * e as i64)
+            .sum();
+        let lms_error = (lpc_residual << LMS_SHIFT) - lms_sum;
+        for (w, &e) in self.lms_weights.iter_mut().zip(&self.lpc_residuals) {
+            let direction = lms_error.signum() as i32 * e.signum();
+            *w = (*w + LMS_STEP * direction).clamp(-(1 << 20), 1 << 20);
+        }
+        self.lpc_residuals.rotate_right(1);
+        self.lpc_residuals[0] = lpc_residual.clamp(-VALUE_LIMIT, VALUE_LIMIT) as i32;
+
+        if self.values.len() >= 4 {
+            let hash = self.context_hash();
+            self.match_table[hash] = self.values.len() as u32;
+        }
+        self.values.push(value);
+    }
+}
+
+/// Adaptive binary models for residuals, conditioned on the recent residual
+/// magnitude and on the fractional part of the prediction.
+struct ResidualModel {
+    average: u64,
+    previous_negative: bool,
+    zero: Vec<BitModel>,
+    sign: Vec<BitModel>,
+    width: Vec<BitModel>,
+    mantissa_top: Vec<BitModel>,
+    mantissa_low: Vec<BitModel>,
+}
+
+impl ResidualModel {
+    fn new() -> Self {
+        ResidualModel {
+            average: 0,
+            previous_negative: false,
+            zero: vec![BitModel::default(); MAGNITUDE_CONTEXTS],
+            sign: vec![BitModel::default(); MAGNITUDE_CONTEXTS * 8],
+            width: vec![BitModel::default(); MAGNITUDE_CONTEXTS * MAX_WIDTH],
+            mantissa_top: vec![BitModel::default(); MAGNITUDE_CONTEXTS * MAX_WIDTH * 4],
+            mantissa_low: vec![BitModel::default(); MAX_WIDTH * MAX_WIDTH],
+        }
+    }
+
+    /// Residual models primed with the probabilities in `stats`.
+    fn with_stats(stats: &SharedStats) -> Self {
+        let mut model = ResidualModel::new();
+        for (bit, &p) in model.models_mut().zip(&stats.probabilities) {
+            *bit = BitModel::with_probability(((p as u32) << 8) | 0x80, SHARED_COUNT);
+        }
+        model
+    }
+
+    fn models(&self) -> impl Iterator<Item = &BitModel> {
+        self.zero
+            .iter()
+            .chain(&self.sign)
+            .chain(&self.width)
+            .chain(&self.mantissa_top)
+            .chain(&self.mantissa_low)
+    }
+
+    fn models_mut(&mut self) -> impl Iterator<Item = &mut BitModel> {
+        self.zero
+            .iter_mut()
+            .chain(&mut self.sign)
+            .chain(&mut self.width)
+            .chain(&mut self.mantissa_top)
+            .chain(&mut self.mantissa_low)
+    }
+
+    fn context(&self) -> usize {
+        bit_width(self.average >> 4).min(MAGNITUDE_CONTEXTS - 1)
+    }
+
+    fn sign_index(&self, context: usize, prediction: &Prediction) -> usize {
+        // Where the unrounded prediction fell relative to the rounded one hints at the sign.
+        let fraction = prediction.mixed - (prediction.value << FRAC_BITS) + (1 << (FRAC_BITS - 1));
+        let quarter = (fraction >> (FRAC_BITS - 2)).clamp(0, 3) as usize;
+        (context * 4 + quarter) * 2 + self.previous_negative as usize
+    }
+
+    fn update(&mut self, residual: i64) {
+        let magnitude = residual.unsigned_abs().min(1 << 24);
+        self.average = self.average + ((magnitude << 4) >> 3) - (self.average >> 3);
+        if residual != 0 {
+            self.previous_negative = residual < 0;
+        }
+    }
+
+    fn encode(&mut self, encoder: &mut ArithmeticEncoder, residual: i64, prediction: &Prediction) {
+        let context = self.context();
+        encoder.encode(&mut self.zero[context], residual == 0);
+        if residual != 0 {
+            let sign = self.sign_index(context, prediction);
+            encoder.encode(&mut self.sign[sign], residual < 0);
+
+            let magnitude = residual.unsigned_abs() - 1;
+            let width = bit_width(magnitude);
+            for k in 0..MAX_WIDTH {
+                let more = k < width;
+                encoder.encode(&mut self.width[context * MAX_WIDTH + k], more);
+                if !more {
+                    break;
+                }
+            }
+
+            let mut node = 1;
+            for bit in (0..width.saturating_sub(1)).rev() {
+                let one = (magnitude >> bit) & 1 == 1;
+                let model = if node < 4 {
+                    &mut self.mantissa_top[(context * MAX_WIDTH + width) * 4 + node]
+                } else {
+                    &mut self.mantissa_low[width * MAX_WIDTH + bit]
+                };
+                encoder.encode(model, one);
+                node = node * 2 + one as usize;
+            }
+        }
+        self.update(residual);
+    }
+
+    fn decode(&mut self, decoder: &mut ArithmeticDecoder, prediction: &Prediction) -> i64 {
+        let context = self.context();
+        let mut residual = 0;
+        if !decoder.decode(&mut self.zero[context]) {
+            let sign = self.sign_index(context, prediction);
+            let negative = decoder.decode(&mut self.sign[sign]);
+
+            let mut width = 0;
+            while width < MAX_WIDTH && decoder.decode(&mut self.width[context * MAX_WIDTH + width])
+            {
+                width += 1;
+            }
+            // The encoder never produces MAX_WIDTH; clamp so corrupt input stays in bounds.
+            let width = width.min(MAX_WIDTH - 1);
+
+            let mut magnitude: u64 = if width > 0 { 1 } else { 0 };
+            let mut node = 1;
+            for bit in (0..width.saturating_sub(1)).rev() {
+                let model = if node < 4 {
+                    &mut self.mantissa_top[(context * MAX_WIDTH + width) * 4 + node]
+                } else {
+                    &mut self.mantissa_low[width * MAX_WIDTH + bit]
+                };
+                let one = decoder.decode(model);
+                magnitude = (magnitude << 1) | one as u64;
+                node = node * 2 + one as usize;
+            }
+
+            residual = (magnitude + 1) as i64;
+            if negative {
+                residual = -residual;
+            }
+        }
+        self.update(residual);
+        residual
+    }
+}
+
+/// Number of binary models in a [`ResidualModel`], and so of probabilities
+/// in [`SharedStats`].
+const RESIDUAL_MODELS: usize =
+    MAGNITUDE_CONTEXTS * (1 + 8 + MAX_WIDTH + MAX_WIDTH * 4) + MAX_WIDTH * MAX_WIDTH;
+
+/// Serialized size of [`SharedStats`].
+const SHARED_STATS_BYTES: usize = RESIDUAL_MODELS + LPC_ORDER * 5 + 4;
+
+/// What recordings of one dataset have in common, learned once so that each
+/// file coded against it does not pay again to learn it: the starting
+/// probability of every residual model, the typical LPC coefficients,
+/// against which each block's coefficients are coded as small differences,
+/// and the most common value grid.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedStats {
+    /// One byte per model, the top of its 16-bit probability; models no
+    /// training recording reached stay at one half.
+    probabilities: Vec<u8>,
+    lpc_mean: [i32; LPC_ORDER],
+    /// Rice parameter of each coefficient's difference from the mean.
+    lpc_parameters: [u32; LPC_ORDER],
+    /// Step and offset of the grid most training channels sit on.
+    grid: (i32, i32),
+}
+
+impl SharedStats {
+    /// Learns from every channel of `recordings`: residual model
+    /// probabilities are averaged where the model was used, LPC coefficients
+    /// over every block.
+    pub fn train(recordings: &[(&[i16], &WavSpec)]) -> SharedStats {
+        let channels: Vec<ChannelSummary> = recordings
+            .par_iter()
+            .flat_map_iter(|&(samples, spec)| {
+                deinterleave(samples, spec.channels.max(1) as usize)
+                    .into_iter()
+                    .map(|channel| {
+                        encode_channel(&mut BitWriter::new(BitOrder::Msb), &channel, None)
+                    })
+            })
+            .collect();
+
+        let mut sums = vec![(0u64, 0u64); RESIDUAL_MODELS];
+        for channel in &channels {
+            for (sum, bit) in sums.iter_mut().zip(channel.residuals.models()) {
+                if bit.is_trained() {
+                    sum.0 += bit.probability() as u64;
+                    sum.1 += 1;
+                }
+            }
+        }
+        let probabilities = sums
+            .iter()
+            .map(|&(total, count)| match count {
+                0 => 0x80,
+                _ => ((total / count) >> 8) as u8,
+            })
+            .collect();
+
+        let blocks: Vec<&[i32; LPC_ORDER]> =
+            channels.iter().flat_map(|channel| &channel.lpc).collect();
+        let mut lpc_mean = [0; LPC_ORDER];
+        let mut lpc_parameters = [0; LPC_ORDER];
+        for j in 0..LPC_ORDER {
+            let total: i64 = blocks.iter().map(|c| c[j] as i64).sum();
+            lpc_mean[j] = (total / blocks.len().max(1) as i64) as i32;
+            let differences: Vec<u32> = blocks.iter().map(|c| zigzag(c[j] - lpc_mean[j])).collect();
+            lpc_parameters[j] = estimate_parameter(&differences);
+        }
+        // Most common grid, ties going to the smallest.
+        let mut grids = BTreeMap::new();
+        for channel in &channels {
+            *grids.entry(channel.grid).or_insert(0usize) += 1;
+        }
+        let grid = grids
+            .into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+            .map_or((1, 0), |(grid, _)| grid);
+        debug!(
+            "Trained shared statistics on {} channels, {} LPC blocks, grid {:?}",
+            channels.len(),
+            blocks.len(),
+            grid
+        );
+        SharedStats {
+            probabilities,
+            lpc_mean,
+            lpc_parameters,
+            grid,
+        }
+    }
+
+    /// Serializes the statistics: the model probabilities, then each mean
+    /// coefficient (32-bit big-endian), each Rice parameter (one byte) and
+    /// the grid step and offset (16 bits each).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.probabilities.clone();
+        for mean in self.lpc_mean {
+            bytes.extend_from_slice(&mean.to_be_bytes());
+        }
+        bytes.extend(self.lpc_parameters.iter().map(|&k| k as u8));
+        bytes.extend_from_slice(&(self.grid.0 as u16).to_be_bytes());
+        bytes.extend_from_slice(&(self.grid.1 as u16).to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<SharedStats, Box<dyn Error + Send + Sync>> {
+        if bytes.len() != SHARED_STATS_BYTES {
+            return Err(Box::from(format!(
+                "Shared statistics of {} bytes, expected {}",
+                bytes.len(),
+                SHARED_STATS_BYTES
+            )));
+        }
+        let (probabilities, lpc) = bytes.split_at(RESIDUAL_MODELS);
+        let (means, rest) = lpc.split_at(LPC_ORDER * 4);
+        let (parameters, grid) = rest.split_at(LPC_ORDER);
+        let mut lpc_mean = [0; LPC_ORDER];
+        for (mean, bytes) in lpc_mean.iter_mut().zip(means.chunks_exact(4)) {
+            *mean = i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+        let mut lpc_parameters = [0; LPC_ORDER];
+        for (k, &byte) in lpc_parameters.iter_mut().zip(parameters) {
+            if byte as u32 > MAX_PARAMETER {
+                return Err(Box::from(format!("Invalid Rice parameter {}", byte)));
+            }
+            *k = byte as u32;
+        }
+        let step = u16::from_be_bytes([grid[0], grid[1]]) as i32;
+        let offset = u16::from_be_bytes([grid[2], grid[3]]) as i32;
+        if step == 0 || offset >= step {
+            return Err(Box::from(format!(
+                "Invalid grid: step {}, offset {}",
+                step, offset
+            )));
+        }
+        Ok(SharedStats {
+            probabilities: probabilities.to_vec(),
+            lpc_mean,
+            lpc_parameters,
+            grid: (step, offset),
+        })
+    }
+
+    /// FNV-1a hash of [`SharedStats::to_bytes`], by which streams coded
+    /// against these statistics refer to them.
+    pub fn hash(&self) -> u64 {
+        self.to_bytes()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+                (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+}
+
+/// What [`SharedStats::train`] learns from one coded channel.
+struct ChannelSummary {
+    /// The residual models as they ended.
+    residuals: ResidualModel,
+    /// LPC coefficients of every block.
+    lpc: Vec<[i32; LPC_ORDER]>,
+    grid: (i32, i32),
+}
+
+/// Writes a channel's grid, which with [`SharedStats`] is a single bit when
+/// it is the shared one.
+fn write_grid(writer: &mut BitWriter, grid: (i32, i32), stats: Option<&SharedStats>) {
+    if let Some(stats) = stats {
+        writer.write_bits((grid == stats.grid) as u32, 1);
+        if grid == stats.grid {
+            return;
+        }
+    }
+    writer.write_bits(grid.0 as u32, 16);
+    writer.write_bits(grid.1 as u32, 16);
+}
+
+fn read_grid(
+    reader: &mut BitReader,
+    stats: Option<&SharedStats>,
+) -> Result<(i64, i64), Box<dyn Error + Send + Sync>> {
+    if let Some(stats) = stats {
+        if reader.read_bits(1)? == 1 {
+            return Ok((stats.grid.0 as i64, stats.grid.1 as i64));
+        }
+    }
+    Ok((reader.read_bits(16)? as i64, reader.read_bits(16)? as i64))
+}
+
+fn encode_channel(
+    writer: &mut BitWriter,
+    channel: &[i32],
+    stats: Option<&SharedStats>,
+) -> ChannelSummary {
+    let (step, offset) = detect_grid(channel);
+    write_grid(writer, (step, offset), stats);
+    let values: Vec<i32> = channel.iter().map(|&x| (x - offset) / step).collect();
+
+    let blocks: Vec<[i32; LPC_ORDER]> = values.chunks(BLOCK_SIZE).map(compute_lpc).collect();
+    for coefficients in &blocks {
+        write_lpc(writer, coefficients, stats);
+    }
+
+    let mut encoder = ArithmeticEncoder::new();
+    let mut model = ChannelModel::new(values.len());
+    let mut residuals = stats.map_or_else(ResidualModel::new, ResidualModel::with_stats);
+    for (block, coefficients) in values.chunks(BLOCK_SIZE).zip(&blocks) {
+        for &value in block {
+            let prediction = model.predict(coefficients);
+            residuals.encode(&mut encoder, value as i64 - prediction.value, &prediction);
+            model.update(&prediction, value);
+        }
+    }
+
+    let bytes = encoder.finish();
+    writer.write_bits(bytes.len() as u32, 32);
+    writer.write_bytes(&bytes);
+    ChannelSummary {
+        residuals,
+        lpc: blocks,
+        grid: (step, offset),
+    }
+}
+
+fn decode_channel(
+    reader: &mut BitReader,
+    frames: usize,
+    stats: Option<&SharedStats>,
+) -> Result<Vec<i32>, Box<dyn Error + Send + Sync>> {
+    let (step, offset) = read_grid(reader, stats)?;
+    let blocks = (0..frames.div_ceil(BLOCK_SIZE))
+        .map(|_| read_lpc(reader, stats))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let len = reader.read_bits(32)? as usize;
+    let mut decoder = ArithmeticDecoder::new(reader.read_bytes(len)?);
+    let mut model = ChannelModel::new(frames.min(len * 8));
+    let mut residuals = stats.map_or_else(ResidualModel::new, ResidualModel::with_stats);
+    for (block_index, coefficients) in blocks.iter().enumerate() {
+        let block_len = BLOCK_SIZE.min(frames - block_index * BLOCK_SIZE);
+        for _ in 0..block_len {
+            let prediction = model.predict(coefficients);
+            let residual = residuals.decode(&mut decoder, &prediction);
+            let value = (prediction.value + residual).clamp(-VALUE_LIMIT, VALUE_LIMIT) as i32;
+            model.update(&prediction, value);
+        }
+        decoder.check_overrun()?;
+    }
+
+    Ok(model
+        .values
+        .iter()
+        .map(|&v| (v as i64 * step + offset) as i32)
+        .collect())
+}
+
+/// Compresses samples with the context-mixing model: LPC, sign-LMS and
+/// waveform-match predictions mixed by online NLMS weights, with residuals
+/// binary arithmetic coded under adaptive contexts. Slow, aimed at archival ratio.
+pub fn compress_cm(
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    compress_cm_with(samples, spec, None)
+}
+
+/// [`compress_cm`] with the residual models, LPC coefficients and grids
+/// coded against `stats`. The output only decodes with the same statistics.
+pub fn compress_cm_with(
+    samples: &[i16],
+    spec: &WavSpec,
+    stats: Option<&SharedStats>,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into context-mixing format...");
+
+    let mut writer = BitWriter::with_capacity(BitOrder::Msb, samples.len());
+    write_spec_header(&mut writer, spec, samples.len());
+    for channel in deinterleave(samples, spec.channels as usize) {
+        encode_channel(&mut writer, &channel, stats);
+    }
+
+    debug!("Finished compressing data into context-mixing format");
+    Ok(writer.finish())
+}
+
+/// Estimated encoder cost of [`compress_cm`], with [`COST_CODED_BITS`]
+/// arithmetic-coded decisions per residual, each a range split and a model
+/// update with one division.
+pub fn cost_cm() -> CostModel {
+    let lpc = LPC_ORDER as f64;
+    let lms = LMS_ORDER as f64;
+    let inputs = INPUTS as f64;
+    let multiplies = 4.0 + (lpc + 1.0) // window and autocorrelation
+        + 3.0 // grid detection and mapping
+        + lpc + 2.0 * lms // LPC and LMS predictions, LMS sum again on update
+        + 2.0 * 3.0 // waveform hash, on predict and update
+        + inputs * 4.0 // mixing, its input energy and NLMS step
+        + 2.0 * lms // sign-sign LMS update
+        + 2.0 * COST_CODED_BITS;
+    let additions = 3.0 + (lpc + 1.0)
+        + 4.0
+        + lpc + 2.0 * lms
+        + 2.0 * 8.0 + 5.0 // hashes and the match lookup
+        + inputs * 5.0 + inputs * 5.0 + 8.0 // clamped deltas, mixing and its update
+        + 6.0 * lms // sign-sign update and history shift
+        + 11.0 * COST_CODED_BITS;
+    CostModel {
+        multiplies,
+        additions,
+        // The current channel's match table and residual models, and one
+        // block's windowed signal.
+        state_bytes: (4 << MATCH_HASH_BITS)
+            + RESIDUAL_MODELS * std::mem::size_of::<BitModel>()
+            + BLOCK_SIZE * 8,
+        // Deinterleaved channel, grid indices and the model's own history.
+        buffer_bytes_per_sample: 4.0 + 4.0 + 4.0,
+    }
+}
+
+pub fn decompress_cm(buffer: &[u8]) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    decompress_cm_with(buffer, None)
+}
+
+/// Inverse of [`compress_cm_with`].
+pub fn decompress_cm_with(
+    buffer: &[u8],
+    stats: Option<&SharedStats>,
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from context-mixing format...");
+
+    let mut reader = BitReader::new(buffer, BitOrder::Msb);
+    let (spec, sample_count) = read_spec_header(&mut reader)?;
+    let frames = sample_count / spec.channels as usize;
+
+    let mut channels = Vec::with_capacity(spec.channels as usize);
+    for _ in 0..spec.channels {
+        channels.push(decode_channel(&mut reader, frames, stats)?);
+    }
+
+    debug!("Finished decompressing data from context-mixing format");
+    Ok((interleave(&channels), spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codec_roundtrip() {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        // A quantized oscillation with a repeating spike-like transient.
+        let samples: Vec<i16> = (0..20_000)
+            .map(|i: i32| {
+                let spike = if i % 700 < 12 {
+                    [0, -9, -20, -14, 3, 8, 6, 4, 2, 1, 0, 0][(i % 700) as usize]
+                } else {
+                    0
+                };
+                (((i * 13) % 41 - 20 + spike) * 64 + 31) as i16
+            })
+            .collect();
+
+        let compressed = compress_cm(&samples, &spec).unwrap();
+        let (decoded, decoded_spec) = decompress_cm(&compressed).unwrap();
+        assert_eq!(decoded, samples);
+        assert_eq!(decoded_spec, spec);
+        assert!(compressed.len() < samples.len());
+    }
+
+    #[test]
+    fn shared_stats_roundtrip() {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let recordings: Vec<Vec<i16>> = (1..4)
+            .map(|seed: i32| {
+                (0..12_000)
+                    .map(|i: i32| (((i * (seed + 12)) % 37 - 18 + (i / 50) % 3) * 64) as i16)
+                    .collect()
+            })
+            .collect();
+        let training: Vec<(&[i16], &WavSpec)> =
+            recordings.iter().map(|r| (r.as_slice(), &spec)).collect();
+        let stats = SharedStats::train(&training);
+        let bytes = stats.to_bytes();
+        assert_eq!(SharedStats::from_bytes(&bytes).unwrap(), stats);
+        assert!(SharedStats::from_bytes(&bytes[1..]).is_err());
+
+        for samples in &recordings {
+            let compressed = compress_cm_with(samples, &spec, Some(&stats)).unwrap();
+            let (decoded, decoded_spec) = decompress_cm_with(&compressed, Some(&stats)).unwrap();
+            assert_eq!(decoded, *samples);
+            assert_eq!(decoded_spec, spec);
+        }
+    }
+}
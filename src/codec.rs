@@ -0,0 +1,563 @@
+use crate::ans::{
+    compress_ans, compress_ans_adaptive, cost_ans, cost_ans_adaptive, decompress_ans,
+    decompress_ans_adaptive,
+};
+use crate::bitpack::{BitOrder, BitReader, BitWriter};
+use crate::brotli_sb::{compress_brotli, cost_brotli, decompress_brotli};
+use crate::cm::{compress_cm_with, cost_cm, decompress_cm_with, SharedStats};
+use crate::cost::CostModel;
+use crate::embedded::{compress_embedded, cost_embedded, decompress_embedded};
+use crate::flac::{compress_flac, cost_flac, decompress_flac};
+use crate::lms::{
+    compress_lms_ans, compress_lms_rice, cost_lms_ans, cost_lms_rice, decompress_lms_ans,
+    decompress_lms_rice,
+};
+use crate::model::split_model_reference;
+use crate::realtime::{
+    compress_realtime, cost_realtime, decompress_realtime, DEFAULT_PACKET_FRAMES,
+};
+use crate::recording::split_recording;
+use crate::rice::{compress_rice, cost_rice, decompress_rice};
+use crate::spikes::{compress_spike, cost_spike, decompress_spike};
+use crate::wav::{check_recording_length, MAX_RECORDING_SAMPLES, MAX_SAMPLES};
+use crate::zlib::{compress_zlib, cost_zlib, decompress_zlib};
+use crate::zstd::{compress_zstd, cost_zstd, decompress_zstd};
+use hound::WavSpec;
+use rayon::prelude::*;
+use std::error::Error;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Frame magic of zstd output. Plain zstd streams are written untagged so that
+/// files produced before codec selection existed still decode.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xB5, 0x2F, 0xFD];
+
+/// Tag of the blocked container written by [`Codec::compress_blocked`].
+const BLOCKED_TAG: u8 = 0xFF;
+
+/// FLAC output per sample assumed when costing the Brotli pass of
+/// `flac-brotli`: about a quarter of the 16-bit input.
+const FLAC_BYTES_PER_SAMPLE: f64 = 0.5;
+
+/// The compression schemes `compress`, `decompress` and `process_batch` can use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Codec {
+    Zstd,
+    Zlib,
+    Flac,
+    FlacBrotli,
+    Rice,
+    Ans,
+    AnsAdaptive,
+    Cm,
+    LmsRice,
+    LmsAns,
+    Spike,
+    Realtime,
+    Embedded,
+}
+
+impl Codec {
+    pub const ALL: &'static [Codec] = &[
+        Codec::Zstd,
+        Codec::Zlib,
+        Codec::Flac,
+        Codec::FlacBrotli,
+        Codec::Rice,
+        Codec::Ans,
+        Codec::AnsAdaptive,
+        Codec::Cm,
+        Codec::LmsRice,
+        Codec::LmsAns,
+        Codec::Spike,
+        Codec::Realtime,
+        Codec::Embedded,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+            Codec::Zlib => "zlib",
+            Codec::Flac => "flac",
+            Codec::FlacBrotli => "flac-brotli",
+            Codec::Rice => "rice",
+            Codec::Ans => "ans",
+            Codec::AnsAdaptive => "ans-adaptive",
+            Codec::Cm => "cm",
+            Codec::LmsRice => "lms-rice",
+            Codec::LmsAns => "lms-ans",
+            Codec::Spike => "spike",
+            Codec::Realtime => "realtime",
+            Codec::Embedded => "embedded",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Codec, Box<dyn Error + Send + Sync>> {
+        Codec::ALL
+            .iter()
+            .copied()
+            .find(|codec| codec.name() == name)
+            .ok_or_else(|| {
+                let names: Vec<_> = Codec::ALL.iter().map(|codec| codec.name()).collect();
+                Box::from(format!(
+                    "Unknown codec: {} (expected one of {})",
+                    name,
+                    names.join(", ")
+                ))
+            })
+    }
+
+    /// Parses a comma separated codec list; `all` selects every codec.
+    pub fn parse_list(list: &str) -> Result<Vec<Codec>, Box<dyn Error + Send + Sync>> {
+        if list == "all" {
+            return Ok(Codec::ALL.to_vec());
+        }
+        list.split(',').map(Codec::from_name).collect()
+    }
+
+    /// Tag byte written in front of the codec's payload (zstd has none).
+    fn tag(self) -> Option<u8> {
+        match self {
+            Codec::Zstd => None,
+            Codec::Zlib => Some(1),
+            Codec::Flac => Some(2),
+            Codec::FlacBrotli => Some(3),
+            Codec::Rice => Some(4),
+            Codec::Ans => Some(5),
+            Codec::AnsAdaptive => Some(6),
+            Codec::Cm => Some(7),
+            Codec::LmsRice => Some(8),
+            Codec::LmsAns => Some(9),
+            Codec::Spike => Some(10),
+            Codec::Realtime => Some(11),
+            Codec::Embedded => Some(12),
+        }
+    }
+
+    /// Identifies the codec that produced `buffer`.
+    pub fn detect(buffer: &[u8]) -> Result<Codec, Box<dyn Error + Send + Sync>> {
+        if buffer.starts_with(&ZSTD_MAGIC) {
+            return Ok(Codec::Zstd);
+        }
+        let tag = *buffer.first().ok_or("Compressed buffer is empty")?;
+        Codec::ALL
+            .iter()
+            .copied()
+            .find(|codec| codec.tag() == Some(tag))
+            .ok_or_else(|| Box::from(format!("Unknown codec tag: {}", tag)))
+    }
+
+    /// Estimated encoder cost of this codec on recordings shaped like `spec`;
+    /// see [`CostModel`] for what is counted.
+    pub fn cost(self, spec: &WavSpec) -> CostModel {
+        match self {
+            Codec::Zstd => cost_zstd(),
+            Codec::Zlib => cost_zlib(),
+            Codec::Flac => cost_flac(spec),
+            Codec::FlacBrotli => cost_flac(spec) + cost_brotli(FLAC_BYTES_PER_SAMPLE),
+            Codec::Rice => cost_rice(),
+            Codec::Ans => cost_ans(),
+            Codec::AnsAdaptive => cost_ans_adaptive(),
+            Codec::Cm => cost_cm(),
+            Codec::LmsRice => cost_lms_rice(),
+            Codec::LmsAns => cost_lms_ans(),
+            Codec::Spike => cost_spike(spec),
+            Codec::Realtime => cost_realtime(spec, DEFAULT_PACKET_FRAMES),
+            Codec::Embedded => cost_embedded(spec),
+        }
+    }
+
+    /// Whether [`Codec::compress_with`] makes use of shared statistics.
+    pub fn uses_shared_stats(self) -> bool {
+        self == Codec::Cm
+    }
+
+    /// Compresses samples, prefixing the payload with this codec's tag.
+    /// Recordings longer than [`MAX_SAMPLES`] go through the blocked
+    /// container, as with [`Codec::compress_blocked`].
+    pub fn compress(
+        self,
+        samples: &[i16],
+        spec: &WavSpec,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.compress_with(samples, spec, None)
+    }
+
+    /// [`Codec::compress`] against statistics shared by a set of recordings.
+    /// Only `cm` makes use of them; its output then only decodes with the
+    /// same statistics, so mark it with [`crate::model::reference_model`]
+    /// for [`decompress_with`].
+    pub fn compress_with(
+        self,
+        samples: &[i16],
+        spec: &WavSpec,
+        stats: Option<&SharedStats>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.compress_within(samples, spec, stats, MAX_SAMPLES)
+    }
+
+    /// [`Codec::compress_with`] for streams of at most `stream_limit`
+    /// samples. Longer recordings are split across the blocked container in
+    /// blocks of a quarter of that.
+    fn compress_within(
+        self,
+        samples: &[i16],
+        spec: &WavSpec,
+        stats: Option<&SharedStats>,
+        stream_limit: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        if samples.len() > stream_limit {
+            let frames = (stream_limit / 4 / spec.channels.max(1) as usize).max(1);
+            return self.compress_blocked_with(samples, spec, frames, stats);
+        }
+        let payload = match self {
+            Codec::Zstd => return compress_zstd(samples, spec),
+            Codec::Zlib => compress_zlib(samples, spec)?,
+            Codec::Flac => compress_flac(samples, spec)?,
+            Codec::FlacBrotli => compress_brotli(&compress_flac(samples, spec)?)?,
+            Codec::Rice => compress_rice(samples, spec)?,
+            Codec::Ans => compress_ans(samples, spec)?,
+            Codec::AnsAdaptive => compress_ans_adaptive(samples, spec)?,
+            Codec::Cm => compress_cm_with(samples, spec, stats)?,
+            Codec::LmsRice => compress_lms_rice(samples, spec)?,
+            Codec::LmsAns => compress_lms_ans(samples, spec)?,
+            Codec::Spike => compress_spike(samples, spec)?,
+            Codec::Realtime => compress_realtime(samples, spec, DEFAULT_PACKET_FRAMES)?,
+            Codec::Embedded => compress_embedded(samples, spec)?,
+        };
+
+        let mut output = Vec::with_capacity(payload.len() + 1);
+        output.extend(self.tag());
+        output.extend_from_slice(&payload);
+        Ok(output)
+    }
+
+    /// Splits the recording into blocks of `block_frames` frames, compresses
+    /// them independently across the rayon pool and concatenates the results
+    /// behind a table of block lengths. Blocks never share state, so the
+    /// output does not depend on the number of threads.
+    pub fn compress_blocked(
+        self,
+        samples: &[i16],
+        spec: &WavSpec,
+        block_frames: usize,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        self.compress_blocked_with(samples, spec, block_frames, None)
+    }
+
+    /// [`Codec::compress_blocked`] with every block coded against `stats`,
+    /// as in [`Codec::compress_with`].
+    pub fn compress_blocked_with(
+        self,
+        samples: &[i16],
+        spec: &WavSpec,
+        block_frames: usize,
+        stats: Option<&SharedStats>,
+    ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+        if block_frames == 0 {
+            return Err(Box::from("Block size must be at least one frame"));
+        }
+        check_recording_length(samples.len())?;
+        let block_len = block_frames.saturating_mul(spec.channels.max(1) as usize);
+        if block_len.min(samples.len()) > MAX_SAMPLES {
+            return Err(Box::from(format!(
+                "Blocks of {} frames exceed the limit of {} samples per block",
+                block_frames, MAX_SAMPLES
+            )));
+        }
+        let mut chunks: Vec<&[i16]> = samples.chunks(block_len).collect();
+        if chunks.is_empty() {
+            // An empty block still carries the spec.
+            chunks.push(&[]);
+        }
+
+        let blocks = chunks
+            .par_iter()
+            .map(|chunk| self.compress_with(chunk, spec, stats))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let total: usize = blocks.iter().map(Vec::len).sum();
+        let mut writer = BitWriter::with_capacity(BitOrder::Msb, total + 4 * blocks.len() + 5);
+        writer.write_bits(BLOCKED_TAG as u32, 8);
+        writer.write_bits(blocks.len() as u32, 32);
+        for block in &blocks {
+            writer.write_bits(block.len() as u32, 32);
+        }
+        for block in &blocks {
+            writer.write_bytes(block);
+        }
+        Ok(writer.finish())
+    }
+}
+
+/// Compresses with the `realtime` codec using packets of `packet_frames`
+/// frames rather than the default, tagged like [`Codec::compress`] output.
+/// The stream is never blocked, so it holds at most [`MAX_SAMPLES`].
+pub fn compress_realtime_packets(
+    samples: &[i16],
+    spec: &WavSpec,
+    packet_frames: usize,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    if samples.len() > MAX_SAMPLES {
+        return Err(Box::from(format!(
+            "Recording of {} samples exceeds the limit of {} for one realtime stream",
+            samples.len(),
+            MAX_SAMPLES
+        )));
+    }
+    let payload = compress_realtime(samples, spec, packet_frames)?;
+    let mut output = Vec::with_capacity(payload.len() + 1);
+    output.extend(Codec::Realtime.tag());
+    output.extend_from_slice(&payload);
+    Ok(output)
+}
+
+/// Decompresses a buffer produced by [`Codec::compress`] or
+/// [`Codec::compress_blocked`] with any codec. For a recording wrapped by
+/// [`crate::recording::wrap_recording`] this returns its samples; see
+/// [`crate::recording::decompress_recording`] for the rest of the file.
+pub fn decompress(buffer: &[u8]) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    decompress_with(buffer, None)
+}
+
+/// Decompresses a stream coded against shared statistics, which
+/// [`crate::model::reference_model`] marks with the hash of the statistics
+/// it needs; `stats` must be those. Unmarked streams ignore `stats` and
+/// decode as with [`decompress`].
+pub fn decompress_with(
+    buffer: &[u8],
+    stats: Option<&SharedStats>,
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    let (stream, stats) = codec_stream(buffer, stats)?;
+    if stream.first() == Some(&BLOCKED_TAG) {
+        return decompress_blocked(&stream[1..], stats);
+    }
+    decompress_single(stream, stats)
+}
+
+/// Decodes like [`decompress_with`], but hands the samples to `sink` in
+/// order a block at a time instead of collecting them, so a blocked stream
+/// is never in memory whole. Blocks are decoded a thread pool's worth at a
+/// time; an unblocked stream arrives as a single block.
+pub fn decompress_blocks_with<F>(
+    buffer: &[u8],
+    stats: Option<&SharedStats>,
+    mut sink: F,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    F: FnMut(&[i16], &WavSpec) -> Result<(), Box<dyn Error + Send + Sync>>,
+{
+    let (stream, stats) = codec_stream(buffer, stats)?;
+    if stream.first() != Some(&BLOCKED_TAG) {
+        let (samples, spec) = decompress_single(stream, stats)?;
+        return sink(&samples, &spec);
+    }
+
+    let blocks = split_blocks(&stream[1..])?;
+    let mut first_spec = None;
+    let mut left = MAX_RECORDING_SAMPLES;
+    for batch in blocks.chunks(rayon::current_num_threads().max(1)) {
+        let decoded = batch
+            .par_iter()
+            .map(|block| decompress_single(block, stats))
+            .collect::<Result<Vec<_>, _>>()?;
+        for (samples, spec) in decoded {
+            if *first_spec.get_or_insert(spec) != spec {
+                return Err(Box::from("Blocks of one stream have different specs"));
+            }
+            left = left.checked_sub(samples.len()).ok_or_else(|| {
+                format!(
+                    "Blocked stream exceeds the limit of {} samples",
+                    MAX_RECORDING_SAMPLES
+                )
+            })?;
+            sink(&samples, &spec)?;
+        }
+    }
+    Ok(())
+}
+
+/// A codec stream and the statistics it was coded against.
+type CodecStream<'a, 'b> = (&'a [u8], Option<&'b SharedStats>);
+
+/// The codec stream inside `buffer`, past any recording wrapper and model
+/// reference, and the statistics it was coded against.
+fn codec_stream<'a, 'b>(
+    buffer: &'a [u8],
+    stats: Option<&'b SharedStats>,
+) -> Result<CodecStream<'a, 'b>, Box<dyn Error + Send + Sync>> {
+    let stream = match split_recording(buffer)? {
+        Some((_, _, stream)) => stream,
+        None => buffer,
+    };
+    match (split_model_reference(stream)?, stats) {
+        (None, _) => Ok((stream, None)),
+        (Some((hash, inner)), Some(stats)) if stats.hash() == hash => Ok((inner, Some(stats))),
+        (Some((hash, _)), Some(stats)) => Err(Box::from(format!(
+            "Stream was coded against model {:016x}, not {:016x}",
+            hash,
+            stats.hash()
+        ))),
+        (Some((hash, _)), None) => Err(Box::from(format!(
+            "Stream was coded against model {:016x}; pass the model file",
+            hash
+        ))),
+    }
+}
+
+/// The blocks of a blocked container, after its tag.
+fn split_blocks(buffer: &[u8]) -> Result<Vec<&[u8]>, Box<dyn Error + Send + Sync>> {
+    let mut reader = BitReader::new(buffer, BitOrder::Msb);
+    let count = reader.read_bits(32)? as usize;
+    if count == 0 || count as u64 * 32 > reader.bits_remaining() {
+        return Err(Box::from(format!("Invalid block count {}", count)));
+    }
+    let lengths = (0..count)
+        .map(|_| Ok(reader.read_bits(32)? as usize))
+        .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+    lengths.iter().map(|&len| reader.read_bytes(len)).collect()
+}
+
+fn decompress_blocked(
+    buffer: &[u8],
+    stats: Option<&SharedStats>,
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    let blocks = split_blocks(buffer)?;
+
+    // Every block is bounded on its own; the shared budget bounds their sum.
+    let budget = AtomicUsize::new(MAX_RECORDING_SAMPLES);
+    let decoded = blocks
+        .par_iter()
+        .map(|block| {
+            let (samples, spec) = decompress_single(block, stats)?;
+            budget
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |left| {
+                    left.checked_sub(samples.len())
+                })
+                .map_err(|_| {
+                    format!(
+                        "Blocked stream exceeds the limit of {} samples",
+                        MAX_RECORDING_SAMPLES
+                    )
+                })?;
+            Ok((samples, spec))
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+
+    let spec = decoded[0].1;
+    let mut samples = Vec::with_capacity(decoded.iter().map(|(s, _)| s.len()).sum());
+    for (block, block_spec) in decoded {
+        if block_spec != spec {
+            return Err(Box::from("Blocks of one stream have different specs"));
+        }
+        samples.extend(block);
+    }
+    Ok((samples, spec))
+}
+
+fn decompress_single(
+    buffer: &[u8],
+    stats: Option<&SharedStats>,
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    // Every tagged codec's payload starts after its one-byte tag.
+    match Codec::detect(buffer)? {
+        Codec::Zstd => decompress_zstd(buffer),
+        Codec::Zlib => decompress_zlib(&buffer[1..]),
+        Codec::Flac => decompress_flac(&buffer[1..]),
+        Codec::FlacBrotli => decompress_flac(&decompress_brotli(&buffer[1..])?),
+        Codec::Rice => decompress_rice(&buffer[1..]),
+        Codec::Ans => decompress_ans(&buffer[1..]),
+        Codec::AnsAdaptive => decompress_ans_adaptive(&buffer[1..]),
+        Codec::Cm => decompress_cm_with(&buffer[1..], stats),
+        Codec::LmsRice => decompress_lms_rice(&buffer[1..]),
+        Codec::LmsAns => decompress_lms_ans(&buffer[1..]),
+        Codec::Spike => decompress_spike(&buffer[1..]),
+        Codec::Realtime => decompress_realtime(&buffer[1..]),
+        Codec::Embedded => decompress_embedded(&buffer[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocked_output_independent_of_thread_count() {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let samples: Vec<i16> = (0..50_000)
+            .map(|i: i32| ((i * 7919) % 2001 - 1000) as i16)
+            .collect();
+
+        let encode = |threads| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| Codec::Rice.compress_blocked(&samples, &spec, 4096))
+                .unwrap()
+        };
+        let single = encode(1);
+        assert_eq!(single, encode(4));
+        assert_eq!(decompress(&single).unwrap(), (samples.clone(), spec));
+
+        let mut blocks = Vec::new();
+        decompress_blocks_with(&single, None, |block, block_spec| {
+            assert_eq!(*block_spec, spec);
+            blocks.push(block.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(blocks.len(), samples.len().div_ceil(2 * 4096));
+        assert_eq!(blocks.concat(), samples);
+
+        let empty = Codec::Zstd.compress_blocked(&[], &spec, 4096).unwrap();
+        assert_eq!(decompress(&empty).unwrap(), (vec![], spec));
+    }
+
+    #[test]
+    fn long_recordings_are_split_into_blocks() {
+        let spec = WavSpec {
+            channels: 3,
+            sample_rate: 30000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let samples: Vec<i16> = (0..3000)
+            .map(|i: i32| ((i * 7919) % 2001 - 1000) as i16)
+            .collect();
+        for codec in [Codec::Zstd, Codec::Rice, Codec::Cm] {
+            let whole = codec.compress_within(&samples, &spec, None, 3000).unwrap();
+            assert_ne!(whole[0], BLOCKED_TAG);
+            let split = codec.compress_within(&samples, &spec, None, 2999).unwrap();
+            assert_eq!(split[0], BLOCKED_TAG);
+            // Blocks of 249 frames, a quarter of the limit.
+            assert_eq!(split[1..5], 5u32.to_be_bytes());
+            assert_eq!(decompress(&split).unwrap(), (samples.clone(), spec));
+        }
+    }
+
+    #[test]
+    fn every_codec_has_a_cost() {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        for &codec in Codec::ALL {
+            let cost = codec.cost(&spec);
+            assert!(cost.operations() > 0.0, "{}", codec.name());
+            assert!(cost.peak_memory(1000) > 0, "{}", codec.name());
+        }
+        // The streaming encoders' memory does not grow with the recording.
+        for codec in [Codec::Realtime, Codec::Embedded] {
+            let cost = codec.cost(&spec);
+            assert!(cost.state_bytes < 64 << 10);
+        }
+        assert_eq!(Codec::Realtime.cost(&spec).buffer_bytes_per_sample, 0.0);
+    }
+}
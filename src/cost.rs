@@ -0,0 +1,117 @@
+use std::fmt;
+use std::ops::Add;
+
+/// Estimated encoder cost of a codec, for choosing one under a power budget
+/// rather than on ratio alone.
+///
+/// Operation counts are per input sample and come from the hot loops of the
+/// encoder: multiplications include divisions and remainders, other
+/// operations are additions, subtractions, comparisons, shifts and bit logic.
+/// Per-block work such as table builds is amortized over the block. The
+/// third-party encoders are estimated from their settings in this crate and
+/// are rougher than the in-crate ones. Memory excludes the input samples and
+/// the compressed output.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CostModel {
+    /// Multiplications and divisions per sample.
+    pub multiplies: f64,
+    /// Other arithmetic and logic operations per sample.
+    pub additions: f64,
+    /// Working memory that does not depend on the input length, in bytes.
+    pub state_bytes: usize,
+    /// Working memory that grows with the input, in bytes per sample.
+    pub buffer_bytes_per_sample: f64,
+}
+
+impl CostModel {
+    /// A stage that only holds a copy of the input, `bytes_per_sample` wide.
+    pub fn buffer(bytes_per_sample: f64) -> CostModel {
+        CostModel {
+            buffer_bytes_per_sample: bytes_per_sample,
+            ..CostModel::default()
+        }
+    }
+
+    /// Operations per sample of either kind.
+    pub fn operations(&self) -> f64 {
+        self.multiplies + self.additions
+    }
+
+    /// Peak working memory when encoding `samples` samples, in bytes.
+    pub fn peak_memory(&self, samples: usize) -> usize {
+        self.state_bytes + (self.buffer_bytes_per_sample * samples as f64).ceil() as usize
+    }
+}
+
+/// Cost of running two stages on every sample, one after the other.
+impl Add for CostModel {
+    type Output = CostModel;
+
+    fn add(self, other: CostModel) -> CostModel {
+        CostModel {
+            multiplies: self.multiplies + other.multiplies,
+            additions: self.additions + other.additions,
+            state_bytes: self.state_bytes + other.state_bytes,
+            buffer_bytes_per_sample: self.buffer_bytes_per_sample + other.buffer_bytes_per_sample,
+        }
+    }
+}
+
+impl fmt::Display for CostModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:.0} mul + {:.0} ops per sample, {:.1} KiB state + {:.1} B/sample",
+            self.multiplies,
+            self.additions,
+            self.state_bytes as f64 / 1024.0,
+            self.buffer_bytes_per_sample
+        )
+    }
+}
+
+/// Marks the points of `(ratio, operations)` pairs that no other point beats:
+/// nothing else compresses at least as well for at most the same operations
+/// while being strictly better in one of the two.
+pub fn pareto_front(points: &[(f64, f64)]) -> Vec<bool> {
+    points
+        .iter()
+        .map(|&(ratio, operations)| {
+            !points.iter().any(|&(other_ratio, other_operations)| {
+                other_ratio >= ratio
+                    && other_operations <= operations
+                    && (other_ratio > ratio || other_operations < operations)
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pareto_front_drops_dominated_points() {
+        let points = [
+            (2.0, 10.0),
+            (3.0, 100.0),
+            (2.5, 200.0),
+            (3.0, 100.0),
+            (1.0, 5.0),
+        ];
+        assert_eq!(pareto_front(&points), [true, true, false, true, true]);
+    }
+
+    #[test]
+    fn stages_add_up() {
+        let stage = CostModel {
+            multiplies: 2.0,
+            additions: 3.0,
+            state_bytes: 100,
+            buffer_bytes_per_sample: 4.0,
+        };
+        let both = stage + stage;
+        assert_eq!(both.operations(), 10.0);
+        assert_eq!(both.peak_memory(10), 280);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::bitpack::{BitOrder, BitReader};
+use crate::cost::CostModel;
+use crate::lms::{cascade_cost, LmsKind};
+use crate::predictor::detect_grid;
+use crate::rice::read_value;
+use crate::wav::MAX_SAMPLES;
+use hound::{SampleFormat, WavSpec};
+use smallbrain_core::predictor::{NLMS_ORDER, SIGN_ORDER};
+use smallbrain_core::{max_chunk_len, ChannelState, Config, Encoder, HEADER_LEN};
+use std::error::Error;
+use tracing::debug;
+
+/// Frames per chunk when encoding on the host: about what a device with a
+/// few kilobytes of RAM per channel would buffer.
+pub const CHUNK_FRAMES: usize = 256;
+
+/// Compresses samples with the `no_std` encoder core, chunk by chunk through
+/// a fixed scratch buffer the way a device would.
+pub fn compress_embedded(
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into embedded format...");
+
+    let values: Vec<i32> = samples.iter().map(|&s| s as i32).collect();
+    let (step, offset) = detect_grid(&values);
+    let config = Config {
+        sample_rate: spec.sample_rate,
+        channels: spec.channels,
+        bits_per_sample: spec.bits_per_sample,
+        step: step as u16,
+        offset: offset as u16,
+    };
+    let mut states = vec![ChannelState::new(); spec.channels as usize];
+    let mut encoder = Encoder::new(config, &mut states).map_err(|e| e.to_string())?;
+
+    let mut output = vec![0; HEADER_LEN];
+    encoder
+        .write_header(&mut output)
+        .map_err(|e| e.to_string())?;
+    let mut scratch = vec![0; max_chunk_len(CHUNK_FRAMES, spec.channels as usize)];
+    for chunk in samples.chunks(CHUNK_FRAMES * spec.channels as usize) {
+        let written = encoder
+            .encode_chunk(chunk, &mut scratch)
+            .map_err(|e| e.to_string())?;
+        output.extend_from_slice(&scratch[..written]);
+    }
+
+    debug!("Finished compressing data into embedded format");
+    Ok(output)
+}
+
+/// Estimated encoder cost of [`compress_embedded`]. The core's state is the
+/// [`ChannelState`]s and one scratch chunk; the only buffer is the host's
+/// grid detection pass, which a device given its grid does not need.
+pub fn cost_embedded(spec: &WavSpec) -> CostModel {
+    let channels = spec.channels as usize;
+    let taps = (NLMS_ORDER + SIGN_ORDER) as f64;
+    let cascade = cascade_cost(&[
+        (LmsKind::Normalized, NLMS_ORDER),
+        (LmsKind::SignSign, SIGN_ORDER),
+    ]);
+    let coder = CostModel {
+        // The prediction coded against, the grid index (checked, then
+        // computed again) and the grid's gcd.
+        multiplies: taps + 2.0 + 2.0,
+        // The running-mean parameter search and update, zigzag and writing.
+        additions: taps + 4.0 + 3.0 + 12.0 + 3.0 + 2.0 + 6.0,
+        state_bytes: 0,
+        buffer_bytes_per_sample: 4.0,
+    };
+    CostModel {
+        state_bytes: channels * std::mem::size_of::<ChannelState>()
+            + max_chunk_len(CHUNK_FRAMES, channels),
+        ..cascade + coder
+    }
+}
+
+/// Decodes the output of the `smallbrain-core` encoder.
+pub fn decompress_embedded(
+    buffer: &[u8],
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from embedded format...");
+
+    let config = Config::from_bytes(buffer).map_err(|e| format!("Invalid header: {}", e))?;
+    if config.bits_per_sample == 0 || config.bits_per_sample > 16 {
+        return Err(Box::from(format!(
+            "Invalid header: unsupported {} bits per sample",
+            config.bits_per_sample
+        )));
+    }
+    let spec = WavSpec {
+        channels: config.channels,
+        sample_rate: config.sample_rate,
+        bits_per_sample: config.bits_per_sample,
+        sample_format: SampleFormat::Int,
+    };
+    let channels = config.channels as usize;
+
+    let mut reader = BitReader::new(&buffer[HEADER_LEN..], BitOrder::Msb);
+    let mut states = vec![ChannelState::new(); channels];
+    let mut samples = Vec::new();
+    while reader.bits_remaining() > 0 {
+        let frames = reader.read_bits(16)? as usize;
+        if frames == 0 {
+            return Err(Box::from("Invalid chunk of 0 frames"));
+        }
+        reader.check_count(frames * channels, "samples")?;
+        if samples.len() + frames * channels > MAX_SAMPLES {
+            return Err(Box::from(format!(
+                "Stream exceeds the limit of {} samples",
+                MAX_SAMPLES
+            )));
+        }
+        samples.reserve(frames * channels);
+        for _ in 0..frames {
+            for state in states.iter_mut() {
+                let value = read_value(&mut reader, state.parameter())?;
+                let index = state.decode(value) as i64;
+                let sample = config.offset as i64 + index * config.step as i64;
+                samples.push(
+                    i16::try_from(sample)
+                        .map_err(|_| format!("Decoded sample {} is out of range", sample))?,
+                );
+            }
+        }
+        reader.align();
+    }
+
+    debug!("Finished decompressing data from embedded format");
+    Ok((samples, spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{generate, SynthConfig};
+
+    #[test]
+    fn roundtrips_core_output() {
+        let (samples, spec) = generate(&SynthConfig {
+            channels: 3,
+            frames: 3000,
+            ..SynthConfig::default()
+        });
+        let compressed = compress_embedded(&samples, &spec).unwrap();
+        assert_eq!(&compressed[8..12], &[0, 64, 0, 31]);
+        assert!(compressed.len() < samples.len());
+        assert_eq!(decompress_embedded(&compressed).unwrap(), (samples, spec));
+
+        let (empty, _) = decompress_embedded(&compressed[..HEADER_LEN]).unwrap();
+        assert!(empty.is_empty());
+    }
+}
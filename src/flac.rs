@@ -1,3 +1,5 @@
+use crate::cost::CostModel;
+use crate::wav::MAX_SAMPLES;
 use claxon::FlacReader;
 use flacenc::component::BitRepr;
 use flacenc::error::Verify;
@@ -16,7 +18,9 @@ pub fn compress_flac(
     let (channels, bits_per_sample, sample_rate) =
         (spec.channels as u8, spec.bits_per_sample, spec.sample_rate);
 
-    let config = flacenc::config::Encoder::default().into_verified().unwrap();
+    let config = flacenc::config::Encoder::default()
+        .into_verified()
+        .map_err(|(_, e)| format!("Invalid FLAC encoder config: {:?}", e))?;
     let source = flacenc::source::MemSource::from_samples(
         &samples_as_i32,
         channels as usize,
@@ -24,13 +28,46 @@ pub fn compress_flac(
         sample_rate as usize,
     );
     let flac_stream = flacenc::encode_with_fixed_block_size(&config, source, config.block_size)
-        .expect("Encode failed.");
+        .map_err(|e| format!("FLAC encode failed: {:?}", e))?;
 
     let mut sink = flacenc::bitsink::ByteSink::new();
-    let _ = flac_stream.write(&mut sink);
+    flac_stream
+        .write(&mut sink)
+        .map_err(|e| format!("FLAC write failed: {:?}", e))?;
+
+    let mut output = sink.as_slice().to_vec();
+    if samples.is_empty() {
+        // With no frames flacenc leaves STREAMINFO's block and frame size
+        // bounds at their initial min > max, which decoders reject. Declare
+        // the configured block size and unknown (zero) frame sizes instead.
+        let block_size = (config.block_size as u16).to_be_bytes();
+        output[8..10].copy_from_slice(&block_size);
+        output[10..12].copy_from_slice(&block_size);
+        output[12..15].fill(0);
+    }
 
     debug!("Finished compressing data into FLAC format");
-    Ok(sink.as_slice().to_vec())
+    Ok(output)
+}
+
+/// Rough encoder cost of [`compress_flac`] with flacenc's default settings:
+/// per channel sample a window, the autocorrelation and residual of the
+/// order-10 LPC, and the residuals of the fixed predictors. Stereo frames
+/// also try mid and side channels, doubling the work.
+pub fn cost_flac(spec: &WavSpec) -> CostModel {
+    let order = flacenc::constant::qlpc::DEFAULT_ORDER as f64;
+    let block = flacenc::constant::DEFAULT_BLOCK_SIZE;
+    let variants = if spec.channels == 2 { 2.0 } else { 1.0 };
+    CostModel {
+        // Window, autocorrelation, LPC residual and the fixed orders' taps.
+        multiplies: variants * (1.0 + (order + 1.0) + order + 9.0),
+        // The same, plus fixed residual sums, Rice partition search and writing.
+        additions: variants * ((order + 1.0) + order + 2.0 + 25.0 + 10.0 + 6.0),
+        // One frame of every candidate channel: samples, window and residuals.
+        state_bytes: block * spec.channels as usize * variants as usize * 3 * 4,
+        // The i32 copy of the input and the encoded stream's residuals.
+        buffer_bytes_per_sample: 4.0 + 8.0,
+    }
 }
 
 // Decompress FLAC data to WAV format using claxon crate
@@ -47,8 +84,20 @@ pub fn decompress_flac(buffer: &[u8]) -> Result<(Vec<i16>, WavSpec), Box<dyn Err
         sample_format: hound::SampleFormat::Int,
     };
 
+    // flacenc pads the final fixed-size frame, so stop at the length recorded in STREAMINFO
+    let total_samples = reader
+        .streaminfo()
+        .samples
+        .map_or(u64::MAX, |frames| frames * spec.channels as u64);
+
     let mut samples = Vec::new();
-    for sample in reader.samples() {
+    for sample in reader.samples().take(total_samples as usize) {
+        if samples.len() == MAX_SAMPLES {
+            return Err(Box::from(format!(
+                "FLAC stream exceeds the limit of {} samples",
+                MAX_SAMPLES
+            )));
+        }
         let sample: i32 = sample?;
         samples.push(sample as i16);
     }
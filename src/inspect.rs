@@ -0,0 +1,264 @@
+use crate::wav::read_wav_header;
+use hound::{SampleFormat, WavSpec};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Header metadata of one recording.
+#[derive(Clone, Debug)]
+pub struct FileMetadata {
+    /// Path relative to the inspected directory, with `/` separators.
+    pub path: String,
+    pub spec: WavSpec,
+    /// Length in frames.
+    pub length: u32,
+    /// Spec fields that differ from the dataset majority.
+    pub deviations: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetadataFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl MetadataFormat {
+    pub fn from_name(name: &str) -> Result<MetadataFormat, Box<dyn Error + Send + Sync>> {
+        match name {
+            "text" => Ok(MetadataFormat::Text),
+            "json" => Ok(MetadataFormat::Json),
+            "csv" => Ok(MetadataFormat::Csv),
+            _ => Err(Box::from(format!(
+                "Unknown metadata format: {} (expected text, json or csv)",
+                name
+            ))),
+        }
+    }
+}
+
+fn format_name(format: SampleFormat) -> &'static str {
+    match format {
+        SampleFormat::Int => "int",
+        SampleFormat::Float => "float",
+    }
+}
+
+/// Every `.wav` file below `dir`, sorted.
+fn wav_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            files.extend(wav_files(&path)?);
+        } else if path.extension().and_then(|s| s.to_str()) == Some("wav") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Most common value of a field, ties going to the smallest.
+fn majority<T: Ord + Copy>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts = BTreeMap::new();
+    for value in values {
+        *counts.entry(value).or_insert(0usize) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .map(|(value, _)| value)
+}
+
+/// Reads the header of every WAV under `dir` and flags files whose spec
+/// differs from the most common one.
+pub fn inspect_directory(dir: &Path) -> Result<Vec<FileMetadata>, Box<dyn Error + Send + Sync>> {
+    let mut files = Vec::new();
+    for path in wav_files(dir)? {
+        let file_path = path.to_str().ok_or("Input path is not valid UTF-8")?;
+        let (spec, length) = read_wav_header(file_path)?;
+        let relative = path.strip_prefix(dir).unwrap_or(&path);
+        let components: Vec<_> = relative
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect();
+        files.push(FileMetadata {
+            path: components.join("/"),
+            spec,
+            length,
+            deviations: Vec::new(),
+        });
+    }
+
+    let sample_rate = majority(files.iter().map(|f| f.spec.sample_rate));
+    let bits = majority(files.iter().map(|f| f.spec.bits_per_sample));
+    let channels = majority(files.iter().map(|f| f.spec.channels));
+    let float = majority(
+        files
+            .iter()
+            .map(|f| f.spec.sample_format == SampleFormat::Float),
+    );
+    for file in &mut files {
+        let spec = file.spec;
+        if Some(spec.sample_rate) != sample_rate {
+            file.deviations.push(format!(
+                "sample rate {} (majority {})",
+                spec.sample_rate,
+                sample_rate.unwrap_or_default()
+            ));
+        }
+        if Some(spec.bits_per_sample) != bits {
+            file.deviations.push(format!(
+                "bits per sample {} (majority {})",
+                spec.bits_per_sample,
+                bits.unwrap_or_default()
+            ));
+        }
+        if Some(spec.channels) != channels {
+            file.deviations.push(format!(
+                "channels {} (majority {})",
+                spec.channels,
+                channels.unwrap_or_default()
+            ));
+        }
+        if Some(spec.sample_format == SampleFormat::Float) != float {
+            file.deviations
+                .push(format!("sample format {}", format_name(spec.sample_format)));
+        }
+    }
+    Ok(files)
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+pub fn write_metadata<W: Write>(
+    writer: &mut W,
+    files: &[FileMetadata],
+    format: MetadataFormat,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match format {
+        MetadataFormat::Text => {
+            for file in files {
+                writeln!(writer, "File: {}", file.path)?;
+                writeln!(writer, "Sample Rate: {}", file.spec.sample_rate)?;
+                writeln!(writer, "Bits per Sample: {}", file.spec.bits_per_sample)?;
+                writeln!(writer, "Channels: {}", file.spec.channels)?;
+                writeln!(writer, "Length: {}", file.length)?;
+                if !file.deviations.is_empty() {
+                    writeln!(writer, "Deviates: {}", file.deviations.join(", "))?;
+                }
+                writeln!(writer, "-----------")?;
+                writeln!(writer)?;
+            }
+        }
+        MetadataFormat::Json => {
+            writeln!(writer, "[")?;
+            for (i, file) in files.iter().enumerate() {
+                let deviations: Vec<String> =
+                    file.deviations.iter().map(|d| json_string(d)).collect();
+                writeln!(
+                    writer,
+                    "  {{\"path\": {}, \"sample_rate\": {}, \"bits_per_sample\": {}, \"channels\": {}, \
+                     \"sample_format\": \"{}\", \"length\": {}, \"deviations\": [{}]}}{}",
+                    json_string(&file.path),
+                    file.spec.sample_rate,
+                    file.spec.bits_per_sample,
+                    file.spec.channels,
+                    format_name(file.spec.sample_format),
+                    file.length,
+                    deviations.join(", "),
+                    if i + 1 < files.len() { "," } else { "" }
+                )?;
+            }
+            writeln!(writer, "]")?;
+        }
+        MetadataFormat::Csv => {
+            writeln!(
+                writer,
+                "path,sample_rate,bits_per_sample,channels,sample_format,length,deviations"
+            )?;
+            for file in files {
+                writeln!(
+                    writer,
+                    "{},{},{},{},{},{},{}",
+                    csv_field(&file.path),
+                    file.spec.sample_rate,
+                    file.spec.bits_per_sample,
+                    file.spec.channels,
+                    format_name(file.spec.sample_format),
+                    file.length,
+                    csv_field(&file.deviations.join("; "))
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wav::write_wav_file;
+
+    #[test]
+    fn flags_minority_spec() {
+        let dir =
+            std::env::temp_dir().join(format!("smallbrain-inspect-test-{}", std::process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(dir.join("nested")).unwrap();
+
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let odd = WavSpec {
+            sample_rate: 30000,
+            ..spec
+        };
+        for (name, spec) in [("a.wav", spec), ("b.wav", spec), ("nested/c.wav", odd)] {
+            let path = dir.join(name);
+            write_wav_file(path.to_str().unwrap(), &[0, 1, 2], spec).unwrap();
+        }
+
+        let files = inspect_directory(&dir).unwrap();
+        let mut csv = Vec::new();
+        write_metadata(&mut csv, &files, MetadataFormat::Csv).unwrap();
+        fs::remove_dir_all(&dir).ok();
+
+        let paths: Vec<_> = files.iter().map(|f| f.path.as_str()).collect();
+        assert_eq!(paths, ["a.wav", "b.wav", "nested/c.wav"]);
+        assert!(files[0].deviations.is_empty());
+        assert_eq!(
+            files[2].deviations,
+            ["sample rate 30000 (majority 19531)".to_string()]
+        );
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.contains("nested/c.wav,30000,16,1,int,3,sample rate 30000 (majority 19531)"));
+    }
+}
@@ -0,0 +1,426 @@
+use crate::recording::RecordingParts;
+use crate::wav::check_recording_length;
+use hound::{SampleFormat, WavSpec};
+use std::error::Error;
+use std::io::Write;
+use tracing::debug;
+
+/// Magic number opening an RHD2000 (`.rhd`) file.
+const RHD_MAGIC: u32 = 0xC691_2702;
+
+/// Magic number opening an RHS2000 stimulation/recording (`.rhs`) file.
+const RHS_MAGIC: u32 = 0xD691_27AC;
+
+/// Amplifier samples are unsigned with this midpoint; subtracting it gives the
+/// signed samples the codecs expect without losing a bit.
+const AMPLIFIER_OFFSET: u16 = 0x8000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IntanKind {
+    Rhd,
+    Rhs,
+}
+
+/// What the header of an Intan file says about the data blocks after it.
+///
+/// Every block starts with `samples_per_block` 32-bit timestamps followed by
+/// the amplifier channels, one channel's samples after another. Everything
+/// else in the block (auxiliary inputs, supply voltages, temperature, stim
+/// and DC data, board ADC/DAC and digital lines) is carried through untouched.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntanLayout {
+    pub kind: IntanKind,
+    pub header_len: usize,
+    pub sample_rate: f32,
+    pub amplifier_channels: usize,
+    pub samples_per_block: usize,
+    pub block_len: usize,
+}
+
+impl IntanLayout {
+    /// Bytes of amplifier data in one block.
+    fn amplifier_len(&self) -> usize {
+        self.samples_per_block * self.amplifier_channels * 2
+    }
+
+    /// Bytes of the timestamps in front of the amplifier data.
+    fn timestamps_len(&self) -> usize {
+        self.samples_per_block * 4
+    }
+}
+
+/// Little-endian reader over a header, failing cleanly on truncation.
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> HeaderReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error + Send + Sync>> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position.saturating_add(len))
+            .ok_or("Intan header is truncated")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn i16(&mut self) -> Result<i16, Box<dyn Error + Send + Sync>> {
+        let bytes = self.take(2)?;
+        Ok(i16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Box<dyn Error + Send + Sync>> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn f32(&mut self) -> Result<f32, Box<dyn Error + Send + Sync>> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+
+    /// Skips a Qt string: a byte length, `u32::MAX` for a null string, then
+    /// UTF-16 text.
+    fn qstring(&mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self.u32()? {
+            u32::MAX => Ok(()),
+            len => self.take(len as usize).map(|_| ()),
+        }
+    }
+}
+
+/// Parses the header of an `.rhd` or `.rhs` file, following Intan's
+/// published header layouts up to RHD 3.x and RHS 3.x.
+pub fn parse_header(bytes: &[u8]) -> Result<IntanLayout, Box<dyn Error + Send + Sync>> {
+    let mut reader = HeaderReader { bytes, position: 0 };
+    let kind = match reader.u32()? {
+        RHD_MAGIC => IntanKind::Rhd,
+        RHS_MAGIC => IntanKind::Rhs,
+        magic => {
+            return Err(Box::from(format!(
+                "Not an Intan file (magic number {:#010x})",
+                magic
+            )))
+        }
+    };
+    let version = (reader.i16()?, reader.i16()?);
+    let sample_rate = reader.f32()?;
+    match kind {
+        // DSP flag, six bandwidths, notch mode, two impedance test frequencies.
+        IntanKind::Rhd => reader.take(2 + 6 * 4 + 2 + 2 * 4)?,
+        // DSP flag, eight bandwidths, notch mode, two impedance test
+        // frequencies, settle and charge recovery modes, three stim settings.
+        IntanKind::Rhs => reader.take(2 + 8 * 4 + 2 + 2 * 4 + 2 + 2 + 3 * 4)?,
+    };
+    for _ in 0..3 {
+        reader.qstring()?;
+    }
+
+    let mut temperature_sensors = 0;
+    let mut dc_amplifier_saved = false;
+    match kind {
+        IntanKind::Rhd => {
+            if version >= (1, 1) {
+                temperature_sensors = reader.i16()?.max(0) as usize;
+            }
+            if version >= (1, 3) {
+                reader.take(2)?; // Evaluation board mode.
+            }
+            if version.0 > 1 {
+                reader.qstring()?; // Reference channel.
+            }
+        }
+        IntanKind::Rhs => {
+            dc_amplifier_saved = reader.i16()? != 0;
+            reader.take(2)?; // Evaluation board mode.
+            reader.qstring()?; // Reference channel.
+        }
+    }
+
+    // Enabled channels by signal type.
+    let mut counts = [0usize; 7];
+    let groups = reader.i16()?;
+    for _ in 0..groups.max(0) {
+        reader.qstring()?; // Group name.
+        reader.qstring()?; // Prefix.
+        let enabled = reader.i16()?;
+        let channels = reader.i16()?;
+        reader.i16()?; // Amplifier channels, which the channel list repeats.
+        if enabled <= 0 {
+            continue;
+        }
+        for _ in 0..channels.max(0) {
+            reader.qstring()?; // Native name.
+            reader.qstring()?; // Custom name.
+            reader.take(4)?; // Native and custom order.
+            let signal_type = reader.i16()?;
+            let channel_enabled = reader.i16()?;
+            // Chip channel, (command stream,) board stream, four spike
+            // trigger settings, impedance magnitude and phase.
+            let streams = match kind {
+                IntanKind::Rhd => 2,
+                IntanKind::Rhs => 3,
+            };
+            reader.take(streams * 2 + 4 * 2 + 2 * 4)?;
+            let supported = match kind {
+                IntanKind::Rhd => (0..=5).contains(&signal_type),
+                IntanKind::Rhs => matches!(signal_type, 0 | 3..=6),
+            };
+            if !supported {
+                return Err(Box::from(format!(
+                    "Unsupported Intan signal type {}",
+                    signal_type
+                )));
+            }
+            if channel_enabled != 0 {
+                counts[signal_type as usize] += 1;
+            }
+        }
+    }
+
+    let n = match (kind, version.0) {
+        (IntanKind::Rhd, 1) => 60,
+        _ => 128,
+    };
+    let lines = |count: usize| if count > 0 { 2 * n } else { 0 };
+    let block_len = match kind {
+        IntanKind::Rhd => {
+            4 * n
+                + 2 * n * counts[0]
+                + 2 * (n / 4) * counts[1]
+                + 2 * counts[2]
+                + 2 * temperature_sensors
+                + 2 * n * counts[3]
+                + lines(counts[4])
+                + lines(counts[5])
+        }
+        IntanKind::Rhs => {
+            // Amplifier, optional DC amplifier and stimulation data per channel.
+            4 * n
+                + 2 * n * counts[0] * (2 + dc_amplifier_saved as usize)
+                + 2 * n * (counts[3] + counts[4])
+                + lines(counts[5])
+                + lines(counts[6])
+        }
+    };
+
+    let layout = IntanLayout {
+        kind,
+        header_len: reader.position,
+        sample_rate,
+        amplifier_channels: counts[0],
+        samples_per_block: n,
+        block_len,
+    };
+    debug!(
+        "Intan {:?} v{}.{}: {:?}",
+        kind, version.0, version.1, layout
+    );
+    Ok(layout)
+}
+
+/// Reads the amplifier channels of an Intan file as interleaved signed
+/// samples. The returned metadata is the file without its amplifier data,
+/// which is all [`write_intan`] needs to rebuild the file byte for byte.
+pub fn read_intan(bytes: &[u8]) -> Result<RecordingParts, Box<dyn Error + Send + Sync>> {
+    let layout = parse_header(bytes)?;
+    let channels = layout.amplifier_channels;
+    if channels == 0 || channels > u16::MAX as usize {
+        return Err(Box::from(format!(
+            "Unsupported number of amplifier channels: {}",
+            channels
+        )));
+    }
+    let blocks = (bytes.len() - layout.header_len) / layout.block_len;
+    let n = layout.samples_per_block;
+    check_recording_length(blocks * n * channels)?;
+
+    let mut samples = vec![0i16; blocks * n * channels];
+    let mut metadata = Vec::with_capacity(bytes.len() - samples.len() * 2);
+    metadata.extend_from_slice(&bytes[..layout.header_len]);
+    let data = &bytes[layout.header_len..];
+    for (block, frames) in data
+        .chunks_exact(layout.block_len)
+        .zip(samples.chunks_exact_mut(n * channels))
+    {
+        let (timestamps, rest) = block.split_at(layout.timestamps_len());
+        let (amplifier, rest) = rest.split_at(layout.amplifier_len());
+        for (channel, values) in amplifier.chunks_exact(2 * n).enumerate() {
+            for (t, value) in values.chunks_exact(2).enumerate() {
+                let value = u16::from_le_bytes([value[0], value[1]]);
+                frames[t * channels + channel] = (value ^ AMPLIFIER_OFFSET) as i16;
+            }
+        }
+        metadata.extend_from_slice(timestamps);
+        metadata.extend_from_slice(rest);
+    }
+    // A recording cut short mid-block keeps its partial block verbatim.
+    metadata.extend_from_slice(&data[blocks * layout.block_len..]);
+
+    let spec = WavSpec {
+        channels: channels as u16,
+        sample_rate: layout.sample_rate.round() as u32,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    debug!(
+        "Read {} blocks of {} amplifier channels at {} Hz",
+        blocks, channels, layout.sample_rate
+    );
+    Ok((samples, spec, metadata))
+}
+
+/// Inverse of [`read_intan`]: puts the amplifier samples back between the
+/// timestamps and the other signals of every block.
+pub fn write_intan<W: Write>(
+    mut writer: W,
+    samples: &[i16],
+    metadata: &[u8],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let layout = parse_header(metadata)?;
+    let channels = layout.amplifier_channels;
+    let n = layout.samples_per_block;
+    if channels == 0 || !samples.len().is_multiple_of(n * channels) {
+        return Err(Box::from(format!(
+            "{} samples are not whole blocks of {} channels",
+            samples.len(),
+            channels
+        )));
+    }
+    let blocks = samples.len() / (n * channels);
+    let other_len = layout.block_len - layout.amplifier_len();
+    let data = &metadata[layout.header_len..];
+    if data.len() < blocks * other_len {
+        return Err(Box::from("Intan metadata is shorter than the samples"));
+    }
+
+    writer.write_all(&metadata[..layout.header_len])?;
+    let mut amplifier = vec![0u8; layout.amplifier_len()];
+    for (other, frames) in data
+        .chunks_exact(other_len)
+        .zip(samples.chunks_exact(n * channels))
+    {
+        for (channel, values) in amplifier.chunks_exact_mut(2 * n).enumerate() {
+            for (t, value) in values.chunks_exact_mut(2).enumerate() {
+                let sample = frames[t * channels + channel] as u16 ^ AMPLIFIER_OFFSET;
+                value.copy_from_slice(&sample.to_le_bytes());
+            }
+        }
+        let (timestamps, rest) = other.split_at(layout.timestamps_len());
+        writer.write_all(timestamps)?;
+        writer.write_all(&amplifier)?;
+        writer.write_all(rest)?;
+    }
+    writer.write_all(&data[blocks * other_len..])?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn qstring(header: &mut Vec<u8>, text: &str) {
+        let units: Vec<u16> = text.encode_utf16().collect();
+        header.extend_from_slice(&(units.len() as u32 * 2).to_le_bytes());
+        for unit in units {
+            header.extend_from_slice(&unit.to_le_bytes());
+        }
+    }
+
+    /// A small but complete Intan file: `amplifiers` amplifier channels plus
+    /// one board ADC channel and a digital input, `blocks` blocks and a few
+    /// bytes of a cut-off block.
+    pub(crate) fn intan_file(kind: IntanKind, amplifiers: usize, blocks: usize) -> Vec<u8> {
+        let mut file = Vec::new();
+        let i16s = |file: &mut Vec<u8>, values: &[i16]| {
+            for value in values {
+                file.extend_from_slice(&value.to_le_bytes());
+            }
+        };
+        match kind {
+            IntanKind::Rhd => {
+                file.extend_from_slice(&RHD_MAGIC.to_le_bytes());
+                i16s(&mut file, &[3, 0]);
+                file.extend_from_slice(&20_000f32.to_le_bytes());
+                file.extend_from_slice(&[0; 2 + 6 * 4 + 2 + 2 * 4]);
+            }
+            IntanKind::Rhs => {
+                file.extend_from_slice(&RHS_MAGIC.to_le_bytes());
+                i16s(&mut file, &[3, 0]);
+                file.extend_from_slice(&30_000f32.to_le_bytes());
+                file.extend_from_slice(&[0; 2 + 8 * 4 + 2 + 2 * 4 + 2 + 2 + 3 * 4]);
+            }
+        }
+        qstring(&mut file, "note one");
+        file.extend_from_slice(&u32::MAX.to_le_bytes());
+        qstring(&mut file, "");
+        // One temperature sensor (RHD) or DC amplifier data saved (RHS),
+        // then the evaluation board mode.
+        i16s(&mut file, &[1, 0]);
+        qstring(&mut file, "n/a");
+
+        let (adc, digital_in) = match kind {
+            IntanKind::Rhd => (3, 4),
+            IntanKind::Rhs => (3, 5),
+        };
+        let mut channels = vec![0; amplifiers];
+        channels.extend([adc, digital_in]);
+        i16s(&mut file, &[1]);
+        qstring(&mut file, "Port A");
+        qstring(&mut file, "A");
+        i16s(&mut file, &[1, channels.len() as i16, amplifiers as i16]);
+        for (i, &signal_type) in channels.iter().enumerate() {
+            qstring(&mut file, &format!("A-{:03}", i));
+            qstring(&mut file, &format!("A-{:03}", i));
+            i16s(&mut file, &[i as i16, i as i16, signal_type, 1]);
+            let streams = if kind == IntanKind::Rhs { 3 } else { 2 };
+            file.extend_from_slice(&vec![0; streams * 2 + 4 * 2 + 2 * 4]);
+        }
+
+        let layout = parse_header(&file).unwrap();
+        let mut value = 7u32;
+        for block in 0..blocks {
+            let start = file.len();
+            for t in 0..layout.samples_per_block {
+                let timestamp = (block * layout.samples_per_block + t) as i32;
+                file.extend_from_slice(&timestamp.to_le_bytes());
+            }
+            while file.len() < start + layout.block_len {
+                value = value.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                file.push((value >> 16) as u8);
+            }
+        }
+        file.extend_from_slice(&[1, 2, 3]);
+        file
+    }
+
+    #[test]
+    fn intan_roundtrip() {
+        for kind in [IntanKind::Rhd, IntanKind::Rhs] {
+            let file = intan_file(kind, 3, 4);
+            let (samples, spec, metadata) = read_intan(&file).unwrap();
+            assert_eq!(spec.channels, 3);
+            assert_eq!(samples.len(), 4 * 128 * 3);
+            assert_eq!(metadata.len(), file.len() - samples.len() * 2);
+
+            let mut restored = Vec::new();
+            write_intan(&mut restored, &samples, &metadata).unwrap();
+            assert_eq!(restored, file);
+        }
+    }
+
+    #[test]
+    fn amplifier_samples_are_centred() {
+        let mut file = intan_file(IntanKind::Rhd, 2, 1);
+        let layout = parse_header(&file).unwrap();
+        // Channel 1, sample 0 at the midpoint; channel 0, sample 1 one below.
+        let amplifier = layout.header_len + layout.timestamps_len();
+        file[amplifier + 2 * 128..amplifier + 2 * 128 + 2]
+            .copy_from_slice(&0x8000u16.to_le_bytes());
+        file[amplifier + 2..amplifier + 4].copy_from_slice(&0x7FFFu16.to_le_bytes());
+        let (samples, _, _) = read_intan(&file).unwrap();
+        assert_eq!(samples[1], 0);
+        assert_eq!(samples[2], -1);
+    }
+}
@@ -0,0 +1,57 @@
+//! Lossless compression of neural recordings.
+//!
+//! # Determinism
+//!
+//! Compressed size is the metric this crate is judged on, so every codec
+//! produces byte-identical output for identical input, whatever the run,
+//! rayon thread count or platform:
+//!
+//! - the in-crate codecs (`rice`, `ans`, `cm`, `lms`, `spikes`, `realtime`,
+//!   `embedded`) use integer arithmetic, plus IEEE-exact `f64` operations
+//!   (`+ - * /`, `round`) in the encoder-side analysis of `cm` and `spikes`;
+//!   nothing calls libm functions such as `exp` or `sin` whose results vary
+//!   between platforms;
+//! - parallel paths split the input into independent pieces and collect the
+//!   results in input order, so scheduling never reaches the output;
+//! - maps whose iteration order can influence results are `BTreeMap`s;
+//! - the third-party encoders (zstd, zlib, brotli, flacenc) are deterministic
+//!   for a given library version and settings, which `Cargo.lock` pins. The
+//!   one exception to the platform guarantee is flacenc, whose LPC window
+//!   calls `f32::cos`, so `flac` and `flac-brotli` output is only guaranteed
+//!   to match between platforms sharing a libm.
+//!
+//! `tests/determinism.rs` checks this by running `compress` for every codec
+//! under several `RAYON_NUM_THREADS` values and comparing output hashes, both
+//! with each other and, for every codec but `flac` and `flac-brotli`, with
+//! hashes pinned in the test.
+
+pub mod analyze;
+pub mod ans;
+pub mod archive;
+pub mod arith;
+pub mod bitpack;
+pub mod brotli_sb;
+pub mod cm;
+pub mod codec;
+pub mod cost;
+pub mod embedded;
+pub mod flac;
+pub mod inspect;
+pub mod intan;
+pub mod link;
+pub mod lms;
+pub mod model;
+pub mod nsx;
+pub mod openephys;
+pub mod plot;
+pub mod predictor;
+pub mod realtime;
+pub mod recording;
+pub mod resilient;
+pub mod rice;
+pub mod spikes;
+pub mod synth;
+pub mod tenbit;
+pub mod wav;
+pub mod zlib;
+pub mod zstd;
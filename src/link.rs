@@ -0,0 +1,371 @@
+use crate::realtime::DEFAULT_PACKET_FRAMES;
+use crate::resilient::{
+    GapFill, LossReport, ResilientDecoder, ResilientEncoder, DEFAULT_RESET_INTERVAL,
+};
+use hound::WavSpec;
+use std::error::Error;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{
+    Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// Largest payload of a UDP datagram over IPv4, and so the largest frame
+/// [`serve`] can send over UDP.
+pub const MAX_DATAGRAM: usize = 65_507;
+
+/// Largest frame accepted from a TCP peer, which bounds what a corrupt
+/// length prefix can allocate.
+const MAX_TCP_FRAME: usize = 1 << 24;
+
+/// Transport of a live link.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    /// One frame per datagram. Frames can be lost or reordered, which the
+    /// resilient framing reports as gaps; an empty datagram ends the stream.
+    Udp,
+    /// Every frame behind its 32-bit big-endian length, since a byte stream
+    /// has no datagram boundaries; closing the connection ends the stream.
+    Tcp,
+}
+
+impl Protocol {
+    pub fn from_name(name: &str) -> Result<Protocol, Box<dyn Error + Send + Sync>> {
+        match name {
+            "udp" => Ok(Protocol::Udp),
+            "tcp" => Ok(Protocol::Tcp),
+            _ => Err(Box::from(format!(
+                "Unknown protocol: {} (expected udp or tcp)",
+                name
+            ))),
+        }
+    }
+}
+
+/// Settings of [`serve`].
+#[derive(Clone, Debug)]
+pub struct ServeConfig {
+    pub protocol: Protocol,
+    pub packet_frames: usize,
+    pub reset_interval: u64,
+    /// Pace relative to the sample rate: 1 sends in real time, 2 twice as
+    /// fast, 0 as fast as the link allows.
+    pub speed: f64,
+}
+
+impl Default for ServeConfig {
+    fn default() -> Self {
+        ServeConfig {
+            protocol: Protocol::Udp,
+            packet_frames: DEFAULT_PACKET_FRAMES,
+            reset_interval: DEFAULT_RESET_INTERVAL,
+            speed: 1.0,
+        }
+    }
+}
+
+/// What [`serve`] sent.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ServeReport {
+    pub samples: u64,
+    pub frames: u64,
+    pub bytes: u64,
+}
+
+/// The sending end of a link.
+enum Outbound {
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+}
+
+impl Outbound {
+    fn connect(
+        address: &str,
+        protocol: Protocol,
+    ) -> Result<Outbound, Box<dyn Error + Send + Sync>> {
+        match protocol {
+            Protocol::Udp => {
+                let target = address
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| format!("Address {} did not resolve", address))?;
+                let local = match target {
+                    SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+                    SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+                };
+                let socket = UdpSocket::bind(local)?;
+                socket.connect(target)?;
+                Ok(Outbound::Udp(socket))
+            }
+            Protocol::Tcp => {
+                let stream = TcpStream::connect(address)?;
+                stream.set_nodelay(true)?;
+                Ok(Outbound::Tcp(stream))
+            }
+        }
+    }
+
+    fn send(&mut self, frame: &[u8]) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            Outbound::Udp(socket) => {
+                if frame.len() > MAX_DATAGRAM {
+                    return Err(Box::from(format!(
+                        "Frame of {} bytes does not fit in a datagram; use shorter packets or TCP",
+                        frame.len()
+                    )));
+                }
+                // Nobody listening is not an error for a datagram link: the
+                // receiver may join later and will report what it missed.
+                match socket.send(frame) {
+                    Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                        debug!("No receiver listening, frame dropped");
+                        Ok(())
+                    }
+                    result => result.map(|_| ()).map_err(Box::from),
+                }
+            }
+            Outbound::Tcp(stream) => {
+                stream.write_all(&(frame.len() as u32).to_be_bytes())?;
+                stream.write_all(frame)?;
+                Ok(())
+            }
+        }
+    }
+
+    fn close(mut self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        match self {
+            Outbound::Udp(_) => self.send(&[]),
+            Outbound::Tcp(ref stream) => Ok(stream.shutdown(Shutdown::Write)?),
+        }
+    }
+}
+
+/// Streams interleaved `samples` to `address` as resilient frames, one
+/// packet at a time. Each packet is sent once its last frame would have been
+/// acquired at `config.speed` times the sample rate, so a recording replays
+/// like a live source.
+pub fn serve<I>(
+    samples: I,
+    spec: &WavSpec,
+    address: &str,
+    config: &ServeConfig,
+) -> Result<ServeReport, Box<dyn Error + Send + Sync>>
+where
+    I: IntoIterator<Item = Result<i16, Box<dyn Error + Send + Sync>>>,
+{
+    let mut encoder = ResilientEncoder::new(spec, config.packet_frames, config.reset_interval)?;
+    let mut link = Outbound::connect(address, config.protocol)?;
+    let channels = spec.channels.max(1) as usize;
+    let packet_len = config.packet_frames * channels;
+    let mut samples = samples.into_iter();
+    let mut report = ServeReport::default();
+    let mut send = |link: &mut Outbound, frame: &[u8]| {
+        report.frames += 1;
+        report.bytes += frame.len() as u64;
+        link.send(frame)
+    };
+
+    let start = Instant::now();
+    let mut chunk = Vec::with_capacity(packet_len);
+    let mut acquired = 0u64;
+    loop {
+        chunk.clear();
+        for sample in samples.by_ref().take(packet_len) {
+            chunk.push(sample?);
+        }
+        if chunk.is_empty() {
+            break;
+        }
+        acquired += chunk.len() as u64;
+        if config.speed > 0.0 {
+            let frames = (acquired / channels as u64) as f64;
+            let due =
+                Duration::from_secs_f64(frames / spec.sample_rate.max(1) as f64 / config.speed);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        for frame in encoder.push(&chunk)? {
+            send(&mut link, &frame)?;
+        }
+        if chunk.len() < packet_len {
+            break;
+        }
+    }
+    if let Some(frame) = encoder.flush()? {
+        send(&mut link, &frame)?;
+    }
+    link.close()?;
+
+    report.samples = acquired;
+    debug!(
+        "Sent {} samples in {} frames ({} bytes)",
+        report.samples, report.frames, report.bytes
+    );
+    Ok(report)
+}
+
+/// The receiving end of a link, bound and waiting for a sender.
+pub struct Receiver {
+    inbound: Inbound,
+}
+
+enum Inbound {
+    Udp(UdpSocket),
+    Tcp(TcpListener),
+}
+
+impl Receiver {
+    pub fn bind(
+        address: &str,
+        protocol: Protocol,
+    ) -> Result<Receiver, Box<dyn Error + Send + Sync>> {
+        let inbound = match protocol {
+            Protocol::Udp => Inbound::Udp(UdpSocket::bind(address)?),
+            Protocol::Tcp => Inbound::Tcp(TcpListener::bind(address)?),
+        };
+        Ok(Receiver { inbound })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, Box<dyn Error + Send + Sync>> {
+        Ok(match &self.inbound {
+            Inbound::Udp(socket) => socket.local_addr()?,
+            Inbound::Tcp(listener) => listener.local_addr()?,
+        })
+    }
+
+    /// Receives one stream, handing decoded samples to `output` as they
+    /// arrive, and returns the loss report. Waits as long as it takes for the
+    /// first frame; after that the stream also ends once nothing has arrived
+    /// for `idle_timeout`, so a sender that vanishes does not hang the receiver.
+    pub fn run<F>(
+        self,
+        fill: GapFill,
+        idle_timeout: Duration,
+        mut output: F,
+    ) -> Result<LossReport, Box<dyn Error + Send + Sync>>
+    where
+        F: FnMut(&WavSpec, &[i16]) -> Result<(), Box<dyn Error + Send + Sync>>,
+    {
+        let mut decoder = ResilientDecoder::new(fill);
+        let mut deliver = |decoder: &mut ResilientDecoder, frame: &[u8]| {
+            let samples = decoder.receive(frame)?;
+            match decoder.spec() {
+                Some(spec) if !samples.is_empty() => output(&spec, &samples),
+                _ => Ok(()),
+            }
+        };
+        let timed_out =
+            |e: &std::io::Error| matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut);
+
+        match self.inbound {
+            Inbound::Udp(socket) => {
+                socket.set_read_timeout(Some(idle_timeout))?;
+                let mut buffer = vec![0; MAX_DATAGRAM + 1];
+                let mut started = false;
+                loop {
+                    match socket.recv(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(length) => {
+                            started = true;
+                            deliver(&mut decoder, &buffer[..length])?;
+                        }
+                        Err(e) if timed_out(&e) => {
+                            if started {
+                                debug!("No frame for {:?}, ending the stream", idle_timeout);
+                                break;
+                            }
+                        }
+                        // A previous peer's port being unreachable is not this stream's problem.
+                        Err(e) if e.kind() == ErrorKind::ConnectionRefused => {}
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+            Inbound::Tcp(listener) => {
+                let (mut stream, peer) = listener.accept()?;
+                debug!("Receiving from {}", peer);
+                stream.set_read_timeout(Some(idle_timeout))?;
+                loop {
+                    let mut length = [0; 4];
+                    match stream.read_exact(&mut length) {
+                        Ok(()) => {}
+                        Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                        Err(e) if timed_out(&e) => {
+                            debug!("No frame for {:?}, ending the stream", idle_timeout);
+                            break;
+                        }
+                        Err(e) => return Err(e.into()),
+                    }
+                    let length = u32::from_be_bytes(length) as usize;
+                    if length > MAX_TCP_FRAME {
+                        return Err(Box::from(format!("Invalid frame length {}", length)));
+                    }
+                    let mut frame = vec![0; length];
+                    stream.read_exact(&mut frame)?;
+                    deliver(&mut decoder, &frame)?;
+                }
+            }
+        }
+        Ok(decoder.finish())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resilient::expected_output;
+    use crate::synth::{generate, SynthConfig};
+
+    fn roundtrip(protocol: Protocol) {
+        let (samples, spec) = generate(&SynthConfig {
+            channels: 2,
+            frames: 5000,
+            ..SynthConfig::default()
+        });
+        let receiver = Receiver::bind("127.0.0.1:0", protocol).unwrap();
+        let address = receiver.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let mut received = Vec::new();
+            let report = receiver
+                .run(GapFill::Hold, Duration::from_secs(5), |_, chunk| {
+                    received.extend_from_slice(chunk);
+                    Ok(())
+                })
+                .unwrap();
+            (received, report)
+        });
+
+        let config = ServeConfig {
+            protocol,
+            speed: 0.0,
+            ..ServeConfig::default()
+        };
+        let sent = serve(samples.iter().map(|&s| Ok(s)), &spec, &address, &config).unwrap();
+        assert_eq!(sent.samples, samples.len() as u64);
+
+        let (received, report) = handle.join().unwrap();
+        // A loaded host may drop datagrams even on loopback; whatever it
+        // drops must be reported and held over.
+        assert_eq!(report.received + report.lost, sent.frames);
+        if protocol == Protocol::Tcp {
+            assert_eq!(report.received, sent.frames);
+        }
+        assert_eq!(
+            received,
+            expected_output(&samples, 2, &report.gaps, GapFill::Hold)
+        );
+    }
+
+    #[test]
+    fn tcp_roundtrip() {
+        roundtrip(Protocol::Tcp);
+    }
+
+    #[test]
+    fn udp_roundtrip() {
+        roundtrip(Protocol::Udp);
+    }
+}
@@ -0,0 +1,302 @@
+use crate::ans::{
+    decode_residuals_adaptive, encode_residuals_adaptive, encode_residuals_adaptive_cost,
+    ResidualDecoder,
+};
+use crate::bitpack::{BitOrder, BitReader, BitWriter};
+use crate::cost::CostModel;
+use crate::rice::{decode_residuals, encode_residuals, encode_residuals_cost};
+use crate::wav::{deinterleave, interleave, read_spec_header, write_spec_header};
+use hound::WavSpec;
+use smallbrain_core::predictor::{first_order, stage_predict, stage_update};
+use std::error::Error;
+use tracing::debug;
+
+/// Longest filter a stream may declare; every stage needs at least one tap.
+const MAX_ORDER: usize = 256;
+
+/// How a stage adapts its weights after each sample.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LmsKind {
+    /// `w += step * sign(error) * sign(input)`: cheap, robust, slow to converge.
+    SignSign,
+    /// `w += mu * error * input / |input|^2`: converges quickly at any signal level.
+    Normalized,
+}
+
+/// The cascade used by the `lms-*` codecs: long NLMS filters for the broad
+/// spectral shape, then a short sign-sign filter to mop up.
+pub const DEFAULT_STAGES: &[(LmsKind, usize)] = &[
+    (LmsKind::Normalized, 32),
+    (LmsKind::Normalized, 16),
+    (LmsKind::SignSign, 16),
+];
+
+/// One adaptive FIR stage. It predicts its input from the previous inputs and
+/// passes the prediction error on to the next stage.
+pub struct LmsStage {
+    kind: LmsKind,
+    weights: Vec<i32>,
+    history: Vec<i32>,
+}
+
+impl LmsStage {
+    pub fn new(kind: LmsKind, order: usize) -> Self {
+        LmsStage {
+            kind,
+            weights: vec![0; order],
+            history: vec![0; order],
+        }
+    }
+
+    pub fn predict(&self) -> i32 {
+        stage_predict(&self.weights, &self.history)
+    }
+
+    /// Adapts the weights given the stage's `input` and the `error` it left,
+    /// then shifts `input` into the history.
+    pub fn update(&mut self, input: i32, error: i32) {
+        stage_update(
+            self.kind == LmsKind::Normalized,
+            &mut self.weights,
+            &mut self.history,
+            input,
+            error,
+        );
+    }
+}
+
+/// A scaled first-order difference followed by a chain of [`LmsStage`]s.
+/// Encoder and decoder run the same cascade in lockstep, so no coefficients
+/// are transmitted.
+pub struct LmsCascade {
+    previous: i32,
+    stages: Vec<LmsStage>,
+}
+
+impl LmsCascade {
+    pub fn new(stages: &[(LmsKind, usize)]) -> Self {
+        LmsCascade {
+            previous: 0,
+            stages: stages
+                .iter()
+                .map(|&(kind, order)| LmsStage::new(kind, order))
+                .collect(),
+        }
+    }
+
+    /// The cascade's combined prediction of the next sample: `encode_sample`
+    /// returns the sample minus this value.
+    pub fn predict(&self) -> i32 {
+        self.stages
+            .iter()
+            .fold(first_order(self.previous), |sum, stage| {
+                sum.wrapping_add(stage.predict())
+            })
+    }
+
+    pub fn encode_sample(&mut self, sample: i32) -> i32 {
+        let mut value = sample.wrapping_sub(first_order(self.previous));
+        self.previous = sample;
+        for stage in &mut self.stages {
+            let error = value.wrapping_sub(stage.predict());
+            stage.update(value, error);
+            value = error;
+        }
+        value
+    }
+
+    pub fn decode_sample(&mut self, residual: i32) -> i32 {
+        let mut value = residual;
+        for stage in self.stages.iter_mut().rev() {
+            let input = value.wrapping_add(stage.predict());
+            stage.update(input, value);
+            value = input;
+        }
+        let sample = value.wrapping_add(first_order(self.previous));
+        self.previous = sample;
+        sample
+    }
+
+    /// Runs a whole channel through the cascade, returning the residuals.
+    pub fn encode(&mut self, samples: &[i32]) -> Vec<i32> {
+        samples.iter().map(|&s| self.encode_sample(s)).collect()
+    }
+
+    pub fn decode(&mut self, residuals: &[i32]) -> Vec<i32> {
+        residuals.iter().map(|&r| self.decode_sample(r)).collect()
+    }
+}
+
+/// Encoder cost per sample of a cascade of `stages`. Each stage predicts
+/// with one multiply-add per tap; the normalized update adds the input energy
+/// and a multiply, division and clamp per tap, the sign-sign update two
+/// sign products and a clamp per tap. Shifting the history costs a move per tap.
+pub fn cascade_cost(stages: &[(LmsKind, usize)]) -> CostModel {
+    stages.iter().fold(
+        CostModel {
+            multiplies: 1.0,
+            additions: 2.0,
+            state_bytes: 4,
+            buffer_bytes_per_sample: 0.0,
+        },
+        |cost, &(kind, order)| {
+            let order = order as f64;
+            let (multiplies, additions) = match kind {
+                LmsKind::Normalized => (4.0 * order, 7.0 * order),
+                LmsKind::SignSign => (3.0 * order, 6.0 * order),
+            };
+            cost + CostModel {
+                multiplies,
+                additions: additions + 1.0,
+                // Weights and history.
+                state_bytes: order as usize * 8,
+                buffer_bytes_per_sample: 0.0,
+            }
+        },
+    )
+}
+
+fn write_stages(writer: &mut BitWriter, stages: &[(LmsKind, usize)]) {
+    writer.write_bits(stages.len() as u32, 4);
+    for &(kind, order) in stages {
+        writer.write_bit(kind == LmsKind::Normalized);
+        writer.write_bits(order as u32, 9);
+    }
+}
+
+fn read_stages(
+    reader: &mut BitReader,
+) -> Result<Vec<(LmsKind, usize)>, Box<dyn Error + Send + Sync>> {
+    let count = reader.read_bits(4)?;
+    (0..count)
+        .map(|_| {
+            let kind = if reader.read_bit()? {
+                LmsKind::Normalized
+            } else {
+                LmsKind::SignSign
+            };
+            let order = reader.read_bits(9)? as usize;
+            if order == 0 || order > MAX_ORDER {
+                return Err(Box::from(format!("Invalid LMS filter order {}", order)));
+            }
+            Ok((kind, order))
+        })
+        .collect()
+}
+
+fn compress_with(
+    samples: &[i16],
+    spec: &WavSpec,
+    encode_residuals: fn(&mut BitWriter, &[i32]),
+) -> Vec<u8> {
+    let mut writer = BitWriter::with_capacity(BitOrder::Msb, samples.len());
+    write_spec_header(&mut writer, spec, samples.len());
+    write_stages(&mut writer, DEFAULT_STAGES);
+    for channel in deinterleave(samples, spec.channels as usize) {
+        let residuals = LmsCascade::new(DEFAULT_STAGES).encode(&channel);
+        encode_residuals(&mut writer, &residuals);
+    }
+    writer.finish()
+}
+
+fn decompress_with(
+    buffer: &[u8],
+    decode_residuals: ResidualDecoder,
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    let mut reader = BitReader::new(buffer, BitOrder::Msb);
+    let (spec, sample_count) = read_spec_header(&mut reader)?;
+    let frames = sample_count / spec.channels as usize;
+    let stages = read_stages(&mut reader)?;
+
+    let mut channels = Vec::with_capacity(spec.channels as usize);
+    for _ in 0..spec.channels {
+        let residuals = decode_residuals(&mut reader, frames)?;
+        channels.push(LmsCascade::new(&stages).decode(&residuals));
+    }
+    Ok((interleave(&channels), spec))
+}
+
+/// Compresses samples with the LMS cascade followed by partitioned Rice coding.
+pub fn compress_lms_rice(
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into LMS+Rice format...");
+    let compressed = compress_with(samples, spec, encode_residuals);
+    debug!("Finished compressing data into LMS+Rice format");
+    Ok(compressed)
+}
+
+/// Estimated encoder cost of [`compress_lms_rice`].
+pub fn cost_lms_rice() -> CostModel {
+    // The deinterleaved channels and their residuals are held as i32.
+    CostModel::buffer(8.0) + cascade_cost(DEFAULT_STAGES) + encode_residuals_cost()
+}
+
+pub fn decompress_lms_rice(
+    buffer: &[u8],
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from LMS+Rice format...");
+    let decompressed = decompress_with(buffer, decode_residuals)?;
+    debug!("Finished decompressing data from LMS+Rice format");
+    Ok(decompressed)
+}
+
+/// Compresses samples with the LMS cascade followed by adaptive rANS.
+pub fn compress_lms_ans(
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into LMS+ANS format...");
+    let compressed = compress_with(samples, spec, encode_residuals_adaptive);
+    debug!("Finished compressing data into LMS+ANS format");
+    Ok(compressed)
+}
+
+/// Estimated encoder cost of [`compress_lms_ans`].
+pub fn cost_lms_ans() -> CostModel {
+    CostModel::buffer(8.0) + cascade_cost(DEFAULT_STAGES) + encode_residuals_adaptive_cost()
+}
+
+pub fn decompress_lms_ans(
+    buffer: &[u8],
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from LMS+ANS format...");
+    let decompressed = decompress_with(buffer, decode_residuals_adaptive)?;
+    debug!("Finished decompressing data from LMS+ANS format");
+    Ok(decompressed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cascade_roundtrip_and_whitens() {
+        // Strongly correlated input: a slow oscillation plus a little deterministic jitter.
+        let samples: Vec<i32> = (0..20_000)
+            .map(|i: i32| ((i as f64 / 40.0).sin() * 3000.0) as i32 + (i * 7919) % 23)
+            .collect();
+
+        let residuals = LmsCascade::new(DEFAULT_STAGES).encode(&samples);
+        assert_eq!(LmsCascade::new(DEFAULT_STAGES).decode(&residuals), samples);
+
+        let energy = |v: &[i32]| v[1000..].iter().map(|&x| (x as i64).abs()).sum::<i64>();
+        assert!(energy(&residuals) * 10 < energy(&samples));
+    }
+
+    #[test]
+    fn rejects_empty_stage() {
+        let mut writer = BitWriter::new(BitOrder::Msb);
+        write_stages(&mut writer, &[(LmsKind::Normalized, 0)]);
+        let bytes = writer.finish();
+        assert!(read_stages(&mut BitReader::new(&bytes, BitOrder::Msb)).is_err());
+    }
+
+    #[test]
+    fn decode_survives_extreme_residuals() {
+        let residuals = [i32::MAX, i32::MIN, i32::MAX, 0, -1, i32::MIN];
+        let samples = LmsCascade::new(DEFAULT_STAGES).decode(&residuals);
+        assert_eq!(LmsCascade::new(DEFAULT_STAGES).encode(&samples), residuals);
+    }
+}
@@ -1,29 +1,48 @@
-// use crate::brotli_sb::{compress_brotli, decompress_brotli};
-// use crate::flac::{compress_flac, decompress_flac};
-use crate::wav::{read_wav_file, write_wav_file};
-use crate::zstd::{compress_zstd, decompress_zstd};
-use hound::WavSpec;
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
 use indicatif::{ProgressBar, ProgressStyle};
 use rayon::prelude::*;
+use smallbrain::analyze::{analyze_samples, SignalStats, Summary};
+use smallbrain::archive::{pack_directory, unpack_archive};
+use smallbrain::cm::SharedStats;
+use smallbrain::codec::{
+    compress_realtime_packets, decompress_blocks_with, decompress_with, Codec,
+};
+use smallbrain::cost::{pareto_front, CostModel};
+use smallbrain::inspect::{inspect_directory, write_metadata, MetadataFormat};
+use smallbrain::link::{serve, Protocol, Receiver, ServeConfig, ServeReport};
+use smallbrain::model::{
+    load_model, model_to_bytes, reference_model, required_model, sample_files, save_model,
+    train_on_files, DEFAULT_TRAINING_FILES,
+};
+use smallbrain::plot::{plot_directory, plot_wav_file, PlotFormat};
+use smallbrain::realtime::{packet_frames_for_latency, DEFAULT_PACKET_FRAMES, MAX_PACKET_FRAMES};
+use smallbrain::recording::{
+    decompress_recording_with, read_recording, wrap_recording, write_recording, RecordingFormat,
+};
+use smallbrain::resilient::{
+    expected_output, simulate_loss, GapFill, LossConfig, LossReport, ResilientDecoder,
+    ResilientEncoder, DEFAULT_RESET_INTERVAL,
+};
+use smallbrain::spikes::{detect_all, write_spikes_csv, Polarity, SpikeConfig};
+use smallbrain::synth::{generate, SynthConfig};
+use smallbrain::wav::{
+    patch_wav_lengths, read_raw, read_wav, read_wav_file, write_raw, write_wav, write_wav_file,
+    write_wav_samples, write_wav_stream_header, PcmFormat, RawSamples,
+};
 use std::env;
 use std::error::Error;
-use std::fmt::Write as FmtWrite;
+use std::fmt::{Display, Write as FmtWrite};
 use std::fs;
 use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
-use std::path::Path;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tracing::{debug, info, Level};
 use tracing_subscriber::FmtSubscriber;
 
-mod brotli_sb;
-mod flac;
-mod tenbit;
-mod wav;
-mod zlib;
-mod zstd;
-
 fn initialize_tracing(enable_logs: bool) {
     let subscriber = FmtSubscriber::builder()
         .with_max_level(if enable_logs {
@@ -37,16 +56,27 @@ fn initialize_tracing(enable_logs: bool) {
     tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
 }
 
-fn compress(samples: &[i16], spec: &WavSpec) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-    // let flac_data = compress_flac(samples, spec)?;
-    // compress_brotli(&flac_data)
-    compress_zstd(samples, spec)
+/// Returns the value following `--name` on the command line, if present.
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
 }
 
-fn decompress(buffer: &[u8]) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
-    // let flac_data = decompress_brotli(buffer)?;
-    // decompress_flac(&flac_data)
-    decompress_zstd(buffer)
+/// Parses the value following `--name`, if present.
+fn parsed_option<T>(args: &[String], name: &str) -> Result<Option<T>, Box<dyn Error + Send + Sync>>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    option_value(args, name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e| Box::from(format!("Invalid value {} for {}: {}", value, name, e)))
+        })
+        .transpose()
 }
 
 fn print_diff(original: &[u8], decompressed: &[u8]) {
@@ -93,8 +123,26 @@ fn print_diff(original: &[u8], decompressed: &[u8]) {
     println!("{}", diff_output);
 }
 
-fn process_batch(input_dir: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
-    let start = std::time::Instant::now();
+/// Compressed size of one file under one codec, and how long it took to
+/// encode and to decode.
+struct Run {
+    size: u64,
+    encode: Duration,
+    decode: Duration,
+}
+
+/// One file's original size, sample count and spec, and its run under each
+/// selected codec.
+type FileRuns = (u64, usize, WavSpec, Vec<Run>);
+
+fn process_batch(
+    input_dir: &str,
+    codecs: &[Codec],
+    block_size: Option<usize>,
+    latencies: &[f64],
+    model: Option<&SharedStats>,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let start = Instant::now();
     info!("Removing existing data directory...");
     fs::remove_dir_all(input_dir).ok(); // This will ignore the error if the directory does not exist
 
@@ -115,8 +163,8 @@ fn process_batch(input_dir: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
 
     let failed_files = Arc::new(Mutex::new(vec![]));
 
-    // Process each entry in parallel
-    let results: Vec<Result<(u64, u64), Box<dyn Error + Send + Sync>>> = entries
+    // Process each entry in parallel, running every selected codec on it
+    let results: Vec<Result<FileRuns, Box<dyn Error + Send + Sync>>> = entries
         .par_iter()
         .map(|entry| {
             let path = entry.path();
@@ -128,34 +176,104 @@ fn process_batch(input_dir: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
                 debug!("Processing {}", file_path);
 
                 let (samples, spec) = read_wav_file(file_path)?;
-                let compressed_data = compress(&samples, &spec)?;
-                let (decompressed_samples, decompressed_spec) = decompress(&compressed_data)?;
+                let original_contents = fs::read(file_path)?;
+                let file_size = fs::metadata(file_path)?.len();
 
-                write_wav_file(
-                    &decompressed_file_path,
-                    &decompressed_samples,
-                    decompressed_spec,
-                )?;
+                // Checks that `compressed_data` decodes back to the original file
+                // and returns its size and the decode time.
+                let verify =
+                    |label: &str,
+                     compressed_data: Vec<u8>|
+                     -> Result<(u64, Duration), Box<dyn Error + Send + Sync>> {
+                        let decode_start = Instant::now();
+                        let (decompressed_samples, decompressed_spec) =
+                            decompress_with(&compressed_data, model)?;
+                        let decode = decode_start.elapsed();
 
-                let original_contents = fs::read(file_path)?;
-                let decompressed_contents = fs::read(&decompressed_file_path)?;
+                        write_wav_file(
+                            &decompressed_file_path,
+                            &decompressed_samples,
+                            decompressed_spec,
+                        )?;
 
-                let file_size = fs::metadata(file_path)?.len();
-                let compressed_size = compressed_data.len() as u64;
-
-                if original_contents == decompressed_contents {
-                    debug!(
-                        "{} losslessly compressed from {} bytes to {} bytes",
-                        file_path, file_size, compressed_size
-                    );
-                    Ok((file_size, compressed_size))
-                } else {
-                    print_diff(&original_contents, &decompressed_contents);
-                    Err(Box::from(format!(
-                        "ERROR: {} and {} are different.",
-                        file_path, decompressed_file_path
-                    )))
+                        let decompressed_contents = fs::read(&decompressed_file_path)?;
+                        let compressed_size = compressed_data.len() as u64;
+
+                        if original_contents == decompressed_contents {
+                            debug!(
+                                "{} losslessly compressed with {} from {} bytes to {} bytes",
+                                file_path, label, file_size, compressed_size
+                            );
+                            Ok((compressed_size, decode))
+                        } else {
+                            print_diff(&original_contents, &decompressed_contents);
+                            Err(Box::from(format!(
+                                "ERROR: {} and {} are different with codec {}.",
+                                file_path, decompressed_file_path, label
+                            )))
+                        }
+                    };
+
+                let mut runs = Vec::with_capacity(codecs.len() + latencies.len() + 1);
+                for &codec in codecs {
+                    let encode_start = Instant::now();
+                    let compressed_data = match block_size {
+                        Some(frames) => codec.compress_blocked(&samples, &spec, frames)?,
+                        None => codec.compress(&samples, &spec)?,
+                    };
+                    let encode = encode_start.elapsed();
+                    let (size, decode) = verify(codec.name(), compressed_data)?;
+                    runs.push(Run {
+                        size,
+                        encode,
+                        decode,
+                    });
+                }
+
+                // Realtime streams at each latency budget, then with the longest
+                // packets as the reference the latency cost is measured against.
+                if !latencies.is_empty() {
+                    let packet_lengths = latencies
+                        .iter()
+                        .map(|&ms| packet_frames_for_latency(ms, spec.sample_rate))
+                        .chain([MAX_PACKET_FRAMES]);
+                    for frames in packet_lengths {
+                        let encode_start = Instant::now();
+                        let compressed_data = compress_realtime_packets(&samples, &spec, frames)?;
+                        let encode = encode_start.elapsed();
+                        let label = format!("realtime ({} frame packets)", frames);
+                        let (size, decode) = verify(&label, compressed_data)?;
+                        runs.push(Run {
+                            size,
+                            encode,
+                            decode,
+                        });
+                    }
                 }
+
+                // Codecs that can use the shared model, coded against it.
+                if let Some(model) = model {
+                    for &codec in codecs.iter().filter(|codec| codec.uses_shared_stats()) {
+                        let encode_start = Instant::now();
+                        let stream = match block_size {
+                            Some(frames) => {
+                                codec.compress_blocked_with(&samples, &spec, frames, Some(model))?
+                            }
+                            None => codec.compress_with(&samples, &spec, Some(model))?,
+                        };
+                        let compressed_data = reference_model(model, &stream);
+                        let encode = encode_start.elapsed();
+                        let label = format!("{} (shared model)", codec.name());
+                        let (size, decode) = verify(&label, compressed_data)?;
+                        runs.push(Run {
+                            size,
+                            encode,
+                            decode,
+                        });
+                    }
+                }
+
+                Ok((file_size, samples.len(), spec, runs))
             })();
 
             bar.inc(1);
@@ -171,12 +289,6 @@ fn process_batch(input_dir: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
 
     bar.finish_and_clear();
 
-    // Aggregate results
-    let (total_size_raw, total_size_compressed) = results
-        .iter()
-        .filter_map(|res| res.as_ref().ok())
-        .fold((0u64, 0u64), |acc, &(fs, cs)| (acc.0 + fs, acc.1 + cs));
-
     if results.iter().any(Result::is_err) {
         for err in results.iter().filter_map(|res| res.as_ref().err()) {
             eprintln!("{}", err);
@@ -193,24 +305,394 @@ fn process_batch(input_dir: &str) -> Result<(), Box<dyn Error + Send + Sync>> {
         return Err(Box::from("Some files failed to be processed."));
     }
 
-    let compression_ratio = total_size_raw as f64 / total_size_compressed as f64;
+    // Aggregate results
+    let successes: Vec<_> = results.iter().filter_map(|res| res.as_ref().ok()).collect();
+    let total_size_raw: u64 = successes.iter().map(|(fs, ..)| fs).sum();
+    let total_samples: usize = successes.iter().map(|(_, samples, ..)| samples).sum();
+    let total_size = |i: usize| -> u64 { successes.iter().map(|(.., runs)| runs[i].size).sum() };
+    // Samples per second of one thread: the files ran in parallel, so each
+    // codec's time is summed over them rather than taken from the wall clock.
+    let throughput = |i: usize, time: fn(&Run) -> Duration| -> f64 {
+        let seconds: f64 = successes
+            .iter()
+            .map(|(.., runs)| time(&runs[i]).as_secs_f64())
+            .sum();
+        total_samples as f64 / seconds
+    };
 
     info!("All recordings successfully compressed.");
     info!("Original size (bytes): {}", total_size_raw);
-    info!("Compressed size (bytes): {}", total_size_compressed);
-    info!("Compression ratio: {:.2}", compression_ratio);
+    for (i, codec) in codecs.iter().enumerate() {
+        let total_size_compressed = total_size(i);
+        let compression_ratio = total_size_raw as f64 / total_size_compressed as f64;
+        info!(
+            "{:<12} compressed size (bytes): {:>10}, compression ratio: {:.2}, encode: {:>7.2} Msamples/s, decode: {:>7.2} Msamples/s",
+            codec.name(),
+            total_size_compressed,
+            compression_ratio,
+            throughput(i, |run| run.encode) / 1e6,
+            throughput(i, |run| run.decode) / 1e6
+        );
+    }
+
+    // Estimated encoder cost for the largest recording, with the codecs no
+    // other codec beats on both ratio and operations marked.
+    if let Some(&&(_, samples, spec, _)) = successes.iter().max_by_key(|(_, samples, ..)| samples) {
+        let costs: Vec<CostModel> = codecs.iter().map(|codec| codec.cost(&spec)).collect();
+        let points: Vec<(f64, f64)> = costs
+            .iter()
+            .enumerate()
+            .map(|(i, cost)| {
+                (
+                    total_size_raw as f64 / total_size(i) as f64,
+                    cost.operations(),
+                )
+            })
+            .collect();
+        info!(
+            "Estimated encoder cost ({} samples, * = Pareto-optimal):",
+            samples
+        );
+        for (((codec, cost), &(ratio, _)), optimal) in codecs
+            .iter()
+            .zip(&costs)
+            .zip(&points)
+            .zip(pareto_front(&points))
+        {
+            info!(
+                "{} {:<12} ratio: {:.2}, {}, peak memory: {:.1} KiB",
+                if optimal { '*' } else { ' ' },
+                codec.name(),
+                ratio,
+                cost,
+                cost.peak_memory(samples) as f64 / 1024.0
+            );
+        }
+    }
+
+    if !latencies.is_empty() {
+        let reference = total_size(codecs.len() + latencies.len());
+        info!(
+            "realtime reference ({} frame packets) compressed size (bytes): {:>10}, compression ratio: {:.2}",
+            MAX_PACKET_FRAMES,
+            reference,
+            total_size_raw as f64 / reference as f64
+        );
+        for (i, latency) in latencies.iter().enumerate() {
+            let size = total_size(codecs.len() + i);
+            info!(
+                "realtime {:>6.2} ms latency compressed size (bytes): {:>10}, compression ratio: {:.2}, cost: {:+.2}%",
+                latency,
+                size,
+                total_size_raw as f64 / size as f64,
+                (size as f64 / reference as f64 - 1.0) * 100.0
+            );
+        }
+    }
+
+    // The model is shared by the whole corpus: amortized, it costs nothing
+    // per file; counted, it is paid once against the total.
+    if let Some(model) = model {
+        let model_size = model_to_bytes(model)?.len() as u64;
+        let first = codecs.len() + latencies.len() + usize::from(!latencies.is_empty());
+        for (i, codec) in codecs
+            .iter()
+            .filter(|codec| codec.uses_shared_stats())
+            .enumerate()
+        {
+            let size = total_size(first + i);
+            info!(
+                "{:<12} with shared model {:016x}: compressed size (bytes): {:>10}, compression ratio: {:.2} amortized, {:.2} with the model's {} bytes counted",
+                codec.name(),
+                model.hash(),
+                size,
+                total_size_raw as f64 / size as f64,
+                total_size_raw as f64 / (size + model_size) as f64,
+                model_size
+            );
+        }
+    }
     info!("Time taken: {:.2?}", start.elapsed());
 
     Ok(())
 }
 
+/// `input` itself, or every WAV directly inside it when it is a directory, sorted.
+fn wav_paths(input: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let mut paths = if input.is_dir() {
+        fs::read_dir(input)?
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("wav"))
+            .collect()
+    } else {
+        vec![input.to_path_buf()]
+    };
+    paths.sort();
+    Ok(paths)
+}
+
+/// Prints compression statistics for one WAV or every WAV in a directory,
+/// followed by the totals.
+fn analyze(input: &Path) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let paths = wav_paths(input)?;
+    let stats = paths
+        .par_iter()
+        .map(|path| {
+            let file_path = path.to_str().ok_or("Input path is not valid UTF-8")?;
+            let (samples, spec) = read_wav_file(file_path)?;
+            Ok(analyze_samples(&samples, &spec))
+        })
+        .collect::<Result<Vec<SignalStats>, Box<dyn Error + Send + Sync>>>()?;
+
+    for (path, stats) in paths.iter().zip(&stats) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        println!("{}: {}", name, stats);
+    }
+    println!("Total: {}", Summary::from_stats(&stats));
+    Ok(())
+}
+
+/// Outcome of streaming one file over the simulated link.
+struct LossRun {
+    samples: usize,
+    sent_bytes: u64,
+    frames_sent: usize,
+    report: LossReport,
+    decoded: Vec<i16>,
+    spec: WavSpec,
+}
+
+/// Streams each WAV through the resilient encoder, drops and damages frames
+/// per `config`, decodes what arrives and checks that everything outside the
+/// reported gaps matches the original. Prints per-file and total results.
+fn simulate_loss_files(
+    input: &Path,
+    config: &LossConfig,
+    latency: Option<f64>,
+    reset_interval: u64,
+    fill: GapFill,
+) -> Result<Vec<LossRun>, Box<dyn Error + Send + Sync>> {
+    let paths = wav_paths(input)?;
+    let runs = paths
+        .par_iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let file_path = path.to_str().ok_or("Input path is not valid UTF-8")?;
+            let (samples, spec) = read_wav_file(file_path)?;
+            let packet_frames = latency.map_or(DEFAULT_PACKET_FRAMES, |ms| {
+                packet_frames_for_latency(ms, spec.sample_rate)
+            });
+            let mut encoder = ResilientEncoder::new(&spec, packet_frames, reset_interval)?;
+            let mut frames = encoder.push(&samples)?;
+            frames.extend(encoder.flush()?);
+
+            let link = LossConfig {
+                seed: config.seed.wrapping_add(i as u64),
+                ..config.clone()
+            };
+            let mut decoder = ResilientDecoder::new(fill);
+            let mut decoded = Vec::with_capacity(samples.len());
+            for frame in simulate_loss(&frames, &link) {
+                decoded.extend(decoder.receive(&frame)?);
+            }
+            let report = decoder.finish();
+
+            let expected = expected_output(&samples, spec.channels as usize, &report.gaps, fill);
+            if expected.get(..decoded.len()) != Some(&decoded[..]) {
+                return Err(Box::from(format!(
+                    "{}: decoded samples differ from the original outside the reported gaps",
+                    file_path
+                )));
+            }
+            Ok(LossRun {
+                samples: samples.len(),
+                sent_bytes: frames.iter().map(|f| f.len() as u64).sum(),
+                frames_sent: frames.len(),
+                report,
+                decoded,
+                spec,
+            })
+        })
+        .collect::<Result<Vec<_>, Box<dyn Error + Send + Sync>>>()?;
+
+    for (path, run) in paths.iter().zip(&runs) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        println!(
+            "{}: {} frames, {} bytes (ratio {:.2}), {}",
+            name,
+            run.frames_sent,
+            run.sent_bytes,
+            run.samples as f64 * 2.0 / run.sent_bytes.max(1) as f64,
+            run.report
+        );
+    }
+    let samples: usize = runs.iter().map(|r| r.samples).sum();
+    let sent: u64 = runs.iter().map(|r| r.sent_bytes).sum();
+    let frames_sent: usize = runs.iter().map(|r| r.frames_sent).sum();
+    let sum =
+        |field: fn(&LossReport) -> u64| -> u64 { runs.iter().map(|r| field(&r.report)).sum() };
+    let channel_frames: u64 = runs
+        .iter()
+        .map(|r| (r.samples / r.spec.channels.max(1) as usize) as u64)
+        .sum();
+    println!(
+        "Total: files {}, frames {}, {} bytes (ratio {:.2}), received {}, lost {}, corrupt {}, \
+         undecodable {}, gaps {}, recovered {:.2}% of frames, all decoded samples verified",
+        runs.len(),
+        frames_sent,
+        sent,
+        samples as f64 * 2.0 / sent.max(1) as f64,
+        sum(|r| r.received),
+        sum(|r| r.lost),
+        sum(|r| r.corrupt),
+        sum(|r| r.undecodable),
+        sum(|r| r.gaps.len() as u64),
+        sum(|r| r.frames_decoded) as f64 * 100.0 / channel_frames.max(1) as f64
+    );
+    Ok(runs)
+}
+
+/// Builds a synthetic recording config from the `synth` options.
+fn synth_config(args: &[String]) -> Result<SynthConfig, Box<dyn Error + Send + Sync>> {
+    let mut config = SynthConfig::default();
+    if let Some(seed) = parsed_option(args, "--seed")? {
+        config.seed = seed;
+    }
+    if let Some(rate) = parsed_option(args, "--rate")? {
+        config.sample_rate = rate;
+    }
+    if let Some(channels) = parsed_option(args, "--channels")? {
+        config.channels = channels;
+    }
+    let seconds = parsed_option(args, "--seconds")?.unwrap_or(5.0f64);
+    config.frames = (seconds * config.sample_rate as f64) as usize;
+    if let Some(step) = parsed_option(args, "--step")? {
+        config.step = step;
+    }
+    if let Some(offset) = parsed_option(args, "--offset")? {
+        config.offset = offset;
+    }
+    if let Some(noise) = parsed_option(args, "--noise")? {
+        config.white_noise = noise;
+    }
+    if let Some(noise) = parsed_option(args, "--colored-noise")? {
+        config.colored_noise = noise;
+    }
+    if let Some(rate) = parsed_option(args, "--spike-rate")? {
+        config.spike_rate = rate;
+    }
+    if let Some(units) = parsed_option(args, "--units")? {
+        config.units = units;
+    }
+    if let Some(drift) = parsed_option(args, "--drift")? {
+        config.drift = drift;
+    }
+    if config.channels == 0 || config.sample_rate == 0 || config.step <= 0 {
+        return Err(Box::from("Channels, sample rate and step must be positive"));
+    }
+    Ok(config)
+}
+
+/// Seconds without a frame after which `receive` considers the stream over.
+const DEFAULT_IDLE_TIMEOUT: f64 = 2.0;
+
+/// Spec of headerless PCM, for `serve` from stdin and `compress
+/// --input-format raw`, given by `--rate`, `--channels` (default 1) and
+/// `--bits` (default 16). The rate has no sensible default, so it is required.
+/// Only 8 and 16 bits come back out as WAV, so no other depth is taken in.
+fn raw_spec(args: &[String]) -> Result<WavSpec, Box<dyn Error + Send + Sync>> {
+    let spec = WavSpec {
+        sample_rate: parsed_option(args, "--rate")?.ok_or("Raw PCM input needs --rate")?,
+        channels: parsed_option(args, "--channels")?.unwrap_or(1),
+        bits_per_sample: parsed_option(args, "--bits")?.unwrap_or(16),
+        sample_format: SampleFormat::Int,
+    };
+    if spec.sample_rate == 0 || spec.channels == 0 {
+        return Err(Box::from("Sample rate and channels must be positive"));
+    }
+    if !matches!(spec.bits_per_sample, 8 | 16) {
+        return Err(Box::from(format!(
+            "Unsupported {} bits per sample (raw PCM is 8 or 16 bits)",
+            spec.bits_per_sample
+        )));
+    }
+    Ok(spec)
+}
+
+/// What [`write_decoded`] wrote: the spec, the number of samples and the
+/// output.
+type Decoded<W> = (WavSpec, usize, BufWriter<W>);
+
+/// Decodes `buffer` as WAV or raw PCM into the output `open` returns, one
+/// block at a time. The output is only opened once the first block has
+/// decoded into something it can hold. A WAV header carries placeholder
+/// lengths, for the caller to fill in if the output can seek.
+fn write_decoded<W, F>(
+    buffer: &[u8],
+    model: Option<&SharedStats>,
+    format: PcmFormat,
+    open: F,
+) -> Result<Decoded<W>, Box<dyn Error + Send + Sync>>
+where
+    W: Write,
+    F: FnOnce() -> io::Result<W>,
+{
+    let mut open = Some(open);
+    let mut decoded: Option<Decoded<W>> = None;
+    decompress_blocks_with(buffer, model, |samples, spec| {
+        let (_, count, writer) = match (&mut decoded, open.take()) {
+            (Some(decoded), _) => decoded,
+            (None, Some(open)) => {
+                let mut header = Vec::new();
+                if format == PcmFormat::Wav {
+                    write_wav_stream_header(&mut header, spec)?;
+                }
+                let mut writer = BufWriter::new(open()?);
+                writer.write_all(&header)?;
+                decoded.insert((*spec, 0, writer))
+            }
+            (None, None) => return Err(Box::from("Output could not be opened")),
+        };
+        *count += samples.len();
+        match format {
+            PcmFormat::Wav => write_wav_samples(writer, samples, spec),
+            PcmFormat::Raw => write_raw(writer, samples),
+        }
+    })?;
+    let (spec, count, mut writer) = decoded.ok_or("Stream holds no blocks")?;
+    writer.flush()?;
+    Ok((spec, count, writer))
+}
+
+/// Runs `serve` with packets sized for `latency` milliseconds, if given.
+fn serve_samples<I>(
+    samples: I,
+    spec: &WavSpec,
+    address: &str,
+    latency: Option<f64>,
+    mut config: ServeConfig,
+) -> Result<ServeReport, Box<dyn Error + Send + Sync>>
+where
+    I: IntoIterator<Item = Result<i16, Box<dyn Error + Send + Sync>>>,
+{
+    if let Some(ms) = latency {
+        config.packet_frames = packet_frames_for_latency(ms, spec.sample_rate);
+    }
+    info!(
+        "Streaming {} Hz, {} channel audio to {} in {} frame packets",
+        spec.sample_rate, spec.channels, address, config.packet_frames
+    );
+    serve(samples, spec, address, &config)
+}
+
 fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     let args: Vec<String> = env::args().collect();
 
     if args.len() < 2 {
         eprintln!(
-            "Usage:\n  To compress:   {} compress <input_wav> <output_file>\n  To decompress: {} decompress <input_file> <output_wav>\n  To process batch: {} process_batch <input_dir> [--enable-logs]",
-            args[0], args[0], args[0]
+            "Usage:\n  To compress:   {} compress <input_file|-> <output_file|-> [--codec <name>] [--block-size <frames> | --latency <ms>] [--input-format <wav|raw|rhd|rhs|openephys|nsx>] [--rate <hz> [--channels <n>] [--bits <8|16>]] [--model <file>]\n  To decompress: {} decompress <input_file|-> <output_file|-> [--output-format <wav|raw>] [--model <file>]\n  To process batch: {} process_batch <input_dir> [--codec <name,...|all>] [--block-size <frames>] [--latency <ms,...>] [--model <file>] [--enable-logs]\n  To export spikes: {} spikes <input_wav> <output_csv> [--threshold <k>] [--polarity <negative|positive|both>]\n  To plot:       {} plot <input_wav|input_dir> <output_file|output_dir> [--format <png|svg>]\n  To analyze:    {} analyze <input_wav|input_dir>\n  To inspect:    {} inspect <input_dir> [--format <text|json|csv>] [--output <file>]\n  To synthesize: {} synth <output_wav|output_dir> [--count <n>] [--seed <n>] [--rate <hz>] [--channels <n>] [--seconds <s>] [--step <n>] [--offset <n>] [--noise <sd>] [--colored-noise <sd>] [--spike-rate <hz>] [--units <n>] [--drift <per_s>]\n  To simulate loss: {} simulate_loss <input_wav|input_dir> [--loss <p>] [--corrupt <p>] [--seed <n>] [--latency <ms>] [--reset-interval <packets>] [--fill <skip|zero|hold>] [--output <wav>]\n  To stream:     {} serve <input_wav|-> <address> [--protocol <udp|tcp>] [--latency <ms>] [--reset-interval <packets>] [--speed <x>] [--rate <hz>] [--channels <n>] [--bits <8|16>]\n  To receive:    {} receive <address> <output_wav> [--protocol <udp|tcp>] [--fill <skip|zero|hold>] [--timeout <s>]\n  To pack:       {} pack <input_dir> <archive> [--codec <name>]\n  To unpack:     {} unpack <archive> <output_dir> [--file <path>]\n  To train:      {} train <input_dir> <model_file> [--files <n>]",
+            args[0], args[0], args[0], args[0], args[0], args[0], args[0], args[0], args[0], args[0], args[0], args[0], args[0], args[0]
         );
         std::process::exit(1);
     }
@@ -224,39 +706,486 @@ fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
     match command.as_str() {
         "compress" => {
             if args.len() < 4 {
-                eprintln!("Usage: {} compress <input_wav> <output_file>", args[0]);
+                eprintln!(
+                    "Usage: {} compress <input_file|-> <output_file|-> [--codec <name>] [--block-size <frames> | --latency <ms>] [--input-format <wav|raw|rhd|rhs|openephys|nsx>] [--rate <hz> [--channels <n>] [--bits <8|16>]] [--model <file>]",
+                    args[0]
+                );
                 std::process::exit(1);
             }
             let input_path = &args[2];
             let output_path = &args[3];
-            let (samples, spec) = read_wav_file(input_path)?;
-            let compressed_data = compress(&samples, &spec)?;
-            let mut file = BufWriter::new(File::create(output_path)?);
-            file.write_all(&compressed_data)?;
+            let latency: Option<f64> = parsed_option(&args, "--latency")?;
+            let default_codec = if latency.is_some() {
+                "realtime"
+            } else {
+                "zstd"
+            };
+            let codec = Codec::from_name(option_value(&args, "--codec").unwrap_or(default_codec))?;
+            let model = option_value(&args, "--model")
+                .map(|path| load_model(Path::new(path)))
+                .transpose()?;
+            if model.is_some() && !codec.uses_shared_stats() {
+                return Err(Box::from(format!(
+                    "--model requires a codec that uses shared statistics (cm), not {}",
+                    codec.name()
+                )));
+            }
+            // Acquisition formats are recognised by extension unless named.
+            let input_format = option_value(&args, "--input-format");
+            let recording_format = match input_format {
+                Some("wav" | "raw") => None,
+                Some(name) => Some(RecordingFormat::from_name(name).map_err(|_| {
+                    format!(
+                        "Unknown input format: {} (expected wav, raw, rhd, rhs, openephys or nsx)",
+                        name
+                    )
+                })?),
+                None => RecordingFormat::from_path(Path::new(input_path)),
+            };
+            let (samples, spec, original) = match (recording_format, input_path.as_str()) {
+                (Some(_), "-") => {
+                    return Err(Box::from(
+                        "Acquisition formats are read from files, not stdin",
+                    ))
+                }
+                (Some(format), path) => {
+                    let recording = read_recording(Path::new(path), format)?;
+                    let original = Some((recording.format, recording.metadata));
+                    (recording.samples, recording.spec, original)
+                }
+                (None, path) => {
+                    let (samples, spec) =
+                        match (PcmFormat::from_name(input_format.unwrap_or("wav"))?, path) {
+                            (PcmFormat::Wav, "-") => read_wav(io::stdin().lock())?,
+                            (PcmFormat::Wav, path) => read_wav_file(path)?,
+                            (PcmFormat::Raw, "-") => {
+                                let spec = raw_spec(&args)?;
+                                (read_raw(io::stdin().lock(), &spec)?, spec)
+                            }
+                            (PcmFormat::Raw, path) => {
+                                let spec = raw_spec(&args)?;
+                                (read_raw(BufReader::new(File::open(path)?), &spec)?, spec)
+                            }
+                        };
+                    (samples, spec, None)
+                }
+            };
+            let compressed_data = match (parsed_option(&args, "--block-size")?, latency) {
+                (Some(_), Some(_)) => {
+                    return Err(Box::from("--block-size and --latency cannot be combined"))
+                }
+                (None, Some(_)) if codec != Codec::Realtime => {
+                    return Err(Box::from("--latency requires the realtime codec"))
+                }
+                (None, Some(ms)) => compress_realtime_packets(
+                    &samples,
+                    &spec,
+                    packet_frames_for_latency(ms, spec.sample_rate),
+                )?,
+                (Some(frames), None) => {
+                    codec.compress_blocked_with(&samples, &spec, frames, model.as_ref())?
+                }
+                (None, None) => codec.compress_with(&samples, &spec, model.as_ref())?,
+            };
+            let compressed_data = match &model {
+                Some(model) => reference_model(model, &compressed_data),
+                None => compressed_data,
+            };
+            let compressed_data = match original {
+                Some((format, metadata)) => wrap_recording(format, &metadata, &compressed_data)?,
+                None => compressed_data,
+            };
+            match output_path.as_str() {
+                "-" => {
+                    let mut stdout = io::stdout().lock();
+                    stdout.write_all(&compressed_data)?;
+                    stdout.flush()?;
+                }
+                path => BufWriter::new(File::create(path)?).write_all(&compressed_data)?,
+            }
         }
         "decompress" => {
             if args.len() < 4 {
-                eprintln!("Usage: {} decompress <input_file> <output_wav>", args[0]);
+                eprintln!(
+                    "Usage: {} decompress <input_file|-> <output_file|-> [--output-format <wav|raw>] [--model <file>]",
+                    args[0]
+                );
                 std::process::exit(1);
             }
             let input_path = &args[2];
             let output_path = &args[3];
-            let mut file = BufReader::new(File::open(input_path)?);
             let mut buffer = Vec::new();
-            file.read_to_end(&mut buffer)?;
-            let (samples, spec) = decompress(&buffer)?;
-            write_wav_file(output_path, &samples, spec)?;
+            match input_path.as_str() {
+                "-" => io::stdin().lock().read_to_end(&mut buffer)?,
+                path => BufReader::new(File::open(path)?).read_to_end(&mut buffer)?,
+            };
+            let model = option_value(&args, "--model")
+                .map(|path| load_model(Path::new(path)))
+                .transpose()?;
+            if let (Some(hash), None) = (required_model(&buffer)?, &model) {
+                return Err(Box::from(format!(
+                    "{} was compressed against shared model {:016x}; pass it with --model",
+                    input_path, hash
+                )));
+            }
+            // Recordings come back in their own format unless asked otherwise.
+            let output_format = option_value(&args, "--output-format");
+            match (
+                decompress_recording_with(&buffer, model.as_ref())?,
+                output_format,
+            ) {
+                (Some(recording), None) => match output_path.as_str() {
+                    "-" => write_recording(io::stdout().lock(), &recording)?,
+                    path => write_recording(BufWriter::new(File::create(path)?), &recording)?,
+                },
+                (Some(recording), output_format) => {
+                    let (samples, spec) = (recording.samples, recording.spec);
+                    let format = PcmFormat::from_name(output_format.unwrap_or("wav"))?;
+                    match (format, output_path.as_str()) {
+                        (PcmFormat::Wav, "-") => write_wav(io::stdout().lock(), &samples, spec)?,
+                        (PcmFormat::Wav, path) => write_wav_file(path, &samples, spec)?,
+                        (PcmFormat::Raw, "-") => write_raw(io::stdout().lock(), &samples)?,
+                        (PcmFormat::Raw, path) => write_raw(File::create(path)?, &samples)?,
+                    }
+                    if format == PcmFormat::Raw {
+                        info!(
+                            "Wrote raw PCM: {} Hz, {} channels, {} bits",
+                            spec.sample_rate, spec.channels, spec.bits_per_sample
+                        );
+                    }
+                }
+                (None, output_format) => {
+                    // Blocks are written as they decode. A WAV file gets its
+                    // lengths filled in at the end; stdout keeps the
+                    // placeholders of a streamed WAV.
+                    let format = PcmFormat::from_name(output_format.unwrap_or("wav"))?;
+                    let (spec, sample_count) = match output_path.as_str() {
+                        "-" => {
+                            let stdout = || Ok(io::stdout().lock());
+                            let (spec, count, _) =
+                                write_decoded(&buffer, model.as_ref(), format, stdout)?;
+                            (spec, count)
+                        }
+                        path => {
+                            // A failed decode leaves no partial file behind.
+                            let mut created = false;
+                            let create = || {
+                                created = true;
+                                File::create(path)
+                            };
+                            let result = write_decoded(&buffer, model.as_ref(), format, create)
+                                .and_then(|(spec, count, mut file)| {
+                                    if format == PcmFormat::Wav {
+                                        patch_wav_lengths(&mut file, &spec, count)?;
+                                    }
+                                    Ok((spec, count))
+                                });
+                            if result.is_err() && created {
+                                let _ = fs::remove_file(path);
+                            }
+                            result?
+                        }
+                    };
+                    debug!("Decoded {} samples", sample_count);
+                    if format == PcmFormat::Raw {
+                        info!(
+                            "Wrote raw PCM: {} Hz, {} channels, {} bits",
+                            spec.sample_rate, spec.channels, spec.bits_per_sample
+                        );
+                    }
+                }
+            }
         }
         "process_batch" => {
             if args.len() < 3 {
                 eprintln!(
-                    "Usage: {} process_batch <input_dir> [--enable-logs]",
+                    "Usage: {} process_batch <input_dir> [--codec <name,...|all>] [--block-size <frames>] [--latency <ms,...>] [--model <file>] [--enable-logs]",
                     args[0]
                 );
                 std::process::exit(1);
             }
             let input_dir = &args[2];
-            process_batch(input_dir)?;
+            let codecs = Codec::parse_list(option_value(&args, "--codec").unwrap_or("zstd"))?;
+            let latencies = match option_value(&args, "--latency") {
+                Some(list) => list
+                    .split(',')
+                    .map(|ms| {
+                        ms.parse::<f64>()
+                            .map_err(|e| format!("Invalid value for --latency: {} ({})", ms, e))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => Vec::new(),
+            };
+            let model = option_value(&args, "--model")
+                .map(|path| load_model(Path::new(path)))
+                .transpose()?;
+            process_batch(
+                input_dir,
+                &codecs,
+                parsed_option(&args, "--block-size")?,
+                &latencies,
+                model.as_ref(),
+            )?;
+        }
+        "spikes" => {
+            if args.len() < 4 {
+                eprintln!(
+                    "Usage: {} spikes <input_wav> <output_csv> [--threshold <k>] [--polarity <negative|positive|both>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let input_path = &args[2];
+            let output_path = &args[3];
+            let mut config = SpikeConfig::default();
+            if let Some(threshold) = parsed_option(&args, "--threshold")? {
+                config.threshold = threshold;
+            }
+            if let Some(polarity) = option_value(&args, "--polarity") {
+                config.polarity = Polarity::from_name(polarity)?;
+            }
+            let (samples, spec) = read_wav_file(input_path)?;
+            let spikes = detect_all(&samples, &spec, &config);
+            let mut file = BufWriter::new(File::create(output_path)?);
+            write_spikes_csv(&mut file, &spikes, spec.sample_rate)?;
+            info!("Exported {} spikes to {}", spikes.len(), output_path);
+        }
+        "plot" => {
+            if args.len() < 4 {
+                eprintln!(
+                    "Usage: {} plot <input_wav|input_dir> <output_file|output_dir> [--format <png|svg>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let input_path = Path::new(&args[2]);
+            let output_path = Path::new(&args[3]);
+            if input_path.is_dir() {
+                let format =
+                    PlotFormat::from_name(option_value(&args, "--format").unwrap_or("png"))?;
+                plot_directory(input_path, output_path, format)?;
+            } else {
+                let format = match option_value(&args, "--format") {
+                    Some(name) => PlotFormat::from_name(name)?,
+                    None => PlotFormat::from_path(output_path),
+                };
+                plot_wav_file(input_path, output_path, format)?;
+            }
+        }
+        "analyze" => {
+            if args.len() < 3 {
+                eprintln!("Usage: {} analyze <input_wav|input_dir>", args[0]);
+                std::process::exit(1);
+            }
+            analyze(Path::new(&args[2]))?;
+        }
+        "inspect" => {
+            if args.len() < 3 {
+                eprintln!(
+                    "Usage: {} inspect <input_dir> [--format <text|json|csv>] [--output <file>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let format =
+                MetadataFormat::from_name(option_value(&args, "--format").unwrap_or("text"))?;
+            let files = inspect_directory(Path::new(&args[2]))?;
+            match option_value(&args, "--output") {
+                Some(output_path) => {
+                    let mut file = BufWriter::new(File::create(output_path)?);
+                    write_metadata(&mut file, &files, format)?;
+                }
+                None => write_metadata(&mut std::io::stdout().lock(), &files, format)?,
+            }
+            let deviating = files.iter().filter(|f| !f.deviations.is_empty()).count();
+            info!(
+                "Inspected {} files, {} deviate from the majority spec",
+                files.len(),
+                deviating
+            );
+        }
+        "synth" => {
+            if args.len() < 3 {
+                eprintln!(
+                    "Usage: {} synth <output_wav|output_dir> [--count <n>] [--seed <n>] [--rate <hz>] [--channels <n>] [--seconds <s>] [--step <n>] [--offset <n>] [--noise <sd>] [--colored-noise <sd>] [--spike-rate <hz>] [--units <n>] [--drift <per_s>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let config = synth_config(&args)?;
+            match parsed_option::<u64>(&args, "--count")? {
+                Some(count) => {
+                    let output_dir = Path::new(&args[2]);
+                    fs::create_dir_all(output_dir)?;
+                    for i in 0..count {
+                        let config = SynthConfig {
+                            seed: config.seed.wrapping_add(i),
+                            ..config.clone()
+                        };
+                        let (samples, spec) = generate(&config);
+                        let path = output_dir.join(format!("synth-{:03}.wav", i));
+                        write_wav_file(
+                            path.to_str().ok_or("Output path is not valid UTF-8")?,
+                            &samples,
+                            spec,
+                        )?;
+                    }
+                    info!("Wrote {} recordings to {}", count, output_dir.display());
+                }
+                None => {
+                    let (samples, spec) = generate(&config);
+                    write_wav_file(&args[2], &samples, spec)?;
+                }
+            }
+        }
+        "simulate_loss" => {
+            if args.len() < 3 {
+                eprintln!(
+                    "Usage: {} simulate_loss <input_wav|input_dir> [--loss <p>] [--corrupt <p>] [--seed <n>] [--latency <ms>] [--reset-interval <packets>] [--fill <skip|zero|hold>] [--output <wav>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let config = LossConfig {
+                loss: parsed_option(&args, "--loss")?.unwrap_or(0.01),
+                corruption: parsed_option(&args, "--corrupt")?.unwrap_or(0.0),
+                seed: parsed_option(&args, "--seed")?.unwrap_or(0),
+            };
+            let latency = parsed_option(&args, "--latency")?;
+            let reset_interval =
+                parsed_option(&args, "--reset-interval")?.unwrap_or(DEFAULT_RESET_INTERVAL);
+            let fill = GapFill::from_name(option_value(&args, "--fill").unwrap_or("hold"))?;
+            let input = Path::new(&args[2]);
+            let runs = simulate_loss_files(input, &config, latency, reset_interval, fill)?;
+            if let Some(output_path) = option_value(&args, "--output") {
+                match &runs[..] {
+                    [run] => write_wav_file(output_path, &run.decoded, run.spec)?,
+                    _ => return Err(Box::from("--output needs a single input file")),
+                }
+            }
+        }
+        "serve" => {
+            if args.len() < 4 {
+                eprintln!(
+                    "Usage: {} serve <input_wav|-> <address> [--protocol <udp|tcp>] [--latency <ms>] [--reset-interval <packets>] [--speed <x>] [--rate <hz>] [--channels <n>] [--bits <8|16>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let config = ServeConfig {
+                protocol: Protocol::from_name(option_value(&args, "--protocol").unwrap_or("udp"))?,
+                reset_interval: parsed_option(&args, "--reset-interval")?
+                    .unwrap_or(DEFAULT_RESET_INTERVAL),
+                speed: parsed_option(&args, "--speed")?.unwrap_or(1.0),
+                ..ServeConfig::default()
+            };
+            let latency = parsed_option(&args, "--latency")?;
+            let report = if args[2] == "-" {
+                let spec = raw_spec(&args)?;
+                let samples = RawSamples::new(BufReader::new(io::stdin().lock()));
+                serve_samples(samples, &spec, &args[3], latency, config)?
+            } else {
+                let reader = WavReader::open(&args[2])?;
+                let spec = reader.spec();
+                let samples = reader
+                    .into_samples()
+                    .map(|sample| sample.map_err(Box::from));
+                serve_samples(samples, &spec, &args[3], latency, config)?
+            };
+            println!(
+                "Sent {} samples in {} frames ({} bytes)",
+                report.samples, report.frames, report.bytes
+            );
+        }
+        "receive" => {
+            if args.len() < 4 {
+                eprintln!(
+                    "Usage: {} receive <address> <output_wav> [--protocol <udp|tcp>] [--fill <skip|zero|hold>] [--timeout <s>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let protocol = Protocol::from_name(option_value(&args, "--protocol").unwrap_or("udp"))?;
+            let fill = GapFill::from_name(option_value(&args, "--fill").unwrap_or("hold"))?;
+            let timeout = Duration::try_from_secs_f64(
+                parsed_option(&args, "--timeout")?.unwrap_or(DEFAULT_IDLE_TIMEOUT),
+            )?;
+            if timeout.is_zero() {
+                return Err(Box::from("--timeout must be positive"));
+            }
+
+            let receiver = Receiver::bind(&args[2], protocol)?;
+            println!("Listening on {}", receiver.local_addr()?);
+            let mut writer = None;
+            let report = receiver.run(fill, timeout, |spec, samples| {
+                let writer = match &mut writer {
+                    Some(writer) => writer,
+                    None => writer.insert(WavWriter::create(&args[3], *spec)?),
+                };
+                for &sample in samples {
+                    writer.write_sample(sample)?;
+                }
+                Ok(())
+            })?;
+            writer.ok_or("No decodable stream received")?.finalize()?;
+            println!("{}", report);
+        }
+        "pack" => {
+            if args.len() < 4 {
+                eprintln!(
+                    "Usage: {} pack <input_dir> <archive> [--codec <name>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let codec = Codec::from_name(option_value(&args, "--codec").unwrap_or("cm"))?;
+            let summary = pack_directory(Path::new(&args[2]), Path::new(&args[3]), codec)?;
+            println!(
+                "Packed {} files ({} through {}) from {} to {} bytes (ratio {:.2}), shared statistics {} bytes",
+                summary.files,
+                summary.sample_files,
+                codec.name(),
+                summary.input_bytes,
+                summary.archive_bytes,
+                summary.input_bytes as f64 / summary.archive_bytes.max(1) as f64,
+                summary.stats_bytes
+            );
+        }
+        "unpack" => {
+            if args.len() < 4 {
+                eprintln!(
+                    "Usage: {} unpack <archive> <output_dir> [--file <path>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let count = unpack_archive(
+                Path::new(&args[2]),
+                Path::new(&args[3]),
+                option_value(&args, "--file"),
+            )?;
+            info!("Extracted {} files to {}", count, args[3]);
+        }
+        "train" => {
+            if args.len() < 4 {
+                eprintln!(
+                    "Usage: {} train <input_dir> <model_file> [--files <n>]",
+                    args[0]
+                );
+                std::process::exit(1);
+            }
+            let files = sample_files(Path::new(&args[2]))?;
+            let count = parsed_option(&args, "--files")?.unwrap_or(DEFAULT_TRAINING_FILES);
+            let model = train_on_files(&files, count)
+                .ok_or_else(|| format!("No readable recordings in {}", args[2]))?;
+            save_model(Path::new(&args[3]), &model)?;
+            println!(
+                "Trained model {:016x} on up to {} of {} recordings: {} bytes",
+                model.hash(),
+                count.min(files.len()),
+                files.len(),
+                fs::metadata(&args[3])?.len()
+            );
         }
         _ => {
             eprintln!("Unknown command: {}", command);
@@ -0,0 +1,236 @@
+use crate::cm::SharedStats;
+use crate::recording::{is_sample_file, read_sample_file, SampleFile};
+use crate::wav::read_to_end_limited;
+use hound::WavSpec;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// First bytes of a model file; zstd-compressed [`SharedStats`] follow.
+const MODEL_MAGIC: &[u8; 4] = b"SBM1";
+
+/// Tag of a stream coded against a shared model: the model's 64-bit
+/// [`SharedStats::hash`] follows, then an ordinary codec stream. Sits below
+/// [`crate::recording`]'s tag, which may wrap it.
+pub(crate) const MODEL_TAG: u8 = 0xFD;
+
+/// zstd level for model files, which are written once and read often.
+const MODEL_LEVEL: i32 = 19;
+
+/// Recordings a model learns from unless told otherwise. Each costs a full
+/// `cm` encode, and the statistics settle long before this.
+pub const DEFAULT_TRAINING_FILES: usize = 64;
+
+/// Every file below `dir` the codecs take, sorted. Symlinked directories
+/// are skipped, so a link back up the tree cannot recurse forever.
+pub fn sample_files(dir: &Path) -> Result<Vec<PathBuf>, Box<dyn Error + Send + Sync>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            files.extend(sample_files(&path)?);
+        } else if file_type.is_symlink() && path.is_dir() {
+            debug!("Skipping symlinked directory {}", path.display());
+        } else if is_sample_file(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Learns shared statistics from up to `count` of `paths`, spread evenly
+/// over them. Files that fail to read are skipped; `None` if none could be.
+pub fn train_on_files(paths: &[PathBuf], count: usize) -> Option<SharedStats> {
+    let step = paths.len().div_ceil(count.max(1)).max(1);
+    let training: Vec<SampleFile> = paths
+        .par_iter()
+        .step_by(step)
+        .filter_map(|path| match read_sample_file(path) {
+            Ok(file) => file,
+            Err(e) => {
+                debug!("Not training on {}: {}", path.display(), e);
+                None
+            }
+        })
+        .collect();
+    let recordings: Vec<(&[i16], &WavSpec)> = training
+        .iter()
+        .map(|(samples, spec, _)| (samples.as_slice(), spec))
+        .collect();
+    (!recordings.is_empty()).then(|| SharedStats::train(&recordings))
+}
+
+/// Serializes a model file: [`MODEL_MAGIC`], then the statistics with zstd.
+pub fn model_to_bytes(stats: &SharedStats) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut bytes = MODEL_MAGIC.to_vec();
+    bytes.extend(zstd::encode_all(stats.to_bytes().as_slice(), MODEL_LEVEL)?);
+    Ok(bytes)
+}
+
+pub fn model_from_bytes(bytes: &[u8]) -> Result<SharedStats, Box<dyn Error + Send + Sync>> {
+    let compressed = bytes
+        .strip_prefix(MODEL_MAGIC)
+        .ok_or("Not a smallbrain model file")?;
+    SharedStats::from_bytes(&read_to_end_limited(zstd::Decoder::new(compressed)?)?)
+}
+
+pub fn save_model(path: &Path, stats: &SharedStats) -> Result<(), Box<dyn Error + Send + Sync>> {
+    debug!("Writing model {:016x} to {}", stats.hash(), path.display());
+    fs::write(path, model_to_bytes(stats)?)?;
+    Ok(())
+}
+
+pub fn load_model(path: &Path) -> Result<SharedStats, Box<dyn Error + Send + Sync>> {
+    let stats = model_from_bytes(&fs::read(path)?)?;
+    debug!("Loaded model {:016x} from {}", stats.hash(), path.display());
+    Ok(stats)
+}
+
+/// A model hash and the codec stream coded against it, as stored by
+/// [`reference_model`].
+pub(crate) type ModelReference<'a> = (u64, &'a [u8]);
+
+/// Marks `stream`, coded against `stats`, with the hash of the model it
+/// needs.
+pub fn reference_model(stats: &SharedStats, stream: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(9 + stream.len());
+    output.push(MODEL_TAG);
+    output.extend_from_slice(&stats.hash().to_be_bytes());
+    output.extend_from_slice(stream);
+    output
+}
+
+/// Splits the output of [`reference_model`] into the model hash and the
+/// codec stream. `None` for a stream that needs no model.
+pub(crate) fn split_model_reference(
+    buffer: &[u8],
+) -> Result<Option<ModelReference<'_>>, Box<dyn Error + Send + Sync>> {
+    if buffer.first() != Some(&MODEL_TAG) {
+        return Ok(None);
+    }
+    let hash = buffer.get(1..9).ok_or("Truncated model reference")?;
+    let hash = u64::from_be_bytes([
+        hash[0], hash[1], hash[2], hash[3], hash[4], hash[5], hash[6], hash[7],
+    ]);
+    let stream = &buffer[9..];
+    if stream.first() == Some(&MODEL_TAG) {
+        return Err(Box::from("Nested model references are not valid"));
+    }
+    Ok(Some((hash, stream)))
+}
+
+/// The hash of the model a stream was coded against, if any, so a caller can
+/// tell which model file to load.
+pub fn required_model(buffer: &[u8]) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+    let stream = match crate::recording::split_recording(buffer)? {
+        Some((_, _, stream)) => stream,
+        None => buffer,
+    };
+    Ok(split_model_reference(stream)?.map(|(hash, _)| hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decompress, decompress_with, Codec};
+    use crate::recording::{decompress_recording_with, wrap_recording, RecordingFormat};
+    use crate::wav::write_wav_file;
+    use hound::SampleFormat;
+
+    fn spec() -> WavSpec {
+        WavSpec {
+            channels: 1,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        }
+    }
+
+    fn recording(seed: i32) -> Vec<i16> {
+        (0..6000)
+            .map(|i: i32| (((i * (seed + 5)) % 29 - 14 + (i / 40) % 4) * 64 + 32) as i16)
+            .collect()
+    }
+
+    #[test]
+    fn model_file_roundtrip_and_training_from_a_directory() {
+        let dir = std::env::temp_dir().join(format!("smallbrain-model-{}", std::process::id()));
+        fs::create_dir_all(dir.join("day2")).unwrap();
+        for seed in 0..4 {
+            let path = dir.join(format!("day{}/{}.wav", 1 + seed / 2, seed));
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            write_wav_file(path.to_str().unwrap(), &recording(seed), spec()).unwrap();
+        }
+        fs::write(dir.join("notes.txt"), "not a recording").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("day2/loop")).unwrap();
+
+        let files = sample_files(&dir).unwrap();
+        assert_eq!(files.len(), 4);
+        let stats = train_on_files(&files, 2).unwrap();
+        let path = dir.join("dataset.model");
+        save_model(&path, &stats).unwrap();
+        let loaded = load_model(&path).unwrap();
+        assert_eq!(loaded, stats);
+        assert_eq!(loaded.hash(), stats.hash());
+        assert!(model_from_bytes(b"SBM0").is_err());
+
+        // Grids whose offset is not below the step, or with no step at all,
+        // are never written and mark a corrupt model.
+        let mut bytes = stats.to_bytes();
+        let grid = bytes.len() - 4;
+        for invalid in [[0, 64, 0, 64], [0, 64, 0, 200], [0, 0, 0, 0]] {
+            bytes[grid..].copy_from_slice(&invalid);
+            assert!(SharedStats::from_bytes(&bytes).is_err());
+        }
+        bytes[grid..].copy_from_slice(&[0, 64, 0, 63]);
+        assert!(SharedStats::from_bytes(&bytes).is_ok());
+        assert!(train_on_files(&[], DEFAULT_TRAINING_FILES).is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn streams_name_the_model_they_need() {
+        let training: Vec<Vec<i16>> = (0..3).map(recording).collect();
+        let spec = spec();
+        let recordings: Vec<(&[i16], &WavSpec)> =
+            training.iter().map(|r| (r.as_slice(), &spec)).collect();
+        let stats = SharedStats::train(&recordings);
+        let other = SharedStats::train(&recordings[..1]);
+        assert_ne!(stats.hash(), other.hash());
+
+        let samples = recording(7);
+        let stream = Codec::Cm
+            .compress_with(&samples, &spec, Some(&stats))
+            .unwrap();
+        let referenced = reference_model(&stats, &stream);
+        assert_eq!(required_model(&referenced).unwrap(), Some(stats.hash()));
+        assert_eq!(
+            decompress_with(&referenced, Some(&stats)).unwrap(),
+            (samples.clone(), spec)
+        );
+        assert!(decompress(&referenced).is_err());
+        assert!(decompress_with(&referenced, Some(&other)).is_err());
+
+        // A recording wrapper keeps the reference inside it.
+        let wrapped = wrap_recording(RecordingFormat::Nsx, b"header", &referenced).unwrap();
+        assert_eq!(required_model(&wrapped).unwrap(), Some(stats.hash()));
+        let restored = decompress_recording_with(&wrapped, Some(&stats))
+            .unwrap()
+            .unwrap();
+        assert_eq!(restored.samples, samples);
+
+        let plain = Codec::Cm.compress(&samples, &spec).unwrap();
+        assert_eq!(required_model(&plain).unwrap(), None);
+        assert_eq!(
+            decompress_with(&plain, Some(&stats)).unwrap(),
+            (samples, spec)
+        );
+        assert!(decompress_with(&[MODEL_TAG, 0, 0], Some(&stats)).is_err());
+    }
+}
@@ -0,0 +1,254 @@
+use crate::recording::RecordingParts;
+use crate::wav::check_recording_length;
+use hound::{SampleFormat, WavSpec};
+use std::error::Error;
+use std::io::Write;
+use tracing::debug;
+
+/// Sampling periods in NSx headers count ticks of this clock.
+const NSX_CLOCK: u32 = 30_000;
+
+/// Byte that opens every data packet of NSx 2.2 and later.
+const PACKET_START: u8 = 0x01;
+
+/// Where an NSx file keeps its header fields, which differ between the
+/// unpacketized 2.1 layout and the 2.2+ one.
+struct NsxHeader {
+    header_len: usize,
+    period: u32,
+    channels: usize,
+    /// Bytes of a data packet header, or `None` for 2.1 files whose samples
+    /// follow the header directly.
+    packet_header_len: Option<usize>,
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    let field = bytes
+        .get(offset..offset + 4)
+        .ok_or("NSx header is truncated")?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+fn parse_header(bytes: &[u8]) -> Result<NsxHeader, Box<dyn Error + Send + Sync>> {
+    let header = match bytes.get(..8) {
+        Some(b"NEURALSG") => {
+            let channels = u32_at(bytes, 28)? as usize;
+            NsxHeader {
+                header_len: 32 + 4 * channels,
+                period: u32_at(bytes, 24)?,
+                channels,
+                packet_header_len: None,
+            }
+        }
+        Some(b"NEURALCD") => {
+            let major = *bytes.get(8).ok_or("NSx header is truncated")?;
+            // 3.0 widened the packet timestamp from 32 to 64 bits.
+            let timestamp_len = if major >= 3 { 8 } else { 4 };
+            NsxHeader {
+                header_len: u32_at(bytes, 10)? as usize,
+                period: u32_at(bytes, 286)?,
+                channels: u32_at(bytes, 310)? as usize,
+                packet_header_len: Some(1 + timestamp_len + 4),
+            }
+        }
+        _ => return Err(Box::from("Not an NSx file")),
+    };
+    if header.header_len > bytes.len() {
+        return Err(Box::from("NSx header is truncated"));
+    }
+    if header.period == 0 || header.channels == 0 || header.channels > u16::MAX as usize {
+        return Err(Box::from(format!(
+            "Invalid NSx header: period {}, {} channels",
+            header.period, header.channels
+        )));
+    }
+    Ok(header)
+}
+
+/// Reads a Blackrock `.ns1`–`.ns6` file, NSx 2.1 through 3.0, as one run of
+/// interleaved samples. Pauses split 2.2+ recordings into several data
+/// packets; their samples are concatenated and the packet headers go into
+/// the metadata with everything else [`write_nsx`] needs to restore the file:
+///
+/// - the header length (32-bit little-endian) and the header itself;
+/// - the packet count, then every packet header followed by the frames that
+///   were actually present after it (32-bit little-endian);
+/// - any trailing bytes that do not form a whole frame or packet.
+pub fn read_nsx(bytes: &[u8]) -> Result<RecordingParts, Box<dyn Error + Send + Sync>> {
+    let header = parse_header(bytes)?;
+    let frame_len = 2 * header.channels;
+    let mut metadata = Vec::new();
+    metadata.extend_from_slice(&(header.header_len as u32).to_le_bytes());
+    metadata.extend_from_slice(&bytes[..header.header_len]);
+
+    // Byte ranges of sample data, and the packet headers in front of them.
+    let mut runs = Vec::new();
+    let mut packets = Vec::new();
+    let mut position = header.header_len;
+    match header.packet_header_len {
+        None => {
+            let frames = (bytes.len() - position) / frame_len;
+            runs.push(position..position + frames * frame_len);
+            position += frames * frame_len;
+        }
+        Some(packet_header_len) => {
+            while bytes.len() - position >= packet_header_len && bytes[position] == PACKET_START {
+                let declared = u32_at(bytes, position + packet_header_len - 4)? as usize;
+                let start = position + packet_header_len;
+                let frames = declared.min((bytes.len() - start) / frame_len);
+                packets.push((position..start, frames as u32));
+                runs.push(start..start + frames * frame_len);
+                position = start + frames * frame_len;
+                if frames < declared {
+                    break;
+                }
+            }
+        }
+    }
+
+    let sample_count: usize = runs.iter().map(|run| run.len() / 2).sum();
+    check_recording_length(sample_count)?;
+    let mut samples = Vec::with_capacity(sample_count);
+    for run in runs {
+        samples.extend(
+            bytes[run]
+                .chunks_exact(2)
+                .map(|pair| i16::from_le_bytes([pair[0], pair[1]])),
+        );
+    }
+    metadata.extend_from_slice(&(packets.len() as u32).to_le_bytes());
+    for (packet_header, frames) in &packets {
+        metadata.extend_from_slice(&bytes[packet_header.clone()]);
+        metadata.extend_from_slice(&frames.to_le_bytes());
+    }
+    metadata.extend_from_slice(&bytes[position..]);
+
+    let spec = WavSpec {
+        channels: header.channels as u16,
+        sample_rate: (NSX_CLOCK as f64 / header.period as f64).round() as u32,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    debug!(
+        "Read {} frames of {} channels in {} packets",
+        sample_count / header.channels,
+        header.channels,
+        packets.len()
+    );
+    Ok((samples, spec, metadata))
+}
+
+/// Inverse of [`read_nsx`].
+pub fn write_nsx<W: Write>(
+    mut writer: W,
+    samples: &[i16],
+    metadata: &[u8],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let invalid = || Box::<dyn Error + Send + Sync>::from("Invalid NSx metadata");
+    let header_len = u32_at(metadata, 0)? as usize;
+    let header_bytes = metadata.get(4..4 + header_len).ok_or_else(invalid)?;
+    let header = parse_header(header_bytes)?;
+    writer.write_all(header_bytes)?;
+
+    let mut position = 4 + header_len;
+    let packet_count = u32_at(metadata, position)?;
+    position += 4;
+    let mut written = 0;
+    let mut write_samples =
+        |writer: &mut W, count: usize| -> Result<(), Box<dyn Error + Send + Sync>> {
+            let run = samples.get(written..written + count).ok_or_else(invalid)?;
+            for &sample in run {
+                writer.write_all(&sample.to_le_bytes())?;
+            }
+            written += count;
+            Ok(())
+        };
+    match header.packet_header_len {
+        None => write_samples(&mut writer, samples.len())?,
+        Some(packet_header_len) => {
+            for _ in 0..packet_count {
+                let packet_header = metadata
+                    .get(position..position + packet_header_len)
+                    .ok_or_else(invalid)?;
+                let frames = u32_at(metadata, position + packet_header_len)? as usize;
+                writer.write_all(packet_header)?;
+                write_samples(&mut writer, frames * header.channels)?;
+                position += packet_header_len + 4;
+            }
+        }
+    }
+    if written != samples.len() {
+        return Err(Box::from(format!(
+            "NSx metadata accounts for {} of {} samples",
+            written,
+            samples.len()
+        )));
+    }
+    writer.write_all(&metadata[position..])?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An NSx 2.3 file of `channels` channels at 1 kHz with two data packets
+    /// and a cut-off third one.
+    pub(crate) fn nsx_file(channels: usize) -> Vec<u8> {
+        let header_len = 314 + 66 * channels;
+        let mut file = vec![0u8; header_len];
+        file[..8].copy_from_slice(b"NEURALCD");
+        file[8..10].copy_from_slice(&[2, 3]);
+        file[10..14].copy_from_slice(&(header_len as u32).to_le_bytes());
+        file[286..290].copy_from_slice(&30u32.to_le_bytes());
+        file[290..294].copy_from_slice(&NSX_CLOCK.to_le_bytes());
+        file[310..314].copy_from_slice(&(channels as u32).to_le_bytes());
+        for channel in 0..channels {
+            file[314 + 66 * channel..][..2].copy_from_slice(b"CC");
+        }
+        let mut sample = 0i16;
+        for (timestamp, declared, present) in [(0u32, 50u32, 50), (9000, 20, 20), (12000, 10, 4)] {
+            file.push(PACKET_START);
+            file.extend_from_slice(&timestamp.to_le_bytes());
+            file.extend_from_slice(&declared.to_le_bytes());
+            for _ in 0..present * channels {
+                sample = sample.wrapping_mul(31).wrapping_add(17);
+                file.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        file.push(0xAB);
+        file
+    }
+
+    #[test]
+    fn nsx_roundtrip() {
+        let file = nsx_file(3);
+        let (samples, spec, metadata) = read_nsx(&file).unwrap();
+        assert_eq!(spec.sample_rate, 1000);
+        assert_eq!(spec.channels, 3);
+        assert_eq!(samples.len(), (50 + 20 + 4) * 3);
+
+        let mut restored = Vec::new();
+        write_nsx(&mut restored, &samples, &metadata).unwrap();
+        assert_eq!(restored, file);
+        assert!(write_nsx(&mut Vec::new(), &samples[3..], &metadata).is_err());
+    }
+
+    #[test]
+    fn nsx_21_roundtrip() {
+        let mut file = b"NEURALSG".to_vec();
+        file.extend_from_slice(&[0; 16]);
+        file.extend_from_slice(&15u32.to_le_bytes());
+        file.extend_from_slice(&2u32.to_le_bytes());
+        file.extend_from_slice(&[1, 0, 0, 0, 2, 0, 0, 0]);
+        file.extend((0..41).map(|i| i as u8));
+
+        let (samples, spec, metadata) = read_nsx(&file).unwrap();
+        assert_eq!(spec.sample_rate, 2000);
+        assert_eq!(samples.len(), 20);
+        let mut restored = Vec::new();
+        write_nsx(&mut restored, &samples, &metadata).unwrap();
+        assert_eq!(restored, file);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::recording::RecordingParts;
+use crate::wav::check_recording_length;
+use hound::{SampleFormat, WavSpec};
+use serde_json::Value;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+/// Name of the file describing the streams of an Open Ephys recording.
+const STRUCTURE_FILE: &str = "structure.oebin";
+
+/// Sample rate and channel count of one continuous stream, from the
+/// `structure.oebin` of the recording.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StreamInfo {
+    pub sample_rate: f64,
+    pub channels: usize,
+}
+
+/// Finds the `structure.oebin` of a `continuous.dat`, which sits at
+/// `<recording>/continuous/<stream>/continuous.dat`, and the stream's entry in it.
+pub fn stream_info(dat_path: &Path) -> Result<StreamInfo, Box<dyn Error + Send + Sync>> {
+    let stream_dir = dat_path
+        .parent()
+        .ok_or("continuous.dat has no stream directory")?;
+    let stream = stream_dir
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or("continuous.dat has no stream directory")?;
+    let structure_path: PathBuf = stream_dir
+        .ancestors()
+        .nth(2)
+        .map(|recording| recording.join(STRUCTURE_FILE))
+        .filter(|path| path.is_file())
+        .ok_or_else(|| {
+            format!(
+                "No {} found for {}; use --input-format raw for bare PCM",
+                STRUCTURE_FILE,
+                dat_path.display()
+            )
+        })?;
+    debug!(
+        "Reading stream {} from {}",
+        stream,
+        structure_path.display()
+    );
+
+    let structure: Value = serde_json::from_str(&fs::read_to_string(&structure_path)?)?;
+    let entry = structure["continuous"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|entry| {
+            entry["folder_name"]
+                .as_str()
+                .map(|name| name.trim_end_matches('/'))
+                == Some(stream)
+        })
+        .ok_or_else(|| {
+            format!(
+                "Stream {} is not listed in {}",
+                stream,
+                structure_path.display()
+            )
+        })?;
+    let sample_rate = entry["sample_rate"]
+        .as_f64()
+        .ok_or("Stream has no sample_rate")?;
+    let channels = entry["num_channels"]
+        .as_u64()
+        .ok_or("Stream has no num_channels")? as usize;
+    if sample_rate <= 0.0 || channels == 0 || channels > u16::MAX as usize {
+        return Err(Box::from(format!(
+            "Invalid stream: {} Hz, {} channels",
+            sample_rate, channels
+        )));
+    }
+    Ok(StreamInfo {
+        sample_rate,
+        channels,
+    })
+}
+
+/// Reads the `continuous.dat` of an Open Ephys binary recording: interleaved
+/// little-endian 16-bit samples whose rate and channel count come from
+/// `structure.oebin`. The metadata is whatever follows the last whole frame,
+/// normally nothing; the `.oebin`, timestamps and events stay where they are.
+pub fn read_openephys(dat_path: &Path) -> Result<RecordingParts, Box<dyn Error + Send + Sync>> {
+    let info = stream_info(dat_path)?;
+    let bytes = fs::read(dat_path)?;
+    let whole = bytes.len() / (2 * info.channels) * 2 * info.channels;
+    check_recording_length(whole / 2)?;
+    let samples = bytes[..whole]
+        .chunks_exact(2)
+        .map(|pair| i16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+    let spec = WavSpec {
+        channels: info.channels as u16,
+        sample_rate: info.sample_rate.round() as u32,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    debug!(
+        "Read {} frames of {} channels at {} Hz",
+        whole / 2 / info.channels,
+        info.channels,
+        info.sample_rate
+    );
+    Ok((samples, spec, bytes[whole..].to_vec()))
+}
+
+/// Inverse of [`read_openephys`]: writes the `continuous.dat` contents.
+pub fn write_openephys<W: Write>(
+    mut writer: W,
+    samples: &[i16],
+    metadata: &[u8],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    for &sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    writer.write_all(metadata)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Writes a recording directory with a `channels`-channel 30 kHz stream
+    /// holding `frames` frames and a stray byte, and returns its
+    /// `continuous.dat`.
+    pub(crate) fn openephys_recording(dir: &Path, channels: usize, frames: usize) -> PathBuf {
+        let stream = dir
+            .join("continuous")
+            .join("Acquisition_Board-100.Rhythm Data");
+        fs::create_dir_all(&stream).unwrap();
+        let channel_list: Vec<String> = (1..=channels)
+            .map(|i| format!(r#"{{"channel_name": "CH{}", "bit_volts": 0.195}}"#, i))
+            .collect();
+        fs::write(
+            dir.join(STRUCTURE_FILE),
+            format!(
+                r#"{{
+                "GUI version": "0.6.4",
+                "continuous": [
+                    {{"folder_name": "Other-101.0/", "sample_rate": 1000.0, "num_channels": 8}},
+                    {{
+                        "folder_name": "Acquisition_Board-100.Rhythm Data/",
+                        "sample_rate": 30000.0,
+                        "num_channels": {},
+                        "channels": [{}]
+                    }}
+                ],
+                "events": [],
+                "spikes": []
+            }}"#,
+                channels,
+                channel_list.join(", ")
+            ),
+        )
+        .unwrap();
+        let mut data: Vec<u8> = (0..frames * channels)
+            .flat_map(|i| ((i as i16).wrapping_mul(37) % 500).to_le_bytes())
+            .collect();
+        data.push(9);
+        let dat_path = stream.join("continuous.dat");
+        fs::write(&dat_path, data).unwrap();
+        dat_path
+    }
+
+    #[test]
+    fn openephys_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("smallbrain-openephys-{}", std::process::id()));
+        let dat_path = openephys_recording(&dir, 2, 300);
+        let (samples, spec, metadata) = read_openephys(&dat_path).unwrap();
+        assert_eq!((spec.sample_rate, spec.channels), (30000, 2));
+        assert_eq!(samples.len(), 600);
+
+        let mut restored = Vec::new();
+        write_openephys(&mut restored, &samples, &metadata).unwrap();
+        assert_eq!(restored, fs::read(&dat_path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,258 @@
+use crate::predictor::fixed_block_residuals;
+use crate::wav::{deinterleave, read_wav_file};
+use hound::WavSpec;
+use plotters::coord::Shift;
+use plotters::prelude::*;
+use rayon::prelude::*;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+/// Image size. The top 1024x768 matches the original `viz/` waveform plots;
+/// the residual and histogram panels sit underneath it.
+pub const PLOT_SIZE: (u32, u32) = (1024, 1152);
+
+const WAVEFORM_HEIGHT: u32 = 768;
+
+const HISTOGRAM_BINS: i64 = 100;
+
+/// Channel colours; the first channel is drawn in blue as in `viz/`.
+const CHANNEL_COLORS: [RGBColor; 5] = [BLUE, RED, GREEN, MAGENTA, CYAN];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlotFormat {
+    Png,
+    Svg,
+}
+
+impl PlotFormat {
+    pub fn from_name(name: &str) -> Result<PlotFormat, Box<dyn Error + Send + Sync>> {
+        match name {
+            "png" => Ok(PlotFormat::Png),
+            "svg" => Ok(PlotFormat::Svg),
+            _ => Err(Box::from(format!(
+                "Unknown plot format: {} (expected png or svg)",
+                name
+            ))),
+        }
+    }
+
+    /// Picks the format from an output file's extension, defaulting to PNG.
+    pub fn from_path(path: &Path) -> PlotFormat {
+        match path.extension().and_then(|s| s.to_str()) {
+            Some("svg") => PlotFormat::Svg,
+            _ => PlotFormat::Png,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            PlotFormat::Png => "png",
+            PlotFormat::Svg => "svg",
+        }
+    }
+}
+
+/// Renders the waveform, fixed-predictor residual and sample histogram of
+/// one recording into `output`.
+pub fn plot_samples(
+    samples: &[i16],
+    spec: &WavSpec,
+    output: &Path,
+    format: PlotFormat,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match format {
+        PlotFormat::Png => draw(
+            BitMapBackend::new(output, PLOT_SIZE).into_drawing_area(),
+            samples,
+            spec,
+        ),
+        PlotFormat::Svg => draw(
+            SVGBackend::new(output, PLOT_SIZE).into_drawing_area(),
+            samples,
+            spec,
+        ),
+    }
+}
+
+pub fn plot_wav_file(
+    input: &Path,
+    output: &Path,
+    format: PlotFormat,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    debug!("Plotting {} to {}", input.display(), output.display());
+    let input = input.to_str().ok_or("Input path is not valid UTF-8")?;
+    let (samples, spec) = read_wav_file(input)?;
+    plot_samples(&samples, &spec, output, format)
+}
+
+/// Plots every WAV in `input_dir` in parallel, writing `<uuid>.<ext>` files
+/// into `output_dir` like `viz/`. Returns the number of plots written.
+pub fn plot_directory(
+    input_dir: &Path,
+    output_dir: &Path,
+    format: PlotFormat,
+) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    fs::create_dir_all(output_dir)?;
+
+    let mut inputs: Vec<PathBuf> = fs::read_dir(input_dir)?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("wav"))
+        .collect();
+    inputs.sort();
+
+    let failures: Vec<String> = inputs
+        .par_iter()
+        .filter_map(|input| {
+            // Appended rather than set, so `rec.v1.wav` keeps its `.v1`.
+            let output = output_dir.join(format!(
+                "{}.{}",
+                input.file_stem()?.to_string_lossy(),
+                format.extension()
+            ));
+            plot_wav_file(input, &output, format)
+                .err()
+                .map(|e| format!("{}: {}", input.display(), e))
+        })
+        .collect();
+
+    if !failures.is_empty() {
+        return Err(Box::from(format!(
+            "Failed to plot {} files:\n{}",
+            failures.len(),
+            failures.join("\n")
+        )));
+    }
+
+    info!("Wrote {} plots to {}", inputs.len(), output_dir.display());
+    Ok(inputs.len())
+}
+
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<(), Box<dyn Error + Send + Sync>>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+    let (top, bottom) = root.split_vertically(WAVEFORM_HEIGHT);
+    let (left, right) = bottom.split_horizontally(PLOT_SIZE.0 / 2);
+
+    let channels = deinterleave(samples, spec.channels.max(1) as usize);
+    let frames = channels.first().map_or(0, Vec::len).max(1);
+    let full_scale = (1i64 << (spec.bits_per_sample.clamp(1, 32) - 1)) as f64;
+
+    let mut chart = ChartBuilder::on(&top)
+        .caption("Audio Waveform", ("sans-serif", 40))
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(40)
+        .build_cartesian_2d(0..frames, -1.0..1.0)?;
+    chart.configure_mesh().draw()?;
+    for (i, channel) in channels.iter().enumerate() {
+        chart.draw_series(LineSeries::new(
+            channel
+                .iter()
+                .enumerate()
+                .map(|(x, &y)| (x, y as f64 / full_scale)),
+            CHANNEL_COLORS[i % CHANNEL_COLORS.len()],
+        ))?;
+    }
+
+    let residuals = channels
+        .first()
+        .map_or_else(Vec::new, |channel| fixed_block_residuals(channel).1);
+    let peak = residuals
+        .iter()
+        .map(|r| r.unsigned_abs())
+        .max()
+        .unwrap_or(0)
+        .max(1) as i64;
+    let mut chart = ChartBuilder::on(&left)
+        .caption("Fixed Predictor Residual", ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(50)
+        .build_cartesian_2d(0..frames, -peak..peak)?;
+    chart.configure_mesh().draw()?;
+    chart.draw_series(LineSeries::new(
+        residuals.iter().enumerate().map(|(x, &r)| (x, r as i64)),
+        RED,
+    ))?;
+
+    let min = samples.iter().copied().min().unwrap_or(0) as i64;
+    let max = samples.iter().copied().max().unwrap_or(0) as i64;
+    let width = (max - min) / HISTOGRAM_BINS + 1;
+    let mut counts = vec![0u64; ((max - min) / width + 1) as usize];
+    for &s in samples {
+        counts[((s as i64 - min) / width) as usize] += 1;
+    }
+    let tallest = counts.iter().copied().max().unwrap_or(0).max(1);
+    let mut chart = ChartBuilder::on(&right)
+        .caption("Sample Histogram", ("sans-serif", 24))
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(50)
+        .build_cartesian_2d(min..min + width * counts.len() as i64, 0..tallest)?;
+    chart.configure_mesh().draw()?;
+    chart.draw_series(counts.iter().enumerate().map(|(i, &count)| {
+        let left = min + width * i as i64;
+        Rectangle::new([(left, 0), (left + width, count)], BLUE.filled())
+    }))?;
+
+    root.present()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_svg() {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let samples: Vec<i16> = (0..4000)
+            .map(|i: i32| ((i * 37) % 900 - 450) as i16)
+            .collect();
+        let output =
+            std::env::temp_dir().join(format!("smallbrain-plot-test-{}.svg", std::process::id()));
+
+        plot_samples(&samples, &spec, &output, PlotFormat::from_path(&output)).unwrap();
+        let rendered = fs::read_to_string(&output).unwrap();
+        fs::remove_file(&output).ok();
+        assert!(rendered.contains("<svg"));
+        assert!(rendered.contains("Audio Waveform"));
+    }
+
+    #[test]
+    fn dotted_names_get_their_own_plot() {
+        let base = std::env::temp_dir().join(format!("smallbrain-plot-dir-{}", std::process::id()));
+        let input = base.join("input");
+        fs::create_dir_all(&input).unwrap();
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        for name in ["rec.v1.wav", "rec.v2.wav"] {
+            crate::wav::write_wav_file(input.join(name).to_str().unwrap(), &[0, 64, -64], spec)
+                .unwrap();
+        }
+
+        let output = base.join("output");
+        plot_directory(&input, &output, PlotFormat::Svg).unwrap();
+        assert!(output.join("rec.v1.svg").is_file());
+        assert!(output.join("rec.v2.svg").is_file());
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
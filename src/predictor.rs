@@ -0,0 +1,144 @@
+use crate::bitpack::{BitReader, BitWriter};
+use crate::cost::CostModel;
+use std::error::Error;
+
+/// Highest polynomial order supported by the fixed predictors.
+pub const MAX_FIXED_ORDER: usize = 4;
+
+/// Samples per fixed predictor block; each block picks its own order.
+pub const FIXED_BLOCK_SIZE: usize = 4096;
+
+/// Predicts `samples[i]` from the preceding samples with the FLAC fixed
+/// polynomial of the given order. Near the start of the stream the order is
+/// reduced to however many samples of history exist.
+pub fn fixed_prediction(samples: &[i32], i: usize, order: usize) -> i32 {
+    let order = order.min(i);
+    let x = |k: usize| samples[i - k] as i64;
+    let prediction = match order {
+        0 => 0,
+        1 => x(1),
+        2 => 2 * x(1) - x(2),
+        3 => 3 * x(1) - 3 * x(2) + x(3),
+        _ => 4 * x(1) - 6 * x(2) + 4 * x(3) - x(4),
+    };
+    prediction as i32
+}
+
+/// Residuals of `samples[start..end]` under the fixed predictor of `order`,
+/// using the samples before `start` as history.
+pub fn fixed_residuals(samples: &[i32], start: usize, end: usize, order: usize) -> Vec<i32> {
+    (start..end)
+        .map(|i| samples[i].wrapping_sub(fixed_prediction(samples, i, order)))
+        .collect()
+}
+
+/// Reconstructs samples from residuals produced by [`fixed_residuals`],
+/// appending them to `samples` (which must hold everything before the block).
+pub fn restore_fixed(samples: &mut Vec<i32>, residuals: &[i32], order: usize) {
+    for &residual in residuals {
+        let i = samples.len();
+        samples.push(residual.wrapping_add(fixed_prediction(samples, i, order)));
+    }
+}
+
+/// Picks the fixed order with the smallest sum of absolute residuals over the block.
+pub fn best_fixed_order(samples: &[i32], start: usize, end: usize) -> usize {
+    (0..=MAX_FIXED_ORDER)
+        .min_by_key(|&order| {
+            (start..end)
+                .map(|i| {
+                    (samples[i] as i64 - fixed_prediction(samples, i, order) as i64).unsigned_abs()
+                })
+                .sum::<u64>()
+        })
+        .unwrap()
+}
+
+/// Runs the fixed predictor over a whole channel, choosing the order for each
+/// block of [`FIXED_BLOCK_SIZE`] samples. Returns the orders and the residuals.
+pub fn fixed_block_residuals(samples: &[i32]) -> (Vec<u8>, Vec<i32>) {
+    let mut orders = Vec::with_capacity(samples.len().div_ceil(FIXED_BLOCK_SIZE));
+    let mut residuals = Vec::with_capacity(samples.len());
+    for start in (0..samples.len()).step_by(FIXED_BLOCK_SIZE) {
+        let end = (start + FIXED_BLOCK_SIZE).min(samples.len());
+        let order = best_fixed_order(samples, start, end);
+        orders.push(order as u8);
+        residuals.extend(fixed_residuals(samples, start, end, order));
+    }
+    (orders, residuals)
+}
+
+/// Encoder cost of [`fixed_block_residuals`]: every order's sum of absolute
+/// residuals, then the residuals of the chosen order (counted at order 4).
+pub fn fixed_cost() -> CostModel {
+    CostModel {
+        // Orders 2, 3 and 4 scale their taps with 1, 2 and 3 multiplies.
+        multiplies: 6.0 + 3.0,
+        // The orders' taps, then a residual, magnitude and sum each, then the final pass.
+        additions: 6.0 + 3.0 * (MAX_FIXED_ORDER + 1) as f64 + 4.0,
+        state_bytes: 0,
+        buffer_bytes_per_sample: 4.0,
+    }
+}
+
+/// Inverse of [`fixed_block_residuals`].
+pub fn restore_fixed_blocks(orders: &[u8], residuals: &[i32]) -> Vec<i32> {
+    let mut samples = Vec::with_capacity(residuals.len());
+    for (&order, block) in orders.iter().zip(residuals.chunks(FIXED_BLOCK_SIZE)) {
+        restore_fixed(&mut samples, block, order as usize);
+    }
+    samples
+}
+
+pub fn write_fixed_orders(writer: &mut BitWriter, orders: &[u8]) {
+    for &order in orders {
+        writer.write_bits(order as u32, 3);
+    }
+}
+
+/// Reads one block's 3-bit order, rejecting the values above
+/// [`MAX_FIXED_ORDER`] that the field can hold but no encoder writes.
+pub fn read_fixed_order(reader: &mut BitReader) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let order = reader.read_bits(3)? as usize;
+    if order > MAX_FIXED_ORDER {
+        return Err(Box::from(format!(
+            "Invalid fixed predictor order: {}",
+            order
+        )));
+    }
+    Ok(order)
+}
+
+/// Reads the block orders for a channel of `frames` samples.
+pub fn read_fixed_orders(
+    reader: &mut BitReader,
+    frames: usize,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    (0..frames.div_ceil(FIXED_BLOCK_SIZE))
+        .map(|_| Ok(read_fixed_order(reader)? as u8))
+        .collect()
+}
+
+/// Finds the coarsest grid `offset + k * step` containing every sample, so
+/// quantized recordings can be coded as grid indices instead of raw values.
+pub fn detect_grid(samples: &[i32]) -> (i32, i32) {
+    let Some(&first) = samples.first() else {
+        return (1, 0);
+    };
+    let step = samples
+        .iter()
+        .fold(0u32, |step, &s| gcd(step, s.abs_diff(first)));
+    let step = if step == 0 || step > u16::MAX as u32 {
+        1
+    } else {
+        step as i32
+    };
+    (step, first.rem_euclid(step))
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
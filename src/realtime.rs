@@ -0,0 +1,403 @@
+use crate::bitpack::{BitOrder, BitReader, BitWriter};
+use crate::cost::CostModel;
+use crate::lms::{cascade_cost, LmsCascade, DEFAULT_STAGES};
+use crate::predictor::detect_grid;
+use crate::rice::{decode_residuals, encode_residuals, encode_residuals_cost};
+use crate::wav::{read_spec_header, write_spec_header};
+use hound::WavSpec;
+use std::error::Error;
+use tracing::debug;
+
+/// Packet length of the `realtime` codec: 2 ms at 19531 Hz.
+pub const DEFAULT_PACKET_FRAMES: usize = 39;
+
+/// Longest packet; the frame count is stored in 16 bits.
+pub const MAX_PACKET_FRAMES: usize = u16::MAX as usize;
+
+/// Frames per packet that fit in a `latency_ms` buffering budget at
+/// `sample_rate`, at least one.
+pub fn packet_frames_for_latency(latency_ms: f64, sample_rate: u32) -> usize {
+    let frames = (latency_ms * sample_rate as f64 / 1000.0).floor();
+    (frames as usize).clamp(1, MAX_PACKET_FRAMES)
+}
+
+/// Rounds a prediction, clamped to the 16-bit range, to the nearest point of
+/// the grid `offset + k * step`.
+fn snap(prediction: i32, (step, offset): (i32, i32)) -> i64 {
+    let prediction = prediction.clamp(i16::MIN as i32, i16::MAX as i32) as i64;
+    let step = step as i64;
+    (prediction - offset as i64 + step / 2).div_euclid(step) * step + offset as i64
+}
+
+/// Packetizing encoder with bounded delay and state.
+///
+/// Samples are buffered until a packet of `packet_frames` frames is full,
+/// which is then emitted at once, so no sample waits longer than one packet.
+/// Each channel is predicted by an LMS cascade carried from packet to packet,
+/// the prediction is snapped to the quantization grid and the residuals in
+/// grid steps are Rice coded. A packet only needs the state left by the
+/// packets before it; the encoder holds the cascades, the grid and at most one
+/// packet of samples, however long the stream runs.
+///
+/// Packet layout: 16-bit frame count, a flag and, when set, the new 16-bit
+/// grid step and offset, then each channel's residuals.
+pub struct RealtimeEncoder {
+    channels: usize,
+    packet_frames: usize,
+    cascades: Vec<LmsCascade>,
+    /// Coarsest grid holding every sample so far, unknown until a packet
+    /// with more than one distinct value arrives.
+    grid: Option<(i32, i32)>,
+    /// Grid the decoder last received.
+    sent_grid: Option<(i32, i32)>,
+    pending: Vec<i16>,
+}
+
+impl RealtimeEncoder {
+    pub fn new(
+        spec: &WavSpec,
+        packet_frames: usize,
+    ) -> Result<RealtimeEncoder, Box<dyn Error + Send + Sync>> {
+        if packet_frames == 0 || packet_frames > MAX_PACKET_FRAMES {
+            return Err(Box::from(format!(
+                "Packet length must be between 1 and {} frames, got {}",
+                MAX_PACKET_FRAMES, packet_frames
+            )));
+        }
+        if spec.channels == 0 {
+            return Err(Box::from("Realtime streams need at least one channel"));
+        }
+        let channels = spec.channels as usize;
+        Ok(RealtimeEncoder {
+            channels,
+            packet_frames,
+            cascades: (0..channels)
+                .map(|_| LmsCascade::new(DEFAULT_STAGES))
+                .collect(),
+            grid: None,
+            sent_grid: None,
+            pending: Vec::with_capacity(packet_frames * channels),
+        })
+    }
+
+    /// Buffers interleaved samples and returns every packet they complete.
+    pub fn push(&mut self, samples: &[i16]) -> Vec<Vec<u8>> {
+        let packet_len = self.packet_frames * self.channels;
+        let mut packets = Vec::new();
+        for &sample in samples {
+            self.pending.push(sample);
+            if self.pending.len() == packet_len {
+                packets.push(self.encode_pending());
+            }
+        }
+        packets
+    }
+
+    /// Samples waiting for their packet to fill.
+    pub fn buffered(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Emits the buffered samples as a short packet, if there are any.
+    pub fn flush(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        if !self.pending.len().is_multiple_of(self.channels) {
+            return Err(Box::from(format!(
+                "Cannot flush a partial frame of {} samples",
+                self.pending.len() % self.channels
+            )));
+        }
+        if self.pending.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(self.encode_pending()))
+    }
+
+    fn encode_pending(&mut self) -> Vec<u8> {
+        let frames = self.pending.len() / self.channels;
+        let mut writer = BitWriter::with_capacity(BitOrder::Msb, self.pending.len() * 2 + 8);
+        writer.write_bits(frames as u32, 16);
+
+        // The coarsest grid holding both the current grid and this packet.
+        // Grids only ever get finer, so a packet of one repeated value, such
+        // as a flat lead-in, says nothing about the grid: it is coded on the
+        // unit grid and the grid stays unknown.
+        let flat = self.pending.iter().all(|&s| s == self.pending[0]);
+        let grid = match self.grid {
+            None if flat => (1, 0),
+            grid => {
+                let mut values: Vec<i32> = self.pending.iter().map(|&s| s as i32).collect();
+                if let Some((step, offset)) = grid {
+                    values.extend([offset, offset + step]);
+                }
+                let grid = detect_grid(&values);
+                self.grid = Some(grid);
+                grid
+            }
+        };
+        writer.write_bit(self.sent_grid != Some(grid));
+        if self.sent_grid != Some(grid) {
+            writer.write_bits(grid.0 as u32, 16);
+            writer.write_bits(grid.1 as u32, 16);
+            self.sent_grid = Some(grid);
+        }
+
+        for (channel, cascade) in self.cascades.iter_mut().enumerate() {
+            let residuals: Vec<i32> = self.pending[channel..]
+                .iter()
+                .step_by(self.channels)
+                .map(|&sample| {
+                    let predicted = snap(cascade.predict(), grid);
+                    cascade.encode_sample(sample as i32);
+                    ((sample as i64 - predicted) / grid.0 as i64) as i32
+                })
+                .collect();
+            encode_residuals(&mut writer, &residuals);
+        }
+
+        self.pending.clear();
+        writer.finish()
+    }
+}
+
+/// Estimated cost of a [`RealtimeEncoder`] with packets of `packet_frames`
+/// frames. Its memory is all state: the cascades and one packet of samples,
+/// grid values and residuals.
+pub fn cost_realtime(spec: &WavSpec, packet_frames: usize) -> CostModel {
+    let channels = spec.channels as usize;
+    let taps: usize = DEFAULT_STAGES.iter().map(|&(_, order)| order).sum();
+    let cascade = cascade_cost(DEFAULT_STAGES);
+    let packet = CostModel {
+        // The extra prediction for snapping, the snap itself, the division
+        // into grid steps and the grid's gcd.
+        multiplies: taps as f64 + 2.0 + 1.0 + 2.0,
+        additions: taps as f64 + 6.0 + 2.0 + 3.0,
+        state_bytes: packet_frames * channels * (2 + 4 + 4),
+        buffer_bytes_per_sample: 0.0,
+    };
+    let cost = cascade + packet + encode_residuals_cost();
+    CostModel {
+        state_bytes: cost.state_bytes + cascade.state_bytes * channels.saturating_sub(1),
+        ..cost
+    }
+}
+
+/// Decodes the packets of a [`RealtimeEncoder`] one at a time, in order.
+pub struct RealtimeDecoder {
+    channels: usize,
+    packet_frames: usize,
+    cascades: Vec<LmsCascade>,
+    grid: Option<(i32, i32)>,
+}
+
+impl RealtimeDecoder {
+    pub fn new(
+        spec: &WavSpec,
+        packet_frames: usize,
+    ) -> Result<RealtimeDecoder, Box<dyn Error + Send + Sync>> {
+        if packet_frames == 0 || packet_frames > MAX_PACKET_FRAMES || spec.channels == 0 {
+            return Err(Box::from(format!(
+                "Invalid realtime stream: {} frame packets across {} channels",
+                packet_frames, spec.channels
+            )));
+        }
+        let channels = spec.channels as usize;
+        Ok(RealtimeDecoder {
+            channels,
+            packet_frames,
+            cascades: (0..channels)
+                .map(|_| LmsCascade::new(DEFAULT_STAGES))
+                .collect(),
+            grid: None,
+        })
+    }
+
+    /// Decodes one packet into interleaved samples.
+    pub fn decode_packet(
+        &mut self,
+        packet: &[u8],
+    ) -> Result<Vec<i16>, Box<dyn Error + Send + Sync>> {
+        let mut reader = BitReader::new(packet, BitOrder::Msb);
+        let frames = reader.read_bits(16)? as usize;
+        if frames == 0 || frames > self.packet_frames {
+            return Err(Box::from(format!(
+                "Invalid realtime packet of {} frames (limit {})",
+                frames, self.packet_frames
+            )));
+        }
+        if reader.read_bit()? {
+            let step = reader.read_bits(16)? as i32;
+            let offset = reader.read_bits(16)? as i32;
+            if step == 0 || offset >= step {
+                return Err(Box::from(format!(
+                    "Invalid grid step {} with offset {}",
+                    step, offset
+                )));
+            }
+            self.grid = Some((step, offset));
+        }
+        let grid = self
+            .grid
+            .ok_or("Realtime packet uses a grid that was never sent")?;
+
+        reader.check_count(frames * self.channels, "residuals")?;
+        let mut samples = vec![0; frames * self.channels];
+        for (channel, cascade) in self.cascades.iter_mut().enumerate() {
+            let residuals = decode_residuals(&mut reader, frames)?;
+            for (frame, &residual) in residuals.iter().enumerate() {
+                let value = snap(cascade.predict(), grid) + residual as i64 * grid.0 as i64;
+                let sample = i16::try_from(value).map_err(|_| {
+                    format!("Realtime packet decodes to out-of-range sample {}", value)
+                })?;
+                cascade.encode_sample(sample as i32);
+                samples[frame * self.channels + channel] = sample;
+            }
+        }
+        Ok(samples)
+    }
+}
+
+/// Writes a packet length as a little-endian base-128 varint.
+pub fn write_length(writer: &mut BitWriter, mut length: usize) {
+    while length >= 0x80 {
+        writer.write_bits((length & 0x7F) as u32 | 0x80, 8);
+        length >>= 7;
+    }
+    writer.write_bits(length as u32, 8);
+}
+
+/// Inverse of [`write_length`].
+pub fn read_length(reader: &mut BitReader) -> Result<usize, Box<dyn Error + Send + Sync>> {
+    let mut length = 0usize;
+    for shift in (0..32).step_by(7) {
+        let byte = reader.read_bits(8)?;
+        length |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(length);
+        }
+    }
+    Err(Box::from("Packet length varint is too long"))
+}
+
+/// Compresses a whole recording as a realtime stream: the spec header and
+/// packet length, then every packet behind its byte length.
+pub fn compress_realtime(
+    samples: &[i16],
+    spec: &WavSpec,
+    packet_frames: usize,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into realtime format...");
+
+    let mut encoder = RealtimeEncoder::new(spec, packet_frames)?;
+    let mut writer = BitWriter::with_capacity(BitOrder::Msb, samples.len());
+    write_spec_header(&mut writer, spec, samples.len());
+    writer.write_bits(packet_frames as u32, 16);
+    for packet in encoder.push(samples).into_iter().chain(encoder.flush()?) {
+        write_length(&mut writer, packet.len());
+        writer.write_bytes(&packet);
+    }
+
+    debug!("Finished compressing data into realtime format");
+    Ok(writer.finish())
+}
+
+pub fn decompress_realtime(
+    buffer: &[u8],
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from realtime format...");
+
+    let mut reader = BitReader::new(buffer, BitOrder::Msb);
+    let (spec, sample_count) = read_spec_header(&mut reader)?;
+    let packet_frames = reader.read_bits(16)? as usize;
+    reader.check_count(sample_count, "samples")?;
+    let mut decoder = RealtimeDecoder::new(&spec, packet_frames)?;
+
+    let mut samples = Vec::with_capacity(sample_count.min(buffer.len() * 8));
+    while samples.len() < sample_count {
+        let length = read_length(&mut reader)?;
+        let packet = decoder.decode_packet(reader.read_bytes(length)?)?;
+        if samples.len() + packet.len() > sample_count {
+            return Err(Box::from(
+                "Realtime stream holds more samples than its header",
+            ));
+        }
+        samples.extend(packet);
+    }
+
+    debug!("Finished decompressing data from realtime format");
+    Ok((samples, spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{generate, SynthConfig};
+
+    #[test]
+    fn packets_decode_as_they_arrive() {
+        let (samples, spec) = generate(&SynthConfig {
+            channels: 2,
+            frames: 2000,
+            ..SynthConfig::default()
+        });
+        let mut encoder = RealtimeEncoder::new(&spec, 39).unwrap();
+        let mut decoder = RealtimeDecoder::new(&spec, 39).unwrap();
+
+        // Nothing leaves the encoder until a packet is full.
+        assert!(encoder.push(&samples[..38 * 2]).is_empty());
+        let mut packets = encoder.push(&samples[38 * 2..]);
+        assert_eq!(packets.len(), 2000 / 39);
+        packets.extend(encoder.flush().unwrap());
+
+        let mut decoded = Vec::new();
+        for packet in &packets {
+            decoded.extend(decoder.decode_packet(packet).unwrap());
+        }
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn longer_packets_compress_better() {
+        let (samples, spec) = generate(&SynthConfig {
+            frames: 20_000,
+            ..SynthConfig::default()
+        });
+        assert_eq!(packet_frames_for_latency(2.0, spec.sample_rate), 39);
+
+        let sizes: Vec<usize> = [1.0, 10.0, 1000.0]
+            .iter()
+            .map(|&ms| {
+                let frames = packet_frames_for_latency(ms, spec.sample_rate);
+                let compressed = compress_realtime(&samples, &spec, frames).unwrap();
+                assert_eq!(
+                    decompress_realtime(&compressed).unwrap(),
+                    (samples.clone(), spec)
+                );
+                compressed.len()
+            })
+            .collect();
+        assert!(sizes[0] > sizes[1] && sizes[1] > sizes[2], "{:?}", sizes);
+        // The grid is found, so even 1 ms packets beat raw 16-bit PCM by far.
+        assert!(sizes[0] < samples.len(), "{:?}", sizes);
+    }
+
+    #[test]
+    fn flat_lead_in_does_not_fix_the_grid() {
+        let (samples, spec) = generate(&SynthConfig {
+            frames: 20_000,
+            ..SynthConfig::default()
+        });
+        let mut led_in = vec![0; 39];
+        led_in.extend(&samples);
+
+        let plain = compress_realtime(&samples, &spec, 39).unwrap();
+        let compressed = compress_realtime(&led_in, &spec, 39).unwrap();
+        assert_eq!(decompress_realtime(&compressed).unwrap(), (led_in, spec));
+        // The lead-in costs its own packet, not the 64-step grid of the rest.
+        assert!(
+            compressed.len() < plain.len() + 100,
+            "{} vs {}",
+            compressed.len(),
+            plain.len()
+        );
+    }
+}
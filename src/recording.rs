@@ -0,0 +1,323 @@
+use crate::cm::SharedStats;
+use crate::codec::decompress_with;
+use crate::intan::{read_intan, write_intan};
+use crate::nsx::{read_nsx, write_nsx};
+use crate::openephys::{read_openephys, write_openephys};
+use crate::wav::{read_to_end_limited, read_wav_file};
+use hound::WavSpec;
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tracing::debug;
+
+/// Tag of a compressed recording: the format, its metadata and then an
+/// ordinary codec stream of the samples. Sits below [`crate::codec`]'s
+/// blocked-container tag and above every codec tag.
+pub(crate) const RECORDING_TAG: u8 = 0xFE;
+
+/// zstd level for recording metadata, which is mostly timestamps and
+/// auxiliary channels and far smaller than the samples.
+const METADATA_LEVEL: i32 = 19;
+
+/// Acquisition file formats besides WAV. Their neural channels go through
+/// the codecs like WAV samples; everything else in the file is kept as
+/// metadata so decompression restores the original byte for byte.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecordingFormat {
+    /// Intan RHD2000 `.rhd` and RHS2000 `.rhs`; amplifier channels only.
+    Intan,
+    /// The `continuous.dat` of an Open Ephys binary recording.
+    OpenEphys,
+    /// Blackrock `.ns1`–`.ns6`.
+    Nsx,
+}
+
+impl RecordingFormat {
+    pub fn from_name(name: &str) -> Result<RecordingFormat, Box<dyn Error + Send + Sync>> {
+        match name {
+            "intan" | "rhd" | "rhs" => Ok(RecordingFormat::Intan),
+            "openephys" => Ok(RecordingFormat::OpenEphys),
+            "nsx" => Ok(RecordingFormat::Nsx),
+            _ => Err(Box::from(format!(
+                "Unknown recording format: {} (expected rhd, rhs, openephys or nsx)",
+                name
+            ))),
+        }
+    }
+
+    /// The format a file's extension implies, if any: `.rhd`, `.rhs`, `.dat`
+    /// (Open Ephys) or `.ns1`–`.ns6`.
+    pub fn from_path(path: &Path) -> Option<RecordingFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "rhd" | "rhs" => Some(RecordingFormat::Intan),
+            "dat" => Some(RecordingFormat::OpenEphys),
+            "ns1" | "ns2" | "ns3" | "ns4" | "ns5" | "ns6" => Some(RecordingFormat::Nsx),
+            _ => None,
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            RecordingFormat::Intan => 1,
+            RecordingFormat::OpenEphys => 2,
+            RecordingFormat::Nsx => 3,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<RecordingFormat, Box<dyn Error + Send + Sync>> {
+        match id {
+            1 => Ok(RecordingFormat::Intan),
+            2 => Ok(RecordingFormat::OpenEphys),
+            3 => Ok(RecordingFormat::Nsx),
+            _ => Err(Box::from(format!("Unknown recording format id {}", id))),
+        }
+    }
+}
+
+/// What a format reader returns: interleaved samples, their spec and the
+/// metadata its writer needs to restore the file.
+pub type RecordingParts = (Vec<i16>, WavSpec, Vec<u8>);
+
+/// A recording's format, zstd-compressed metadata and codec stream, as
+/// stored by [`wrap_recording`].
+pub(crate) type WrappedRecording<'a> = (RecordingFormat, &'a [u8], &'a [u8]);
+
+/// Samples of a WAV file or acquisition recording, and the format and
+/// metadata of recordings that are not WAV.
+pub type SampleFile = (Vec<i16>, WavSpec, Option<(RecordingFormat, Vec<u8>)>);
+
+/// Interleaved neural samples plus the format-specific rest of the file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Recording {
+    pub format: RecordingFormat,
+    pub samples: Vec<i16>,
+    pub spec: WavSpec,
+    pub metadata: Vec<u8>,
+}
+
+pub fn read_recording(
+    path: &Path,
+    format: RecordingFormat,
+) -> Result<Recording, Box<dyn Error + Send + Sync>> {
+    debug!("Reading {:?} recording from {}", format, path.display());
+    let (samples, spec, metadata) = match format {
+        RecordingFormat::Intan => read_intan(&fs::read(path)?)?,
+        RecordingFormat::OpenEphys => read_openephys(path)?,
+        RecordingFormat::Nsx => read_nsx(&fs::read(path)?)?,
+    };
+    Ok(Recording {
+        format,
+        samples,
+        spec,
+        metadata,
+    })
+}
+
+/// Whether the codecs take `path`: a `.wav` or an acquisition format.
+pub fn is_sample_file(path: &Path) -> bool {
+    RecordingFormat::from_path(path).is_some()
+        || path.extension().and_then(|s| s.to_str()) == Some("wav")
+}
+
+/// Reads a WAV file or, by extension, an acquisition recording. `None` for
+/// files that are neither.
+pub fn read_sample_file(path: &Path) -> Result<Option<SampleFile>, Box<dyn Error + Send + Sync>> {
+    if let Some(format) = RecordingFormat::from_path(path) {
+        let recording = read_recording(path, format)?;
+        return Ok(Some((
+            recording.samples,
+            recording.spec,
+            Some((recording.format, recording.metadata)),
+        )));
+    }
+    if !is_sample_file(path) {
+        return Ok(None);
+    }
+    let (samples, spec) = read_wav_file(path.to_str().ok_or("Input path is not valid UTF-8")?)?;
+    Ok(Some((samples, spec, None)))
+}
+
+/// Writes a recording back in its own format. For Open Ephys that is the
+/// `continuous.dat` alone.
+pub fn write_recording<W: Write>(
+    writer: W,
+    recording: &Recording,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    write_recording_parts(
+        writer,
+        recording.format,
+        &recording.samples,
+        &recording.metadata,
+    )
+}
+
+/// [`write_recording`] from the parts a format reader returned.
+pub fn write_recording_parts<W: Write>(
+    writer: W,
+    format: RecordingFormat,
+    samples: &[i16],
+    metadata: &[u8],
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match format {
+        RecordingFormat::Intan => write_intan(writer, samples, metadata),
+        RecordingFormat::OpenEphys => write_openephys(writer, samples, metadata),
+        RecordingFormat::Nsx => write_nsx(writer, samples, metadata),
+    }
+}
+
+/// Puts a recording's format and metadata in front of `stream`, the output
+/// of any codec for its samples.
+pub fn wrap_recording(
+    format: RecordingFormat,
+    metadata: &[u8],
+    stream: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let compressed = zstd::encode_all(metadata, METADATA_LEVEL)?;
+    debug!(
+        "Recording metadata: {} bytes, {} compressed",
+        metadata.len(),
+        compressed.len()
+    );
+    let mut output = Vec::with_capacity(6 + compressed.len() + stream.len());
+    output.push(RECORDING_TAG);
+    output.push(format.id());
+    output.extend_from_slice(&(compressed.len() as u32).to_be_bytes());
+    output.extend_from_slice(&compressed);
+    output.extend_from_slice(stream);
+    Ok(output)
+}
+
+/// Splits the output of [`wrap_recording`] into the format, the compressed
+/// metadata and the codec stream. `None` for a plain codec stream.
+pub(crate) fn split_recording(
+    buffer: &[u8],
+) -> Result<Option<WrappedRecording<'_>>, Box<dyn Error + Send + Sync>> {
+    if buffer.first() != Some(&RECORDING_TAG) {
+        return Ok(None);
+    }
+    let header = buffer.get(..6).ok_or("Truncated recording header")?;
+    let format = RecordingFormat::from_id(header[1])?;
+    let metadata_len = u32::from_be_bytes([header[2], header[3], header[4], header[5]]) as usize;
+    let metadata = buffer
+        .get(6..6usize.saturating_add(metadata_len))
+        .ok_or("Truncated recording metadata")?;
+    let stream = &buffer[6 + metadata_len..];
+    if stream.first() == Some(&RECORDING_TAG) {
+        return Err(Box::from("Nested recording containers are not valid"));
+    }
+    Ok(Some((format, metadata, stream)))
+}
+
+/// Decompresses a buffer written by [`wrap_recording`], or returns `None`
+/// for a plain codec stream, which [`crate::codec::decompress`] handles.
+pub fn decompress_recording(
+    buffer: &[u8],
+) -> Result<Option<Recording>, Box<dyn Error + Send + Sync>> {
+    decompress_recording_with(buffer, None)
+}
+
+/// [`decompress_recording`] for samples coded against shared statistics.
+pub fn decompress_recording_with(
+    buffer: &[u8],
+    stats: Option<&SharedStats>,
+) -> Result<Option<Recording>, Box<dyn Error + Send + Sync>> {
+    let Some((format, metadata, _)) = split_recording(buffer)? else {
+        return Ok(None);
+    };
+    let metadata = read_to_end_limited(zstd::Decoder::new(metadata)?)?;
+    let (samples, spec) = decompress_with(buffer, stats)?;
+    Ok(Some(Recording {
+        format,
+        samples,
+        spec,
+        metadata,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{decompress, Codec};
+    use crate::intan::tests::intan_file;
+    use crate::intan::IntanKind;
+    use crate::nsx::tests::nsx_file;
+    use crate::openephys::tests::openephys_recording;
+
+    fn roundtrip(path: &Path, format: RecordingFormat, codec: Codec) {
+        let original = fs::read(path).unwrap();
+        let recording = read_recording(path, format).unwrap();
+        let stream = codec.compress(&recording.samples, &recording.spec).unwrap();
+        let compressed = wrap_recording(recording.format, &recording.metadata, &stream).unwrap();
+
+        // Plain decompression still sees just the samples.
+        assert_eq!(
+            decompress(&compressed).unwrap(),
+            (recording.samples.clone(), recording.spec)
+        );
+        let restored = decompress_recording(&compressed).unwrap().unwrap();
+        assert_eq!(restored, recording);
+        let mut bytes = Vec::new();
+        write_recording(&mut bytes, &restored).unwrap();
+        assert_eq!(bytes, original);
+    }
+
+    #[test]
+    fn every_format_roundtrips_through_a_codec() {
+        let dir = std::env::temp_dir().join(format!("smallbrain-recording-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let rhd = dir.join("session.rhd");
+        fs::write(&rhd, intan_file(IntanKind::Rhd, 4, 3)).unwrap();
+        let rhs = dir.join("session.rhs");
+        fs::write(&rhs, intan_file(IntanKind::Rhs, 2, 2)).unwrap();
+        let ns5 = dir.join("session.ns5");
+        fs::write(&ns5, nsx_file(4)).unwrap();
+        let dat = openephys_recording(&dir.join("Record Node 101/recording1"), 2, 1000);
+
+        for path in [&rhd, &rhs, &ns5, &dat] {
+            let format = RecordingFormat::from_path(path).unwrap();
+            for codec in [Codec::Zstd, Codec::Rice, Codec::LmsAns] {
+                roundtrip(path, format, codec);
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[ignore = "builds a 134 MB recording; the split itself is tested in codec"]
+    fn recordings_longer_than_a_minute_roundtrip() {
+        // 70 s of 32 channels at 30 kHz: more samples than one codec stream
+        // holds, so the codec splits it into blocks.
+        let dir =
+            std::env::temp_dir().join(format!("smallbrain-recording-long-{}", std::process::id()));
+        let dat = openephys_recording(&dir, 32, 70 * 30000);
+        let recording = read_recording(&dat, RecordingFormat::OpenEphys).unwrap();
+        let stream = Codec::Zstd
+            .compress(&recording.samples, &recording.spec)
+            .unwrap();
+        let compressed = wrap_recording(recording.format, &recording.metadata, &stream).unwrap();
+        let mut bytes = Vec::new();
+        write_recording(
+            &mut bytes,
+            &decompress_recording(&compressed).unwrap().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(bytes, fs::read(&dat).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn plain_streams_are_not_recordings() {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let stream = Codec::Rice.compress(&[1, 2, 3], &spec).unwrap();
+        assert_eq!(decompress_recording(&stream).unwrap(), None);
+        assert!(decompress_recording(&[RECORDING_TAG, 1, 0, 0, 0, 9]).is_err());
+        assert!(decompress_recording(&[RECORDING_TAG, 7, 0, 0, 0, 0]).is_err());
+    }
+}
@@ -0,0 +1,573 @@
+use crate::bitpack::{BitOrder, BitReader, BitWriter};
+use crate::realtime::{read_length, write_length, RealtimeDecoder, RealtimeEncoder};
+use crate::synth::Rng;
+use crate::wav::{MAX_RECORDING_SAMPLES, MAX_SAMPLES};
+use flate2::Crc;
+use hound::{SampleFormat, WavSpec};
+use std::error::Error;
+use std::fmt;
+
+/// Marks the start of every frame, so a byte stream can be resynchronized
+/// after corruption.
+pub const SYNC: [u8; 2] = [0xA5, 0x5B];
+
+/// Packets between predictor resets: 100 ms with the default 2 ms packets.
+pub const DEFAULT_RESET_INTERVAL: u64 = 50;
+
+/// Flag of frames whose packet starts from fresh predictor and grid state.
+const RESET_FLAG: u8 = 1;
+
+/// Loss-resilient framing around [`RealtimeEncoder`] packets.
+///
+/// Frame layout: [`SYNC`], 16-bit sequence number, flags, for reset frames
+/// the stream parameters (sample rate, channels, bits per sample, packet
+/// frames), the payload length as a varint, the realtime packet and a CRC-32
+/// over everything after the sync bytes. Every `reset_interval` packets the
+/// predictors start over, so a lost packet only spoils the packets up to the
+/// next reset, and a receiver can join at any reset frame.
+pub struct ResilientEncoder {
+    spec: WavSpec,
+    packet_frames: usize,
+    reset_interval: u64,
+    sequence: u64,
+    encoder: RealtimeEncoder,
+}
+
+impl ResilientEncoder {
+    pub fn new(
+        spec: &WavSpec,
+        packet_frames: usize,
+        reset_interval: u64,
+    ) -> Result<ResilientEncoder, Box<dyn Error + Send + Sync>> {
+        if reset_interval == 0 {
+            return Err(Box::from("Reset interval must be at least one packet"));
+        }
+        Ok(ResilientEncoder {
+            spec: *spec,
+            packet_frames,
+            reset_interval,
+            sequence: 0,
+            encoder: RealtimeEncoder::new(spec, packet_frames)?,
+        })
+    }
+
+    /// Buffers interleaved samples and returns the frames they complete.
+    pub fn push(
+        &mut self,
+        mut samples: &[i16],
+    ) -> Result<Vec<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        let packet_len = self.packet_frames * self.spec.channels as usize;
+        let mut frames = Vec::new();
+        while !samples.is_empty() {
+            if self.encoder.buffered() == 0 && self.sequence.is_multiple_of(self.reset_interval) {
+                self.encoder = RealtimeEncoder::new(&self.spec, self.packet_frames)?;
+            }
+            let take = (packet_len - self.encoder.buffered()).min(samples.len());
+            for packet in self.encoder.push(&samples[..take]) {
+                frames.push(self.frame(&packet));
+            }
+            samples = &samples[take..];
+        }
+        Ok(frames)
+    }
+
+    /// Emits the buffered samples as a short final frame, if there are any.
+    pub fn flush(&mut self) -> Result<Option<Vec<u8>>, Box<dyn Error + Send + Sync>> {
+        Ok(self.encoder.flush()?.map(|packet| self.frame(&packet)))
+    }
+
+    fn frame(&mut self, packet: &[u8]) -> Vec<u8> {
+        let reset = self.sequence.is_multiple_of(self.reset_interval);
+        let mut writer = BitWriter::with_capacity(BitOrder::Msb, packet.len() + 16);
+        writer.write_bits(self.sequence as u16 as u32, 16);
+        writer.write_bits(if reset { RESET_FLAG as u32 } else { 0 }, 8);
+        if reset {
+            writer.write_bits(self.spec.sample_rate, 32);
+            writer.write_bits(self.spec.channels as u32, 16);
+            writer.write_bits(self.spec.bits_per_sample as u32, 16);
+            writer.write_bits(self.packet_frames as u32, 16);
+        }
+        write_length(&mut writer, packet.len());
+        writer.write_bytes(packet);
+        let body = writer.finish();
+
+        let mut crc = Crc::new();
+        crc.update(&body);
+        let mut frame = Vec::with_capacity(body.len() + 6);
+        frame.extend_from_slice(&SYNC);
+        frame.extend_from_slice(&body);
+        frame.extend_from_slice(&crc.sum().to_be_bytes());
+        self.sequence += 1;
+        frame
+    }
+}
+
+/// A parsed frame whose CRC checked out.
+struct Frame<'a> {
+    sequence: u16,
+    /// Spec and packet length carried by reset frames.
+    header: Option<(WavSpec, usize)>,
+    packet: &'a [u8],
+}
+
+/// Parses the frame at the start of `bytes`, returning it and its length.
+fn parse_frame(bytes: &[u8]) -> Result<(Frame<'_>, usize), Box<dyn Error + Send + Sync>> {
+    if !bytes.starts_with(&SYNC) {
+        return Err(Box::from("Missing frame sync"));
+    }
+    let mut reader = BitReader::new(&bytes[SYNC.len()..], BitOrder::Msb);
+    let sequence = reader.read_bits(16)? as u16;
+    let flags = reader.read_bits(8)? as u8;
+    let header = if flags & RESET_FLAG != 0 {
+        let spec = WavSpec {
+            sample_rate: reader.read_bits(32)?,
+            channels: reader.read_bits(16)? as u16,
+            bits_per_sample: reader.read_bits(16)? as u16,
+            sample_format: SampleFormat::Int,
+        };
+        Some((spec, reader.read_bits(16)? as usize))
+    } else {
+        None
+    };
+    let length = read_length(&mut reader)?;
+    let packet = reader.read_bytes(length)?;
+    let body_len = bytes.len() - SYNC.len() - (reader.bits_remaining() / 8) as usize;
+    let crc_bytes = reader.read_bytes(4)?;
+
+    let mut crc = Crc::new();
+    crc.update(&bytes[SYNC.len()..SYNC.len() + body_len]);
+    if crc.sum().to_be_bytes() != crc_bytes {
+        return Err(Box::from(format!("CRC mismatch in frame {}", sequence)));
+    }
+    let frame = Frame {
+        sequence,
+        header,
+        packet,
+    };
+    Ok((frame, SYNC.len() + body_len + 4))
+}
+
+/// What the decoder writes in place of samples it could not recover.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GapFill {
+    /// Leave gaps out, so the output is shorter than the recording.
+    Skip,
+    /// Zero samples, keeping the output aligned with the recording.
+    Zero,
+    /// Repeat the last frame before the gap.
+    Hold,
+}
+
+impl GapFill {
+    pub fn from_name(name: &str) -> Result<GapFill, Box<dyn Error + Send + Sync>> {
+        match name {
+            "skip" => Ok(GapFill::Skip),
+            "zero" => Ok(GapFill::Zero),
+            "hold" => Ok(GapFill::Hold),
+            _ => Err(Box::from(format!(
+                "Unknown gap fill: {} (expected skip, zero or hold)",
+                name
+            ))),
+        }
+    }
+}
+
+/// A run of frames the decoder could not recover.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Gap {
+    /// First missing frame, counted in the sender's timeline.
+    pub start: u64,
+    pub frames: u64,
+    /// Packets lost or undecodable in this run.
+    pub packets: u64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct LossReport {
+    /// Frames that arrived intact and in order.
+    pub received: u64,
+    /// Sequence numbers that never arrived intact, corrupt frames included.
+    /// Losses after the last received frame cannot be seen.
+    pub lost: u64,
+    /// Frames rejected by their CRC (or, in a byte stream, runs of bytes
+    /// skipped while resynchronizing).
+    pub corrupt: u64,
+    /// Duplicate or out-of-order frames that were dropped.
+    pub late: u64,
+    /// Received packets that could not be decoded because an earlier loss
+    /// broke the predictor state before the next reset.
+    pub undecodable: u64,
+    pub frames_decoded: u64,
+    pub gaps: Vec<Gap>,
+}
+
+impl LossReport {
+    pub fn frames_missing(&self) -> u64 {
+        self.gaps.iter().map(|g| g.frames).sum()
+    }
+}
+
+impl fmt::Display for LossReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "received {}, lost {}, corrupt {}, late {}, undecodable {}, \
+             {} gaps, {} frames decoded, {} frames missing",
+            self.received,
+            self.lost,
+            self.corrupt,
+            self.late,
+            self.undecodable,
+            self.gaps.len(),
+            self.frames_decoded,
+            self.frames_missing()
+        )
+    }
+}
+
+/// Decodes [`ResilientEncoder`] frames as they arrive, reporting the gaps
+/// left by lost or damaged frames instead of failing.
+pub struct ResilientDecoder {
+    fill: GapFill,
+    spec: Option<WavSpec>,
+    packet_frames: u64,
+    /// `None` until a reset frame arrives and again after every loss.
+    decoder: Option<RealtimeDecoder>,
+    /// Sequence number expected next, not wrapped.
+    expected: u64,
+    /// Frames of the sender's timeline accounted for so far.
+    position: u64,
+    /// Gap packets whose length is unknown until the first reset frame.
+    deferred: u64,
+    gap_open: bool,
+    last_frame: Vec<i16>,
+    report: LossReport,
+}
+
+impl ResilientDecoder {
+    pub fn new(fill: GapFill) -> ResilientDecoder {
+        ResilientDecoder {
+            fill,
+            spec: None,
+            packet_frames: 0,
+            decoder: None,
+            expected: 0,
+            position: 0,
+            deferred: 0,
+            gap_open: false,
+            last_frame: Vec::new(),
+            report: LossReport::default(),
+        }
+    }
+
+    /// The stream's spec, once a reset frame has arrived.
+    pub fn spec(&self) -> Option<WavSpec> {
+        self.spec
+    }
+
+    pub fn report(&self) -> &LossReport {
+        &self.report
+    }
+
+    pub fn finish(self) -> LossReport {
+        self.report
+    }
+
+    /// Handles one received frame and returns the samples it releases: fill
+    /// for any gap it reveals, then its own samples. Damaged, late and
+    /// undecodable frames are counted in the report rather than failing.
+    pub fn receive(&mut self, bytes: &[u8]) -> Result<Vec<i16>, Box<dyn Error + Send + Sync>> {
+        match parse_frame(bytes) {
+            Ok((frame, _)) => self.accept(frame),
+            Err(_) => {
+                self.report.corrupt += 1;
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn accept(&mut self, frame: Frame) -> Result<Vec<i16>, Box<dyn Error + Send + Sync>> {
+        let missed = frame.sequence.wrapping_sub(self.expected as u16) as u64;
+        if missed >= 0x8000 {
+            self.report.late += 1;
+            return Ok(Vec::new());
+        }
+        self.report.received += 1;
+        self.expected += missed + 1;
+
+        let mut output = Vec::new();
+        if missed > 0 {
+            self.report.lost += missed;
+            self.decoder = None;
+            self.extend_gap(missed, &mut output)?;
+        }
+
+        if let Some((spec, packet_frames)) = frame.header {
+            match self.spec {
+                Some(old) if old != spec || self.packet_frames != packet_frames as u64 => {
+                    return Err(Box::from("Stream parameters changed mid-stream"));
+                }
+                _ => {}
+            }
+            self.decoder = RealtimeDecoder::new(&spec, packet_frames).ok();
+            if self.decoder.is_some() && self.spec.is_none() {
+                self.spec = Some(spec);
+                self.packet_frames = packet_frames as u64;
+                self.last_frame = vec![0; spec.channels as usize];
+                // Gaps before the first reset frame can be placed now.
+                self.extend_gap(0, &mut output)?;
+            }
+        }
+
+        match self.decoder.as_mut().map(|d| d.decode_packet(frame.packet)) {
+            Some(Ok(samples)) => {
+                let channels = self.last_frame.len();
+                let frames = (samples.len() / channels) as u64;
+                if let Some(last) = samples.len().checked_sub(channels) {
+                    self.last_frame.copy_from_slice(&samples[last..]);
+                }
+                self.gap_open = false;
+                self.position += frames;
+                self.report.frames_decoded += frames;
+                output.extend(samples);
+            }
+            _ => {
+                self.report.undecodable += 1;
+                self.decoder = None;
+                self.extend_gap(1, &mut output)?;
+            }
+        }
+        Ok(output)
+    }
+
+    /// Adds `packets` to the current gap, writing its fill once the packet
+    /// length is known.
+    fn extend_gap(
+        &mut self,
+        packets: u64,
+        output: &mut Vec<i16>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        if packets > 0 {
+            match self.report.gaps.last_mut() {
+                Some(gap) if self.gap_open => gap.packets += packets,
+                _ => {
+                    self.report.gaps.push(Gap {
+                        start: self.position,
+                        frames: 0,
+                        packets,
+                    });
+                    self.gap_open = true;
+                }
+            }
+            self.deferred += packets;
+        }
+        if self.packet_frames == 0 || self.deferred == 0 {
+            return Ok(());
+        }
+
+        // Every packet but the last is full, and the last is never followed by
+        // another, so lost packets were `packet_frames` long.
+        let frames = self.deferred * self.packet_frames;
+        self.deferred = 0;
+        if let Some(gap) = self.report.gaps.last_mut() {
+            gap.frames += frames;
+        }
+        self.position += frames;
+
+        let fill_len = frames * self.last_frame.len() as u64;
+        if self.fill != GapFill::Skip && output.len() as u64 + fill_len > MAX_SAMPLES as u64 {
+            return Err(Box::from(format!(
+                "Gap of {} frames is too long to fill",
+                frames
+            )));
+        }
+        match self.fill {
+            GapFill::Skip => {}
+            GapFill::Zero => output.resize(output.len() + fill_len as usize, 0),
+            GapFill::Hold => {
+                for _ in 0..frames {
+                    output.extend_from_slice(&self.last_frame);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Encodes a whole recording as a byte stream of concatenated frames.
+pub fn compress_stream(
+    samples: &[i16],
+    spec: &WavSpec,
+    packet_frames: usize,
+    reset_interval: u64,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut encoder = ResilientEncoder::new(spec, packet_frames, reset_interval)?;
+    let mut stream = Vec::with_capacity(samples.len());
+    for frame in encoder.push(samples)?.into_iter().chain(encoder.flush()?) {
+        stream.extend_from_slice(&frame);
+    }
+    Ok(stream)
+}
+
+/// Decodes a byte stream of frames, skipping damaged stretches by scanning
+/// for the next [`SYNC`].
+pub fn decompress_stream(
+    stream: &[u8],
+    fill: GapFill,
+) -> Result<(Vec<i16>, WavSpec, LossReport), Box<dyn Error + Send + Sync>> {
+    let mut decoder = ResilientDecoder::new(fill);
+    let mut samples = Vec::new();
+    let mut position = 0;
+    let mut resyncing = false;
+    while position < stream.len() {
+        match parse_frame(&stream[position..]) {
+            Ok((frame, length)) => {
+                samples.extend(decoder.accept(frame)?);
+                if samples.len() > MAX_RECORDING_SAMPLES {
+                    return Err(Box::from(format!(
+                        "Stream exceeds the limit of {} samples",
+                        MAX_RECORDING_SAMPLES
+                    )));
+                }
+                position += length;
+                resyncing = false;
+            }
+            Err(_) => {
+                if !resyncing {
+                    decoder.report.corrupt += 1;
+                    resyncing = true;
+                }
+                position = stream[position + 1..]
+                    .windows(SYNC.len())
+                    .position(|w| w == SYNC)
+                    .map_or(stream.len(), |i| position + 1 + i);
+            }
+        }
+    }
+    let spec = decoder.spec().ok_or("Stream holds no decodable frame")?;
+    Ok((samples, spec, decoder.finish()))
+}
+
+/// Random impairments applied by [`simulate_loss`].
+#[derive(Clone, Debug)]
+pub struct LossConfig {
+    /// Probability that a frame is dropped.
+    pub loss: f64,
+    /// Probability that a delivered frame has one bit flipped.
+    pub corruption: f64,
+    pub seed: u64,
+}
+
+/// Drops and damages frames the way a lossy link would, deterministically
+/// for a given seed.
+pub fn simulate_loss(frames: &[Vec<u8>], config: &LossConfig) -> Vec<Vec<u8>> {
+    let mut rng = Rng::new(config.seed);
+    let mut delivered = Vec::with_capacity(frames.len());
+    for frame in frames {
+        if rng.uniform() < config.loss {
+            continue;
+        }
+        let mut frame = frame.clone();
+        if rng.uniform() < config.corruption && !frame.is_empty() {
+            let bit = rng.next_u64() % (frame.len() as u64 * 8);
+            frame[(bit / 8) as usize] ^= 1 << (bit % 8);
+        }
+        delivered.push(frame);
+    }
+    delivered
+}
+
+/// What a [`ResilientDecoder`] using `fill` should output for `samples`
+/// given the gaps it reported; decoded stretches are bit exact.
+pub fn expected_output(samples: &[i16], channels: usize, gaps: &[Gap], fill: GapFill) -> Vec<i16> {
+    let mut output = Vec::with_capacity(samples.len());
+    let mut last_frame = vec![0; channels];
+    let mut position = 0;
+    for gap in gaps {
+        let start = (gap.start as usize * channels).min(samples.len());
+        output.extend_from_slice(&samples[position.min(start)..start]);
+        if start >= channels {
+            last_frame.copy_from_slice(&samples[start - channels..start]);
+        }
+        match fill {
+            GapFill::Skip => {}
+            GapFill::Zero => output.resize(output.len() + gap.frames as usize * channels, 0),
+            GapFill::Hold => {
+                for _ in 0..gap.frames {
+                    output.extend_from_slice(&last_frame);
+                }
+            }
+        }
+        position = ((gap.start + gap.frames) as usize * channels).min(samples.len());
+    }
+    output.extend_from_slice(&samples[position..]);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synth::{generate, SynthConfig};
+
+    fn recording() -> (Vec<i16>, WavSpec) {
+        generate(&SynthConfig {
+            channels: 2,
+            frames: 10_000,
+            ..SynthConfig::default()
+        })
+    }
+
+    #[test]
+    fn lossless_without_impairments() {
+        let (samples, spec) = recording();
+        let stream = compress_stream(&samples, &spec, 39, 10).unwrap();
+        let (decoded, decoded_spec, report) = decompress_stream(&stream, GapFill::Skip).unwrap();
+        assert_eq!((decoded, decoded_spec), (samples, spec));
+        assert!(report.gaps.is_empty());
+        assert_eq!(report.received, 10_000 / 39 + 1);
+    }
+
+    #[test]
+    fn gaps_are_reported_and_filled() {
+        let (samples, spec) = recording();
+        let mut encoder = ResilientEncoder::new(&spec, 39, 10).unwrap();
+        let mut frames = encoder.push(&samples).unwrap();
+        frames.extend(encoder.flush().unwrap());
+        let config = LossConfig {
+            loss: 0.05,
+            corruption: 0.05,
+            seed: 3,
+        };
+
+        for fill in [GapFill::Skip, GapFill::Zero, GapFill::Hold] {
+            let mut decoder = ResilientDecoder::new(fill);
+            let mut decoded = Vec::new();
+            for frame in simulate_loss(&frames, &config) {
+                decoded.extend(decoder.receive(&frame).unwrap());
+            }
+            let report = decoder.finish();
+
+            assert!(report.lost > 0 && report.corrupt > 0 && report.undecodable > 0);
+            assert!(report.received + report.lost <= frames.len() as u64);
+            let expected = expected_output(&samples, 2, &report.gaps, fill);
+            assert_eq!(decoded, expected[..decoded.len()]);
+        }
+    }
+
+    #[test]
+    fn stream_resynchronizes_after_damage() {
+        let (samples, spec) = recording();
+        let mut stream = compress_stream(&samples, &spec, 39, 10).unwrap();
+        let middle = stream.len() / 2;
+        stream.drain(middle..middle + 7);
+
+        let (decoded, _, report) = decompress_stream(&stream, GapFill::Zero).unwrap();
+        assert_eq!(report.corrupt, 1);
+        assert_eq!(report.gaps.len(), 1);
+        assert!(report.gaps[0].packets <= 11, "{:?}", report.gaps);
+        assert_eq!(
+            decoded,
+            expected_output(&samples, 2, &report.gaps, GapFill::Zero)
+        );
+    }
+}
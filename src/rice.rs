@@ -0,0 +1,220 @@
+use crate::bitpack::{BitOrder, BitReader, BitWriter};
+use crate::cost::CostModel;
+use crate::predictor::{
+    best_fixed_order, fixed_cost, fixed_residuals, read_fixed_order, restore_fixed,
+    FIXED_BLOCK_SIZE,
+};
+use crate::wav::{deinterleave, interleave, read_spec_header, write_spec_header};
+use hound::WavSpec;
+use smallbrain_core::rice::{bit_width, ESCAPE_QUOTIENT};
+pub use smallbrain_core::rice::{unzigzag, zigzag};
+pub(crate) use smallbrain_core::rice::{write_value, MAX_PARAMETER};
+use std::error::Error;
+use tracing::debug;
+
+/// Residuals per Rice partition; each partition carries its own parameter.
+pub const PARTITION_SIZE: usize = 256;
+
+/// Bits used to store a partition's Rice parameter.
+const PARAMETER_BITS: u32 = 5;
+
+/// Exact number of bits [`write_value`] spends on `value` with parameter `k`.
+fn value_cost(value: u32, k: u32) -> u64 {
+    let quotient = value >> k;
+    if quotient < ESCAPE_QUOTIENT {
+        (quotient + 1 + k) as u64
+    } else {
+        (ESCAPE_QUOTIENT + 5 + bit_width(value)) as u64
+    }
+}
+
+/// Picks the Rice parameter for a partition. The mean of the values gives a
+/// first guess, which is refined by exact cost evaluation of its neighbours.
+pub fn estimate_parameter(values: &[u32]) -> u32 {
+    if values.is_empty() {
+        return 0;
+    }
+    let mean = values.iter().map(|&v| v as u64).sum::<u64>() / values.len() as u64;
+    let guess = if mean == 0 {
+        0
+    } else {
+        (63 - mean.leading_zeros()).min(MAX_PARAMETER)
+    };
+
+    (guess.saturating_sub(2)..=(guess + 2).min(MAX_PARAMETER))
+        .min_by_key(|&k| values.iter().map(|&v| value_cost(v, k)).sum::<u64>())
+        .unwrap()
+}
+
+pub(crate) fn read_value(
+    reader: &mut BitReader,
+    k: u32,
+) -> Result<u32, Box<dyn Error + Send + Sync>> {
+    let mut quotient = 0;
+    while !reader.read_bit()? {
+        quotient += 1;
+        if quotient == ESCAPE_QUOTIENT {
+            let width = reader.read_bits(5)? + 1;
+            return reader.read_bits(width);
+        }
+    }
+    let remainder = if k > 0 { reader.read_bits(k)? } else { 0 };
+    Ok((quotient << k) | remainder)
+}
+
+/// Rice-codes `residuals` in partitions of [`PARTITION_SIZE`], each preceded
+/// by its parameter. The count is not stored; the caller must know it.
+pub fn encode_residuals(writer: &mut BitWriter, residuals: &[i32]) {
+    for partition in residuals.chunks(PARTITION_SIZE) {
+        let values: Vec<u32> = partition.iter().map(|&r| zigzag(r)).collect();
+        let k = estimate_parameter(&values);
+        writer.write_bits(k, PARAMETER_BITS);
+        for &value in &values {
+            write_value(writer, value, k);
+        }
+    }
+}
+
+/// Encoder cost of [`encode_residuals`]: the zigzag, the mean, the exact
+/// cost of each of the five candidate parameters, then writing the value.
+pub fn encode_residuals_cost() -> CostModel {
+    CostModel {
+        multiplies: 0.0,
+        additions: 2.0 + 1.0 + 5.0 * 5.0 + 6.0,
+        state_bytes: PARTITION_SIZE * 4,
+        buffer_bytes_per_sample: 0.0,
+    }
+}
+
+/// Inverse of [`encode_residuals`].
+pub fn decode_residuals(
+    reader: &mut BitReader,
+    count: usize,
+) -> Result<Vec<i32>, Box<dyn Error + Send + Sync>> {
+    reader.check_count(count, "residuals")?;
+
+    let mut residuals = Vec::with_capacity(count);
+    while residuals.len() < count {
+        let k = reader.read_bits(PARAMETER_BITS)?;
+        if k > MAX_PARAMETER {
+            return Err(Box::from(format!("Invalid Rice parameter {}", k)));
+        }
+        let partition_len = PARTITION_SIZE.min(count - residuals.len());
+        for _ in 0..partition_len {
+            residuals.push(unzigzag(read_value(reader, k)?));
+        }
+    }
+    Ok(residuals)
+}
+
+/// Compresses samples with per-block fixed polynomial prediction followed by
+/// partitioned Rice coding of the residuals.
+pub fn compress_rice(
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into Rice format...");
+
+    let mut writer = BitWriter::with_capacity(BitOrder::Msb, samples.len());
+    write_spec_header(&mut writer, spec, samples.len());
+
+    for channel in deinterleave(samples, spec.channels as usize) {
+        for start in (0..channel.len()).step_by(FIXED_BLOCK_SIZE) {
+            let end = (start + FIXED_BLOCK_SIZE).min(channel.len());
+            let order = best_fixed_order(&channel, start, end);
+            writer.write_bits(order as u32, 3);
+            encode_residuals(&mut writer, &fixed_residuals(&channel, start, end, order));
+        }
+    }
+
+    debug!("Finished compressing data into Rice format");
+    Ok(writer.finish())
+}
+
+/// Estimated encoder cost of [`compress_rice`].
+pub fn cost_rice() -> CostModel {
+    // The deinterleaved channels are held as i32.
+    CostModel::buffer(4.0) + fixed_cost() + encode_residuals_cost()
+}
+
+pub fn decompress_rice(buffer: &[u8]) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from Rice format...");
+
+    let mut reader = BitReader::new(buffer, BitOrder::Msb);
+    let (spec, sample_count) = read_spec_header(&mut reader)?;
+    let frames = sample_count / spec.channels as usize;
+
+    let mut channels = Vec::with_capacity(spec.channels as usize);
+    for _ in 0..spec.channels {
+        let mut channel = Vec::with_capacity(frames.min(reader.bits_remaining() as usize));
+        while channel.len() < frames {
+            let order = read_fixed_order(&mut reader)?;
+            let block_len = FIXED_BLOCK_SIZE.min(frames - channel.len());
+            let residuals = decode_residuals(&mut reader, block_len)?;
+            restore_fixed(&mut channel, &residuals, order);
+        }
+        channels.push(channel);
+    }
+
+    debug!("Finished decompressing data from Rice format");
+    Ok((interleave(&channels), spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn residuals_roundtrip_with_outliers() {
+        let mut residuals: Vec<i32> = (0..1000).map(|i| (i % 17) - 8).collect();
+        residuals[10] = 1 << 20;
+        residuals[500] = i32::MIN;
+        residuals[501] = i32::MAX;
+
+        let mut writer = BitWriter::new(BitOrder::Msb);
+        encode_residuals(&mut writer, &residuals);
+        let bytes = writer.finish();
+
+        let mut reader = BitReader::new(&bytes, BitOrder::Msb);
+        assert_eq!(
+            decode_residuals(&mut reader, residuals.len()).unwrap(),
+            residuals
+        );
+    }
+
+    #[test]
+    fn codec_roundtrip() {
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let samples: Vec<i16> = (0..10_000)
+            .map(|i: i32| ((i * 37) % 2000 - 1000 + (i % 3) * 64) as i16)
+            .collect();
+
+        let compressed = compress_rice(&samples, &spec).unwrap();
+        let (decoded, decoded_spec) = decompress_rice(&compressed).unwrap();
+        assert_eq!(decoded, samples);
+        assert_eq!(decoded_spec, spec);
+    }
+
+    #[test]
+    fn orders_above_four_are_rejected() {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 30000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        for order in 0..8 {
+            let mut writer = BitWriter::new(BitOrder::Msb);
+            write_spec_header(&mut writer, &spec, 10);
+            writer.write_bits(order, 3);
+            encode_residuals(&mut writer, &[0; 10]);
+            let result = decompress_rice(&writer.finish());
+            assert_eq!(result.is_ok(), order <= 4, "order {}", order);
+        }
+    }
+}
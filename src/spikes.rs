@@ -0,0 +1,473 @@
+use crate::ans::{
+    decode_residuals_adaptive, encode_residuals_adaptive, encode_residuals_adaptive_cost,
+};
+use crate::bitpack::{BitOrder, BitReader, BitWriter};
+use crate::cost::CostModel;
+use crate::lms::{cascade_cost, LmsCascade, DEFAULT_STAGES};
+use crate::rice::{decode_residuals, encode_residuals};
+use crate::wav::{deinterleave, interleave, read_spec_header, write_spec_header};
+use hound::WavSpec;
+use std::error::Error;
+use std::io::Write;
+use tracing::debug;
+
+/// Half-width of the centred moving average subtracted by [`highpass`].
+const HIGHPASS_RADIUS: usize = 16;
+
+/// Most templates the spike codec keeps per channel.
+const MAX_TEMPLATES: usize = 64;
+
+/// A snippet joins an existing template when their squared distance is below
+/// this fraction (in 1/16ths) of the snippet's energy.
+const MATCH_FRACTION: i64 = 4;
+
+/// Firing rate per channel assumed by [`cost_spike`], in spikes per second.
+const COST_SPIKE_RATE: f64 = 20.0;
+
+/// Which threshold crossings count as spikes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Polarity {
+    Negative,
+    Positive,
+    Both,
+}
+
+impl Polarity {
+    pub fn from_name(name: &str) -> Result<Polarity, Box<dyn Error + Send + Sync>> {
+        match name {
+            "negative" => Ok(Polarity::Negative),
+            "positive" => Ok(Polarity::Positive),
+            "both" => Ok(Polarity::Both),
+            _ => Err(Box::from(format!(
+                "Unknown polarity: {} (expected negative, positive or both)",
+                name
+            ))),
+        }
+    }
+
+    /// How far past the threshold `value` is in this polarity's direction.
+    fn excursion(self, value: i32) -> i64 {
+        match self {
+            Polarity::Negative => -(value as i64),
+            Polarity::Positive => value as i64,
+            Polarity::Both => (value as i64).abs(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SpikeConfig {
+    /// Threshold as a multiple of the noise level estimated from the filtered signal.
+    pub threshold: f64,
+    pub polarity: Polarity,
+    /// Samples kept before and after the peak in each snippet.
+    pub pre: usize,
+    pub post: usize,
+    /// Minimum distance between two detected peaks.
+    pub refractory: usize,
+}
+
+impl Default for SpikeConfig {
+    fn default() -> Self {
+        SpikeConfig {
+            threshold: 4.5,
+            polarity: Polarity::Negative,
+            pre: 10,
+            post: 22,
+            refractory: 20,
+        }
+    }
+}
+
+/// One detected spike on one channel.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Spike {
+    pub channel: usize,
+    /// Sample index of the peak within the channel.
+    pub index: usize,
+    /// Filtered amplitude at the peak.
+    pub amplitude: i32,
+    /// Filtered signal from `index - pre` to `index + post`.
+    pub waveform: Vec<i32>,
+}
+
+/// Removes the slow local-field and DC components by subtracting a centred
+/// moving average, leaving the spike band.
+pub fn highpass(samples: &[i32]) -> Vec<i32> {
+    let n = samples.len();
+    let mut prefix = Vec::with_capacity(n + 1);
+    prefix.push(0i64);
+    for &s in samples {
+        prefix.push(prefix.last().unwrap() + s as i64);
+    }
+    (0..n)
+        .map(|i| {
+            let start = i.saturating_sub(HIGHPASS_RADIUS);
+            let end = (i + HIGHPASS_RADIUS + 1).min(n);
+            let mean = (prefix[end] - prefix[start]) / (end - start) as i64;
+            (samples[i] as i64 - mean) as i32
+        })
+        .collect()
+}
+
+/// Robust noise level of a filtered signal: `median(|x|) / 0.6745`.
+pub fn noise_level(filtered: &[i32]) -> f64 {
+    if filtered.is_empty() {
+        return 0.0;
+    }
+    let mut magnitudes: Vec<u32> = filtered.iter().map(|&x| x.unsigned_abs()).collect();
+    let middle = magnitudes.len() / 2;
+    let (_, median, _) = magnitudes.select_nth_unstable(middle);
+    *median as f64 / 0.6745
+}
+
+/// Finds spike peaks in one channel: threshold crossings of the high-passed
+/// signal, aligned to the extremum that follows and separated by at least
+/// the refractory period.
+pub fn detect_spikes(samples: &[i32], channel: usize, config: &SpikeConfig) -> Vec<Spike> {
+    let filtered = highpass(samples);
+    let threshold = (noise_level(&filtered) * config.threshold).max(1.0) as i64;
+    let n = filtered.len();
+
+    let mut spikes = Vec::new();
+    let mut i = config.pre;
+    while i + config.post <= n {
+        if config.polarity.excursion(filtered[i]) <= threshold {
+            i += 1;
+            continue;
+        }
+
+        let search_end = (i + config.refractory.max(1)).min(n + 1 - config.post);
+        let peak = (i..search_end)
+            .max_by_key(|&j| (config.polarity.excursion(filtered[j]), std::cmp::Reverse(j)))
+            .unwrap();
+        spikes.push(Spike {
+            channel,
+            index: peak,
+            amplitude: filtered[peak],
+            waveform: filtered[peak - config.pre..peak + config.post].to_vec(),
+        });
+        i = peak + config.refractory.max(1);
+    }
+    spikes
+}
+
+/// Detects spikes on every channel of an interleaved recording.
+pub fn detect_all(samples: &[i16], spec: &WavSpec, config: &SpikeConfig) -> Vec<Spike> {
+    deinterleave(samples, spec.channels as usize)
+        .iter()
+        .enumerate()
+        .flat_map(|(channel, values)| detect_spikes(values, channel, config))
+        .collect()
+}
+
+/// Writes spikes as CSV: channel, sample index, time in seconds, peak
+/// amplitude, then the filtered waveform samples.
+pub fn write_spikes_csv<W: Write>(
+    writer: &mut W,
+    spikes: &[Spike],
+    sample_rate: u32,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let width = spikes.first().map_or(0, |s| s.waveform.len());
+    write!(writer, "channel,sample,time_s,amplitude")?;
+    for k in 0..width {
+        write!(writer, ",w{}", k)?;
+    }
+    writeln!(writer)?;
+
+    for spike in spikes {
+        write!(
+            writer,
+            "{},{},{:.6},{}",
+            spike.channel,
+            spike.index,
+            spike.index as f64 / sample_rate as f64,
+            spike.amplitude
+        )?;
+        for value in &spike.waveform {
+            write!(writer, ",{}", value)?;
+        }
+        writeln!(writer)?;
+    }
+    Ok(())
+}
+
+/// Raw snippet around a peak with its mean removed, i.e. the spike shape
+/// without the local baseline.
+fn centred_snippet(samples: &[i32], start: usize, len: usize) -> Vec<i32> {
+    let snippet = &samples[start..start + len];
+    let mean = snippet.iter().map(|&s| s as i64).sum::<i64>() / len as i64;
+    snippet.iter().map(|&s| (s as i64 - mean) as i32).collect()
+}
+
+fn squared_distance(a: &[i32], b: &[i32]) -> i64 {
+    a.iter()
+        .zip(b)
+        .map(|(&x, &y)| (x as i64 - y as i64).pow(2))
+        .sum()
+}
+
+/// Spike occurrences chosen for subtraction: `(snippet start, template id)`.
+type Events = Vec<(usize, usize)>;
+
+/// Greedily clusters spike snippets into templates, then keeps only the
+/// events whose template actually reduces the energy it is subtracted from.
+fn build_templates(samples: &[i32], config: &SpikeConfig) -> (Vec<Vec<i32>>, Events) {
+    let len = config.pre + config.post;
+    let starts: Vec<usize> = detect_spikes(samples, 0, config)
+        .iter()
+        .map(|spike| spike.index - config.pre)
+        .collect();
+
+    let mut sums: Vec<Vec<i64>> = Vec::new();
+    let mut counts: Vec<i64> = Vec::new();
+    for &start in &starts {
+        let snippet = centred_snippet(samples, start, len);
+        let energy: i64 = snippet.iter().map(|&s| (s as i64).pow(2)).sum();
+        let nearest = sums
+            .iter()
+            .zip(&counts)
+            .map(|(sum, &count)| {
+                let mean: Vec<i32> = sum.iter().map(|&s| (s / count) as i32).collect();
+                squared_distance(&snippet, &mean)
+            })
+            .enumerate()
+            .min_by_key(|&(i, d)| (d, i));
+
+        match nearest {
+            Some((i, distance)) if distance * 16 < energy * MATCH_FRACTION => {
+                for (acc, &s) in sums[i].iter_mut().zip(&snippet) {
+                    *acc += s as i64;
+                }
+                counts[i] += 1;
+            }
+            _ if sums.len() < MAX_TEMPLATES => {
+                sums.push(snippet.iter().map(|&s| s as i64).collect());
+                counts.push(1);
+            }
+            _ => {}
+        }
+    }
+
+    let templates: Vec<Vec<i32>> = sums
+        .iter()
+        .zip(&counts)
+        .map(|(sum, &count)| sum.iter().map(|&s| (s / count) as i32).collect())
+        .collect();
+
+    let mut events = Vec::new();
+    for &start in &starts {
+        let snippet = centred_snippet(samples, start, len);
+        let energy: i64 = snippet.iter().map(|&s| (s as i64).pow(2)).sum();
+        let best = templates
+            .iter()
+            .enumerate()
+            .map(|(i, template)| (squared_distance(&snippet, template), i))
+            .min();
+        if let Some((distance, id)) = best {
+            if distance < energy {
+                events.push((start, id));
+            }
+        }
+    }
+    (templates, events)
+}
+
+fn apply_events(samples: &mut [i32], templates: &[Vec<i32>], events: &Events, sign: i32) {
+    for &(start, id) in events {
+        for (s, &t) in samples[start..].iter_mut().zip(&templates[id]) {
+            *s = s.wrapping_add(sign * t);
+        }
+    }
+}
+
+fn bits_for(count: usize) -> u32 {
+    (usize::BITS - count.saturating_sub(1).leading_zeros()).max(1)
+}
+
+fn encode_channel(writer: &mut BitWriter, channel: &[i32], config: &SpikeConfig) {
+    let (templates, events) = build_templates(channel, config);
+    let len = config.pre + config.post;
+
+    writer.write_bits(templates.len() as u32, 8);
+    for template in &templates {
+        encode_residuals(writer, template);
+    }
+
+    writer.write_bits(events.len() as u32, 32);
+    let mut previous = 0;
+    let deltas: Vec<i32> = events
+        .iter()
+        .map(|&(start, _)| {
+            let delta = (start - previous) as i32;
+            previous = start;
+            delta
+        })
+        .collect();
+    encode_residuals(writer, &deltas);
+    for &(_, id) in &events {
+        writer.write_bits(id as u32, bits_for(templates.len()));
+    }
+
+    let mut cleaned = channel.to_vec();
+    apply_events(&mut cleaned, &templates, &events, -1);
+    let residuals = LmsCascade::new(DEFAULT_STAGES).encode(&cleaned);
+    encode_residuals_adaptive(writer, &residuals);
+    debug!(
+        "Coded {} spikes against {} templates of {} samples",
+        events.len(),
+        templates.len(),
+        len
+    );
+}
+
+fn decode_channel(
+    reader: &mut BitReader,
+    frames: usize,
+    len: usize,
+) -> Result<Vec<i32>, Box<dyn Error + Send + Sync>> {
+    let template_count = reader.read_bits(8)? as usize;
+    let templates = (0..template_count)
+        .map(|_| decode_residuals(reader, len))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let event_count = reader.read_bits(32)? as usize;
+    let deltas = decode_residuals(reader, event_count)?;
+    let mut events = Vec::with_capacity(event_count);
+    let mut start = 0usize;
+    for delta in deltas {
+        start = start
+            .checked_add(usize::try_from(delta)?)
+            .filter(|&s| s + len <= frames)
+            .ok_or("Spike event outside the recording")?;
+        let id = reader.read_bits(bits_for(template_count))? as usize;
+        if id >= template_count {
+            return Err(Box::from(format!("Invalid spike template id {}", id)));
+        }
+        events.push((start, id));
+    }
+
+    let residuals = decode_residuals_adaptive(reader, frames)?;
+    let mut samples = LmsCascade::new(DEFAULT_STAGES).decode(&residuals);
+    apply_events(&mut samples, &templates, &events, 1);
+    Ok(samples)
+}
+
+/// Compresses samples by subtracting detected spikes as instances of a
+/// per-channel template dictionary, then coding the remaining signal with the
+/// LMS cascade and adaptive rANS.
+pub fn compress_spike(
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    debug!("Compressing data into spike-template format...");
+
+    let config = SpikeConfig::default();
+    let mut writer = BitWriter::with_capacity(BitOrder::Msb, samples.len());
+    write_spec_header(&mut writer, spec, samples.len());
+    writer.write_bits((config.pre + config.post) as u32, 8);
+    for channel in deinterleave(samples, spec.channels as usize) {
+        encode_channel(&mut writer, &channel, &config);
+    }
+
+    debug!("Finished compressing data into spike-template format");
+    Ok(writer.finish())
+}
+
+/// Estimated encoder cost of [`compress_spike`]. Template matching is paid
+/// per detected spike, so it is amortized at [`COST_SPIKE_RATE`]; every
+/// snippet is compared against the full set of [`MAX_TEMPLATES`] templates.
+pub fn cost_spike(spec: &WavSpec) -> CostModel {
+    let config = SpikeConfig::default();
+    let len = (config.pre + config.post) as f64;
+    let templates = MAX_TEMPLATES as f64;
+    let spikes_per_sample = COST_SPIKE_RATE / spec.sample_rate.max(1) as f64;
+    let detection = CostModel {
+        // The moving average's division, and the snippet means and energies per spike.
+        multiplies: 1.0 + spikes_per_sample * (templates * len * 3.0 + len * 2.0 + 2.0),
+        // Prefix sum, filter, median selection, threshold, then per spike the
+        // distances, snippets, peak search and subtraction.
+        additions: 1.0
+            + 6.0
+            + 4.0
+            + 2.0
+            + 1.0
+            + spikes_per_sample * (templates * len * 5.0 + len * 4.0 + config.refractory as f64),
+        // Template sums and means.
+        state_bytes: MAX_TEMPLATES * (config.pre + config.post) * (8 + 4),
+        // Deinterleaved channel, prefix sums, filtered signal, magnitudes,
+        // cleaned signal and residuals.
+        buffer_bytes_per_sample: 4.0 + 8.0 + 4.0 + 4.0 + 4.0 + 4.0,
+    };
+    detection + cascade_cost(DEFAULT_STAGES) + encode_residuals_adaptive_cost()
+}
+
+pub fn decompress_spike(
+    buffer: &[u8],
+) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    debug!("Decompressing data from spike-template format...");
+
+    let mut reader = BitReader::new(buffer, BitOrder::Msb);
+    let (spec, sample_count) = read_spec_header(&mut reader)?;
+    let frames = sample_count / spec.channels as usize;
+    let len = reader.read_bits(8)? as usize;
+
+    let mut channels = Vec::with_capacity(spec.channels as usize);
+    for _ in 0..spec.channels {
+        channels.push(decode_channel(&mut reader, frames, len)?);
+    }
+
+    debug!("Finished decompressing data from spike-template format");
+    Ok((interleave(&channels), spec))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic noise plus a biphasic spike every 997 samples.
+    fn recording() -> (Vec<i32>, Vec<usize>) {
+        let shape = [-40, -180, -420, -600, -380, -60, 120, 210, 180, 110, 50, 10];
+        let mut state = 12345u32;
+        let mut samples: Vec<i32> = (0..30_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((state >> 16) % 81) as i32 - 40
+            })
+            .collect();
+        let mut peaks = Vec::new();
+        for start in (500..29_000).step_by(997) {
+            for (k, &v) in shape.iter().enumerate() {
+                samples[start + k] += v;
+            }
+            peaks.push(start + 3);
+        }
+        (samples, peaks)
+    }
+
+    #[test]
+    fn detects_planted_spikes() {
+        let (samples, peaks) = recording();
+        let spikes = detect_spikes(&samples, 0, &SpikeConfig::default());
+        let found: Vec<usize> = spikes.iter().map(|s| s.index).collect();
+        assert_eq!(found.len(), peaks.len());
+        for (found, expected) in found.iter().zip(&peaks) {
+            assert!(found.abs_diff(*expected) <= 1);
+        }
+        assert!(spikes.iter().all(|s| s.waveform.len() == 32));
+    }
+
+    #[test]
+    fn codec_roundtrip() {
+        let (samples, _) = recording();
+        let samples: Vec<i16> = samples.iter().map(|&s| s as i16).collect();
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 19531,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let compressed = compress_spike(&samples, &spec).unwrap();
+        assert_eq!(decompress_spike(&compressed).unwrap(), (samples, spec));
+    }
+}
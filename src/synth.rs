@@ -0,0 +1,213 @@
+use hound::{SampleFormat, WavSpec};
+
+/// Deterministic SplitMix64 generator, so a seed reproduces the same
+/// recording on every platform without pulling in `rand`.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`.
+    pub fn uniform(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Approximately standard normal: the Irwin-Hall sum of twelve uniforms.
+    /// Unlike Box-Muller it needs no libm functions, keeping output identical
+    /// across platforms.
+    pub fn normal(&mut self) -> f64 {
+        (0..12).map(|_| self.uniform()).sum::<f64>() - 6.0
+    }
+}
+
+/// Parameters of a synthetic recording. Amplitudes are in 16-bit sample units.
+#[derive(Clone, Debug)]
+pub struct SynthConfig {
+    pub seed: u64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub frames: usize,
+    /// Quantization grid `offset + k * step` the output snaps to.
+    pub step: i32,
+    pub offset: i32,
+    /// Standard deviation of the white noise floor.
+    pub white_noise: f64,
+    /// Standard deviation of the coloured (AR(1)-filtered) background.
+    pub colored_noise: f64,
+    /// AR(1) pole of the coloured background: 0 is white, close to 1 is brown.
+    pub color: f64,
+    /// Mean firing rate of each unit, in spikes per second.
+    pub spike_rate: f64,
+    /// Distinct spike shapes (units) per channel.
+    pub units: usize,
+    /// Peak trough depth of the largest unit.
+    pub spike_amplitude: f64,
+    /// Baseline drift in sample units per second.
+    pub drift: f64,
+}
+
+impl Default for SynthConfig {
+    /// Roughly the shape of the dataset's recordings: five seconds of mono
+    /// 19531 Hz audio on a 64-step grid.
+    fn default() -> Self {
+        SynthConfig {
+            seed: 0,
+            sample_rate: 19531,
+            channels: 1,
+            frames: 19531 * 5,
+            step: 64,
+            offset: 31,
+            white_noise: 300.0,
+            colored_noise: 800.0,
+            color: 0.995,
+            spike_rate: 8.0,
+            units: 3,
+            spike_amplitude: 6000.0,
+            drift: 200.0,
+        }
+    }
+}
+
+/// Length of a spike waveform, about 1.6 ms at the default rate.
+const SPIKE_LEN: usize = 32;
+
+/// Smooth compact bump: `(1 - x^2)^2` on `|x| < 1`, zero elsewhere.
+fn bump(x: f64) -> f64 {
+    if x.abs() >= 1.0 {
+        0.0
+    } else {
+        (1.0 - x * x) * (1.0 - x * x)
+    }
+}
+
+/// A biphasic extracellular spike: a sharp trough followed by a broader,
+/// smaller rebound, with shape parameters drawn from `rng`.
+fn spike_shape(rng: &mut Rng, amplitude: f64) -> Vec<f64> {
+    let trough_at = 6.0 + 3.0 * rng.uniform();
+    let trough_width = 2.5 + 2.0 * rng.uniform();
+    let rebound_at = trough_at + 6.0 + 4.0 * rng.uniform();
+    let rebound_width = 5.0 + 4.0 * rng.uniform();
+    let rebound = 0.2 + 0.3 * rng.uniform();
+    (0..SPIKE_LEN)
+        .map(|t| {
+            let t = t as f64;
+            amplitude
+                * (-bump((t - trough_at) / trough_width)
+                    + rebound * bump((t - rebound_at) / rebound_width))
+        })
+        .collect()
+}
+
+fn generate_channel(rng: &mut Rng, config: &SynthConfig) -> Vec<f64> {
+    let mut signal = vec![0.0; config.frames];
+
+    // `sqrt` is correctly rounded, so this stays platform independent.
+    let innovation = config.colored_noise * (1.0 - config.color * config.color).max(0.0).sqrt();
+    let mut background = 0.0;
+    let mut baseline = 0.0;
+    let drift_step = config.drift / config.sample_rate as f64;
+    for value in signal.iter_mut() {
+        background = config.color * background + innovation * rng.normal();
+        baseline += drift_step * rng.normal();
+        *value = baseline + background + config.white_noise * rng.normal();
+    }
+
+    let fire_probability = config.spike_rate / config.sample_rate as f64;
+    for unit in 0..config.units {
+        let amplitude = config.spike_amplitude * (1.0 - 0.5 * unit as f64 / config.units as f64);
+        let shape = spike_shape(rng, amplitude);
+        let mut t = 0;
+        while t + SPIKE_LEN <= config.frames {
+            if rng.uniform() < fire_probability {
+                let jitter = 0.9 + 0.2 * rng.uniform();
+                for (s, &v) in signal[t..].iter_mut().zip(&shape) {
+                    *s += v * jitter;
+                }
+                // Refractory period of one spike length.
+                t += SPIKE_LEN;
+            } else {
+                t += 1;
+            }
+        }
+    }
+    signal
+}
+
+/// Generates an interleaved recording and its spec.
+pub fn generate(config: &SynthConfig) -> (Vec<i16>, WavSpec) {
+    let mut rng = Rng::new(config.seed);
+    let channels: Vec<Vec<f64>> = (0..config.channels)
+        .map(|_| generate_channel(&mut rng, config))
+        .collect();
+
+    let step = config.step.max(1) as f64;
+    let offset = config.offset as f64;
+    let low = (((i16::MIN as f64 - offset) / step).ceil() * step + offset) as i32;
+    let high = (((i16::MAX as f64 - offset) / step).floor() * step + offset) as i32;
+    let mut samples = Vec::with_capacity(config.frames * channels.len());
+    for frame in 0..config.frames {
+        for channel in &channels {
+            let snapped = ((channel[frame] - offset) / step).round() * step + offset;
+            samples.push((snapped as i32).clamp(low, high) as i16);
+        }
+    }
+
+    let spec = WavSpec {
+        channels: config.channels,
+        sample_rate: config.sample_rate,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    (samples, spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::predictor::detect_grid;
+    use crate::spikes::{detect_all, SpikeConfig};
+
+    #[test]
+    fn deterministic_and_on_grid() {
+        let config = SynthConfig {
+            seed: 7,
+            channels: 2,
+            frames: 20_000,
+            ..SynthConfig::default()
+        };
+        let (samples, spec) = generate(&config);
+        assert_eq!(generate(&config), (samples.clone(), spec));
+        assert_ne!(generate(&SynthConfig { seed: 8, ..config }).0, samples);
+
+        assert_eq!(samples.len(), 40_000);
+        let values: Vec<i32> = samples.iter().map(|&s| s as i32).collect();
+        assert_eq!(detect_grid(&values), (64, 31));
+    }
+
+    #[test]
+    fn spikes_are_detectable() {
+        let config = SynthConfig {
+            frames: 19531 * 4,
+            units: 1,
+            spike_rate: 10.0,
+            ..SynthConfig::default()
+        };
+        let (samples, spec) = generate(&config);
+        let detected = detect_all(&samples, &spec, &SpikeConfig::default()).len();
+        assert!(
+            (25..=60).contains(&detected),
+            "detected {} spikes",
+            detected
+        );
+    }
+}
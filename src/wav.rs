@@ -1,17 +1,112 @@
-use hound::{WavReader, WavSpec, WavWriter};
+use crate::bitpack::{BitReader, BitWriter};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
 use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
 use tracing::debug;
 
+/// Most samples one codec stream holds, so corrupt headers and
+/// decompression bombs fail cleanly instead of exhausting memory. Longer
+/// recordings are split across the blocked container.
+pub const MAX_SAMPLES: usize = 1 << 26;
+
+/// Most samples of a whole recording, in memory at once. 2^31 samples is
+/// over 18 minutes of 64 channels at 30 kHz.
+pub const MAX_RECORDING_SAMPLES: usize = 1 << 31;
+
+/// Most bytes a decoder will inflate: `MAX_SAMPLES` 16-bit samples plus
+/// generous room for headers.
+pub const MAX_DECODED_BYTES: usize = MAX_SAMPLES * 2 + (1 << 20);
+
 pub fn read_wav_file(file_path: &str) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
     debug!("Reading WAV file from {}", file_path);
-    let mut reader = WavReader::open(file_path)?;
-    let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+    let mut reader = match WavReader::open(file_path) {
+        Ok(reader) => reader,
+        // Saved from a pipe, with the lengths never filled in.
+        Err(hound::Error::FormatError(_)) => return read_wav(File::open(file_path)?),
+        Err(e) => return Err(e.into()),
+    };
+    check_recording_length(reader.len() as usize)?;
+    let samples = reader.samples().collect::<Result<Vec<i16>, _>>()?;
     let spec = reader.spec();
 
     debug!("Read {} samples", samples.len());
     Ok((samples, spec))
 }
 
+/// Reads a WAV file from a stream that cannot seek, such as stdin. The
+/// whole input is buffered, up to [`MAX_RECORDING_SAMPLES`]. Writers that stream
+/// to a pipe cannot go back to fill in the RIFF and data lengths and leave 0
+/// or `u32::MAX` there instead; the data chunk then runs to the end of the input.
+pub fn read_wav<R: Read>(reader: R) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
+    let limit = MAX_RECORDING_SAMPLES as u64 * 2 + (1 << 20);
+    let mut bytes = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > limit {
+        return Err(Box::from(format!(
+            "WAV input exceeds the limit of {} samples",
+            MAX_RECORDING_SAMPLES
+        )));
+    }
+    patch_streamed_lengths(&mut bytes);
+    let mut reader = WavReader::new(Cursor::new(bytes))?;
+    check_recording_length(reader.len() as usize)?;
+    let samples = reader.samples().collect::<Result<Vec<i16>, _>>()?;
+    let spec = reader.spec();
+
+    debug!("Read {} samples from a stream", samples.len());
+    Ok((samples, spec))
+}
+
+/// Fails for recordings longer than [`MAX_RECORDING_SAMPLES`].
+pub fn check_recording_length(samples: usize) -> Result<(), Box<dyn Error + Send + Sync>> {
+    if samples > MAX_RECORDING_SAMPLES {
+        return Err(Box::from(format!(
+            "Recording of {} samples exceeds the limit of {}",
+            samples, MAX_RECORDING_SAMPLES
+        )));
+    }
+    Ok(())
+}
+
+/// Replaces placeholder RIFF and data lengths with the lengths the input
+/// actually has. Anything that is not a RIFF/WAVE file is left for hound to
+/// reject.
+fn patch_streamed_lengths(bytes: &mut [u8]) {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return;
+    }
+    let length_at = |bytes: &[u8], i: usize| {
+        u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+    };
+    let mut position = 12;
+    while position + 8 <= bytes.len() {
+        let length = length_at(bytes, position + 4);
+        let available = bytes.len() - position - 8;
+        if &bytes[position..position + 4] == b"data" {
+            if length == u32::MAX || (length == 0 && available > 0) {
+                let data = available.min(u32::MAX as usize - 1) as u32;
+                debug!(
+                    "Data chunk length unset, taking the {} bytes to the end",
+                    data
+                );
+                bytes[position + 4..position + 8].copy_from_slice(&data.to_le_bytes());
+                let riff = (bytes.len() - 8).min(u32::MAX as usize) as u32;
+                bytes[4..8].copy_from_slice(&riff.to_le_bytes());
+            }
+            return;
+        }
+        position += 8 + length as usize + (length & 1) as usize;
+    }
+}
+
+/// Reads only the header of a WAV file: its spec and length in frames.
+pub fn read_wav_header(file_path: &str) -> Result<(WavSpec, u32), Box<dyn Error + Send + Sync>> {
+    debug!("Reading WAV header from {}", file_path);
+    let reader = WavReader::open(file_path)?;
+    Ok((reader.spec(), reader.duration()))
+}
+
 pub fn write_wav_file(
     output_path: &str,
     samples: &[i16],
@@ -26,3 +121,397 @@ pub fn write_wav_file(
     debug!("Finished writing WAV file to {}", output_path);
     Ok(())
 }
+
+/// Writes a WAV file to a stream that cannot seek, such as stdout. hound's
+/// writer seeks back to fill in the lengths; here they are known up front, so
+/// the header goes out first and the samples follow as they are converted.
+/// The output is byte-identical to [`write_wav_file`].
+pub fn write_wav<W: Write>(
+    writer: W,
+    samples: &[i16],
+    spec: WavSpec,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(&wav_header(&spec, samples.len())?)?;
+    write_wav_samples(&mut writer, samples, &spec)?;
+    writer.flush()?;
+    debug!("Streamed {} samples as WAV", samples.len());
+    Ok(())
+}
+
+/// Writes the header of a WAV file whose length is not known yet, with the
+/// placeholder lengths [`read_wav`] accepts from a pipe. Samples follow with
+/// [`write_wav_samples`]; outputs that can seek get the real lengths from
+/// [`patch_wav_lengths`] once they are all written.
+pub fn write_wav_stream_header<W: Write>(
+    mut writer: W,
+    spec: &WavSpec,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut header = wav_header(spec, 0)?;
+    let data_len = header.len() - 4;
+    header[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+    header[data_len..].copy_from_slice(&u32::MAX.to_le_bytes());
+    writer.write_all(&header)?;
+    Ok(())
+}
+
+/// Writes the data of a WAV file: samples in the width `spec` declares.
+/// Wrap unbuffered writers in a `BufWriter`.
+pub fn write_wav_samples<W: Write>(
+    mut writer: W,
+    samples: &[i16],
+    spec: &WavSpec,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    match spec.bits_per_sample {
+        8 => {
+            for &sample in samples {
+                let narrow = i8::try_from(sample)
+                    .map_err(|_| format!("Sample {} does not fit in 8 bits", sample))?;
+                writer.write_all(&[(narrow as u8).wrapping_add(128)])?;
+            }
+        }
+        _ => {
+            for &sample in samples {
+                writer.write_all(&sample.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replaces the placeholder header of [`write_wav_stream_header`] with the
+/// one for `sample_count` samples, leaving the output as [`write_wav_file`]
+/// would have written it.
+pub fn patch_wav_lengths<W: Write + Seek>(
+    mut writer: W,
+    spec: &WavSpec,
+    sample_count: usize,
+) -> Result<(), Box<dyn Error + Send + Sync>> {
+    writer.seek(SeekFrom::Start(0))?;
+    writer.write_all(&wav_header(spec, sample_count)?)?;
+    writer.seek(SeekFrom::End(0))?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// GUID of integer PCM in a `WAVE_FORMAT_EXTENSIBLE` header.
+const KSDATAFORMAT_SUBTYPE_PCM: [u8; 16] = [
+    0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xaa, 0x00, 0x38, 0x9b, 0x71,
+];
+
+/// The header hound writes for `sample_count` samples of `spec`: plain PCM
+/// up to two channels of 16 bits, `WAVE_FORMAT_EXTENSIBLE` beyond that.
+fn wav_header(
+    spec: &WavSpec,
+    sample_count: usize,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    if spec.sample_format != SampleFormat::Int || !matches!(spec.bits_per_sample, 8 | 16) {
+        return Err(Box::from(format!(
+            "Cannot write {} bits per sample as WAV",
+            spec.bits_per_sample
+        )));
+    }
+    let bytes_per_sample = spec.bits_per_sample / 8;
+    let data_len = u32::try_from(sample_count * bytes_per_sample as usize)
+        .map_err(|_| "Too many samples for a WAV file")?;
+    let extensible = spec.channels > 2;
+    let fmt_len: u32 = if extensible { 40 } else { 16 };
+    let byte_rate = spec.sample_rate * bytes_per_sample as u32 * spec.channels as u32;
+    let block_align = bytes_per_sample * spec.channels;
+
+    let mut header = Vec::with_capacity(68);
+    header.extend_from_slice(b"RIFF");
+    header.extend_from_slice(&(data_len + fmt_len + 20).to_le_bytes());
+    header.extend_from_slice(b"WAVEfmt ");
+    header.extend_from_slice(&fmt_len.to_le_bytes());
+    header.extend_from_slice(&(if extensible { 0xfffe_u16 } else { 1 }).to_le_bytes());
+    header.extend_from_slice(&spec.channels.to_le_bytes());
+    header.extend_from_slice(&spec.sample_rate.to_le_bytes());
+    header.extend_from_slice(&byte_rate.to_le_bytes());
+    header.extend_from_slice(&block_align.to_le_bytes());
+    header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+    if extensible {
+        let channel_mask = (1u32 << spec.channels.min(18)) - 1;
+        header.extend_from_slice(&22u16.to_le_bytes());
+        header.extend_from_slice(&spec.bits_per_sample.to_le_bytes());
+        header.extend_from_slice(&channel_mask.to_le_bytes());
+        header.extend_from_slice(&KSDATAFORMAT_SUBTYPE_PCM);
+    }
+    header.extend_from_slice(b"data");
+    header.extend_from_slice(&data_len.to_le_bytes());
+    Ok(header)
+}
+
+/// Container of uncompressed samples on the command line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PcmFormat {
+    Wav,
+    /// Headerless little-endian 16-bit PCM, interleaved, as acquisition
+    /// hardware delivers it. The spec travels separately.
+    Raw,
+}
+
+impl PcmFormat {
+    pub fn from_name(name: &str) -> Result<PcmFormat, Box<dyn Error + Send + Sync>> {
+        match name {
+            "wav" => Ok(PcmFormat::Wav),
+            "raw" => Ok(PcmFormat::Raw),
+            _ => Err(Box::from(format!(
+                "Unknown format: {} (expected wav or raw)",
+                name
+            ))),
+        }
+    }
+}
+
+/// Reads headerless PCM of the given spec to the end. Samples are 16-bit
+/// containers whatever `spec.bits_per_sample`, but must fit in that many bits.
+pub fn read_raw<R: Read>(
+    reader: R,
+    spec: &WavSpec,
+) -> Result<Vec<i16>, Box<dyn Error + Send + Sync>> {
+    let samples = RawSamples::new(reader)
+        .take(MAX_RECORDING_SAMPLES + 1)
+        .collect::<Result<Vec<i16>, _>>()?;
+    if samples.len() > MAX_RECORDING_SAMPLES {
+        return Err(Box::from(format!(
+            "Raw PCM exceeds the limit of {} samples",
+            MAX_RECORDING_SAMPLES
+        )));
+    }
+    if !samples.len().is_multiple_of(spec.channels.max(1) as usize) {
+        return Err(Box::from(format!(
+            "Raw PCM of {} samples is not a whole number of {} channel frames",
+            samples.len(),
+            spec.channels
+        )));
+    }
+    let limit = 1i32 << (spec.bits_per_sample.clamp(1, 16) - 1);
+    if let Some(&sample) = samples
+        .iter()
+        .find(|&&s| !(-limit..limit).contains(&(s as i32)))
+    {
+        return Err(Box::from(format!(
+            "Raw sample {} does not fit in {} bits",
+            sample, spec.bits_per_sample
+        )));
+    }
+
+    debug!("Read {} raw samples", samples.len());
+    Ok(samples)
+}
+
+/// Writes samples as headerless little-endian 16-bit PCM, the inverse of
+/// [`read_raw`].
+pub fn write_raw<W: Write>(writer: W, samples: &[i16]) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut writer = BufWriter::new(writer);
+    for &sample in samples {
+        writer.write_all(&sample.to_le_bytes())?;
+    }
+    writer.flush()?;
+    debug!("Wrote {} raw samples", samples.len());
+    Ok(())
+}
+
+/// Headerless little-endian 16-bit PCM, read one sample at a time as
+/// acquisition hardware delivers it. Wrap unbuffered readers in a `BufReader`.
+pub struct RawSamples<R> {
+    reader: R,
+}
+
+impl<R: Read> RawSamples<R> {
+    pub fn new(reader: R) -> Self {
+        RawSamples { reader }
+    }
+}
+
+impl<R: Read> Iterator for RawSamples<R> {
+    type Item = Result<i16, Box<dyn Error + Send + Sync>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut bytes = [0; 2];
+        let mut filled = 0;
+        while filled < bytes.len() {
+            match self.reader.read(&mut bytes[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+        match filled {
+            0 => None,
+            1 => Some(Err(Box::from("Raw PCM ends in the middle of a sample"))),
+            _ => Some(Ok(i16::from_le_bytes(bytes))),
+        }
+    }
+}
+
+/// Reads a decompressor's output to the end, failing once it exceeds
+/// [`MAX_DECODED_BYTES`].
+pub fn read_to_end_limited<R: Read>(reader: R) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let mut output = Vec::new();
+    reader
+        .take(MAX_DECODED_BYTES as u64 + 1)
+        .read_to_end(&mut output)?;
+    if output.len() > MAX_DECODED_BYTES {
+        return Err(Box::from(format!(
+            "Decompressed data exceeds the limit of {} bytes",
+            MAX_DECODED_BYTES
+        )));
+    }
+    Ok(output)
+}
+
+/// Writes the fields of `spec` plus the total sample count, for codecs that
+/// carry their own container instead of a WAV file. The count is at most
+/// [`MAX_SAMPLES`]; longer recordings are split before they reach a codec.
+pub fn write_spec_header(writer: &mut BitWriter, spec: &WavSpec, sample_count: usize) {
+    debug_assert!(sample_count <= MAX_SAMPLES);
+    writer.write_bits(spec.sample_rate, 32);
+    writer.write_bits(spec.channels as u32, 16);
+    writer.write_bits(spec.bits_per_sample as u32, 16);
+    writer.write_bits(sample_count as u32, 32);
+}
+
+pub fn read_spec_header(
+    reader: &mut BitReader,
+) -> Result<(WavSpec, usize), Box<dyn Error + Send + Sync>> {
+    let sample_rate = reader.read_bits(32)?;
+    let channels = reader.read_bits(16)? as u16;
+    let bits_per_sample = reader.read_bits(16)? as u16;
+    let sample_count = reader.read_bits(32)? as usize;
+
+    if channels == 0 || !sample_count.is_multiple_of(channels as usize) {
+        return Err(Box::from(format!(
+            "Invalid header: {} samples across {} channels",
+            sample_count, channels
+        )));
+    }
+    if sample_count > MAX_SAMPLES {
+        return Err(Box::from(format!(
+            "Invalid header: {} samples exceeds the limit of {}",
+            sample_count, MAX_SAMPLES
+        )));
+    }
+    if bits_per_sample == 0 || bits_per_sample > 16 {
+        return Err(Box::from(format!(
+            "Invalid header: unsupported {} bits per sample",
+            bits_per_sample
+        )));
+    }
+
+    let spec = WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample,
+        sample_format: SampleFormat::Int,
+    };
+    Ok((spec, sample_count))
+}
+
+/// Splits interleaved samples into one widened vector per channel.
+pub fn deinterleave(samples: &[i16], channels: usize) -> Vec<Vec<i32>> {
+    (0..channels)
+        .map(|channel| {
+            samples
+                .iter()
+                .skip(channel)
+                .step_by(channels)
+                .map(|&s| s as i32)
+                .collect()
+        })
+        .collect()
+}
+
+/// Inverse of [`deinterleave`]; values are truncated back to 16 bits.
+pub fn interleave(channels: &[Vec<i32>]) -> Vec<i16> {
+    let frames = channels.first().map_or(0, |c| c.len());
+    let mut samples = Vec::with_capacity(frames * channels.len());
+    for frame in 0..frames {
+        for channel in channels {
+            samples.push(channel[frame] as i16);
+        }
+    }
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(channels: u16, bits_per_sample: u16) -> WavSpec {
+        WavSpec {
+            channels,
+            sample_rate: 19531,
+            bits_per_sample,
+            sample_format: SampleFormat::Int,
+        }
+    }
+
+    fn hound_bytes(samples: &[i16], spec: WavSpec) -> Vec<u8> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut cursor, spec).unwrap();
+        for &sample in samples {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        cursor.into_inner()
+    }
+
+    #[test]
+    fn streamed_wav_matches_hound() {
+        for (channels, bits) in [(1, 16), (2, 16), (4, 16), (1, 8), (3, 8)] {
+            let samples: Vec<i16> = (0..channels as i16 * 10).map(|i| i * 5 - 60).collect();
+            let mut streamed = Vec::new();
+            write_wav(&mut streamed, &samples, spec(channels, bits)).unwrap();
+            assert_eq!(streamed, hound_bytes(&samples, spec(channels, bits)));
+            assert_eq!(
+                read_wav(streamed.as_slice()).unwrap(),
+                (samples.clone(), spec(channels, bits))
+            );
+
+            // Written in two blocks before the length is known.
+            let mut blocks = Cursor::new(Vec::new());
+            write_wav_stream_header(&mut blocks, &spec(channels, bits)).unwrap();
+            for half in samples.chunks(samples.len() / 2) {
+                write_wav_samples(&mut blocks, half, &spec(channels, bits)).unwrap();
+            }
+            assert_eq!(
+                read_wav(blocks.get_ref().as_slice()).unwrap(),
+                (samples.clone(), spec(channels, bits))
+            );
+            patch_wav_lengths(&mut blocks, &spec(channels, bits), samples.len()).unwrap();
+            assert_eq!(blocks.into_inner(), streamed);
+        }
+    }
+
+    #[test]
+    fn raw_roundtrip_and_validation() {
+        let samples: Vec<i16> = (-50..50).map(|i| i * 40).collect();
+        let mut bytes = Vec::new();
+        write_raw(&mut bytes, &samples).unwrap();
+        assert_eq!(bytes.len(), samples.len() * 2);
+        assert_eq!(read_raw(bytes.as_slice(), &spec(2, 16)).unwrap(), samples);
+
+        // 100 samples do not make whole frames of 3 channels.
+        assert!(read_raw(bytes.as_slice(), &spec(3, 16)).is_err());
+        // -2000 needs 12 bits.
+        assert!(read_raw(bytes.as_slice(), &spec(2, 11)).is_err());
+        assert!(read_raw(&bytes[..5], &spec(1, 16)).is_err());
+    }
+
+    #[test]
+    fn reads_wav_with_unset_lengths() {
+        let samples: Vec<i16> = (0..100).collect();
+        for placeholder in [0, u32::MAX] {
+            let mut bytes = hound_bytes(&samples, spec(2, 16));
+            bytes[4..8].copy_from_slice(&placeholder.to_le_bytes());
+            bytes[40..44].copy_from_slice(&placeholder.to_le_bytes());
+            assert_eq!(
+                read_wav(bytes.as_slice()).unwrap(),
+                (samples.clone(), spec(2, 16))
+            );
+        }
+    }
+}
@@ -1,6 +1,8 @@
+use crate::cost::CostModel;
+use crate::wav::read_to_end_limited;
 use hound::{WavReader, WavSpec, WavWriter};
 use std::error::Error;
-use std::io::{Cursor, Read, Write};
+use std::io::{Cursor, Write};
 use tracing::debug;
 
 pub fn compress_zstd(
@@ -21,28 +23,38 @@ pub fn compress_zstd(
 
     // Compress WAV data using zstd
     let mut encoder = zstd::Encoder::new(Vec::new(), 0)?; // 0 is the default compression level
-    encoder.write_all(&wav_data.get_ref())?;
+    encoder.write_all(wav_data.get_ref())?;
     let compressed_data = encoder.finish()?;
 
     debug!("Finished compressing data into zstd format");
     Ok(compressed_data)
 }
 
+/// Rough encoder cost of [`compress_zstd`] at the default level 3 (the
+/// `dfast` strategy), per 16-bit sample or two bytes of WAV data: two
+/// multiplicative hashes and a match check per byte, then entropy coding.
+pub fn cost_zstd() -> CostModel {
+    CostModel {
+        multiplies: 2.0 * 2.0,
+        additions: 3.0 + 2.0 * 20.0,
+        // Hash table (2^17 entries) and second hash table (2^16 entries).
+        state_bytes: ((1 << 17) + (1 << 16)) * 4,
+        // The WAV copy, which also serves as the match window.
+        buffer_bytes_per_sample: 2.0,
+    }
+}
+
 pub fn decompress_zstd(buffer: &[u8]) -> Result<(Vec<i16>, WavSpec), Box<dyn Error + Send + Sync>> {
     debug!("Decompressing data from zstd format...");
 
     // Decompress zstd data to WAV in memory
-    let mut decompressed_data = Vec::new();
-    {
-        let mut decoder = zstd::Decoder::new(buffer)?;
-        decoder.read_to_end(&mut decompressed_data)?;
-    }
+    let decompressed_data = read_to_end_limited(zstd::Decoder::new(buffer)?)?;
 
     // Parse WAV data
     let mut cursor = Cursor::new(&decompressed_data);
     let mut reader = WavReader::new(&mut cursor)?;
     let spec = reader.spec();
-    let samples: Vec<i16> = reader.samples().map(|s| s.unwrap()).collect();
+    let samples = reader.samples().collect::<Result<Vec<i16>, _>>()?;
 
     debug!("Finished decompressing data from zstd format");
     Ok((samples, spec))
@@ -0,0 +1,110 @@
+use smallbrain::wav::read_wav_file;
+use std::process::{Command, Output};
+
+fn smallbrain(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_smallbrain"))
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn eight_bit_raw_roundtrips_and_other_depths_are_rejected() {
+    let dir = std::env::temp_dir().join(format!("smallbrain-cli-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let (input, compressed, wav, restored) = (
+        path("input.raw"),
+        path("output.sb"),
+        path("output.wav"),
+        path("output.raw"),
+    );
+    let samples: Vec<i16> = (0..6000).map(|i| ((i * 37) % 200 - 100) as i16).collect();
+    let raw: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+    std::fs::write(&input, &raw).unwrap();
+    let raw_input = ["--input-format", "raw", "--rate", "30000", "--bits"];
+
+    for codec in ["zstd", "rice", "flac", "cm"] {
+        let mut args = vec!["compress", &input, &compressed, "--codec", codec];
+        args.extend(raw_input);
+        args.push("8");
+        assert!(smallbrain(&args).status.success(), "{}", codec);
+
+        assert!(smallbrain(&["decompress", &compressed, &wav])
+            .status
+            .success());
+        let (decoded, spec) = read_wav_file(&wav).unwrap();
+        assert_eq!((decoded, spec.bits_per_sample), (samples.clone(), 8));
+
+        let output = smallbrain(&[
+            "decompress",
+            &compressed,
+            &restored,
+            "--output-format",
+            "raw",
+        ]);
+        assert!(output.status.success(), "{}", codec);
+        assert_eq!(std::fs::read(&restored).unwrap(), raw, "{}", codec);
+    }
+
+    // Other depths would compress but not come back out as WAV.
+    let mut args = vec!["compress", &input, &compressed];
+    args.extend(raw_input);
+    args.push("12");
+    let output = smallbrain(&args);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("8 or 16 bits"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_decompress_leaves_no_output() {
+    let dir = std::env::temp_dir().join(format!("smallbrain-cli-fail-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let (input, compressed, decoded) = (path("input.raw"), path("output.sb"), path("output"));
+    let raw: Vec<u8> = (0..40_000)
+        .flat_map(|i: i32| ((i % 300) as i16).to_le_bytes())
+        .collect();
+    std::fs::write(&input, raw).unwrap();
+    let args = [
+        "compress",
+        &input,
+        &compressed,
+        "--input-format",
+        "raw",
+        "--rate",
+        "30000",
+        "--codec",
+        "rice",
+        "--block-size",
+        "10000",
+    ];
+    assert!(smallbrain(&args).status.success());
+
+    // An unknown tag on the last block: on one thread the blocks before it
+    // are decoded and written before the error.
+    let mut bytes = std::fs::read(&compressed).unwrap();
+    let count = u32::from_be_bytes(bytes[1..5].try_into().unwrap()) as usize;
+    let at = 5 + 4 * (count - 1);
+    let last = u32::from_be_bytes(bytes[at..at + 4].try_into().unwrap()) as usize;
+    let end = bytes.len();
+    bytes[end - last] = 0x77;
+    std::fs::write(&compressed, bytes).unwrap();
+    for format in ["wav", "raw"] {
+        let output = Command::new(env!("CARGO_BIN_EXE_smallbrain"))
+            .args([
+                "decompress",
+                &compressed,
+                &decoded,
+                "--output-format",
+                format,
+            ])
+            .env("RAYON_NUM_THREADS", "1")
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(!dir.join("output").exists(), "{}", format);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,118 @@
+use hound::{SampleFormat, WavSpec};
+use smallbrain::codec::Codec;
+use smallbrain::wav::write_wav_file;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const THREAD_COUNTS: [&str; 3] = ["1", "2", "8"];
+
+/// Output hashes of every codec whose output must match across platforms,
+/// unblocked and in 8192-frame blocks. flac and flac-brotli are left out:
+/// flacenc's window goes through libm. A codec change that alters output
+/// must update this table.
+const GOLDEN_HASHES: &[(&str, u64, u64)] = &[
+    ("zstd", 0x9fbb9029fd4dcffd, 0x8ee97c2a147375b7),
+    ("zlib", 0xdb03a030e5d8c3fc, 0x3f5d54b53baacb25),
+    ("rice", 0xd5c6b97e99004bce, 0xe871f1f00b5d443a),
+    ("ans", 0x193ba8a98a2f29cb, 0x41ea636dde1bcdb8),
+    ("ans-adaptive", 0x5bdf4f494b76fb95, 0x1d4d5890fbd44700),
+    ("cm", 0xb53d1135b4f61336, 0xd0afdec7588ad07f),
+    ("lms-rice", 0xcf3f8ab1b0e5a473, 0x32dc44a60cf53adf),
+    ("lms-ans", 0x74da37f525587f9b, 0x8f4b419ca4b6bc9e),
+    ("spike", 0x83ff7bbb47ea884d, 0x889b3eb043082565),
+    ("realtime", 0x5be1314a398e0e9f, 0x7a29555183c48b6f),
+    ("embedded", 0x3aa366e9029d5b03, 0x11e5ec91b96019b6),
+];
+
+/// FNV-1a, so the expected hashes do not depend on std's hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Two channels of quantized oscillation, noise and occasional spikes.
+fn write_input(dir: &Path) -> PathBuf {
+    let spec = WavSpec {
+        channels: 2,
+        sample_rate: 19531,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut state = 0x2545_f491u32;
+    let samples: Vec<i16> = (0..20_000)
+        .map(|i: i32| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            let wave = (i / 2) % 400 - 200;
+            let spike = if (i / 2) % 1500 < 8 { -900 } else { 0 };
+            let noise = (state % 129) as i32 - 64;
+            ((wave + spike + noise) * 16 + 3) as i16
+        })
+        .collect();
+
+    let path = dir.join("input.wav");
+    write_wav_file(path.to_str().unwrap(), &samples, spec).unwrap();
+    path
+}
+
+fn compress(input: &Path, output: &Path, codec: Codec, threads: &str, blocked: bool) -> u64 {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_smallbrain"));
+    command
+        .arg("compress")
+        .arg(input)
+        .arg(output)
+        .args(["--codec", codec.name()])
+        .env("RAYON_NUM_THREADS", threads);
+    if blocked {
+        command.args(["--block-size", "8192"]);
+    }
+    let status = command.status().unwrap();
+    assert!(status.success(), "compress failed for {}", codec.name());
+    fnv1a(&std::fs::read(output).unwrap())
+}
+
+#[test]
+fn compress_is_identical_across_runs_and_thread_counts() {
+    let dir = std::env::temp_dir().join(format!("smallbrain-determinism-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = write_input(&dir);
+    let output = dir.join("output.sb");
+
+    for &codec in Codec::ALL {
+        let golden = GOLDEN_HASHES
+            .iter()
+            .find(|(name, ..)| *name == codec.name())
+            .map(|&(_, single, blocked)| [single, blocked]);
+        assert!(
+            golden.is_some() || matches!(codec, Codec::Flac | Codec::FlacBrotli),
+            "{} has no golden hash",
+            codec.name()
+        );
+        for blocked in [false, true] {
+            let reference = compress(&input, &output, codec, THREAD_COUNTS[0], blocked);
+            if let Some(golden) = golden {
+                assert_eq!(
+                    reference,
+                    golden[blocked as usize],
+                    "{} (blocked: {}) no longer matches its golden hash",
+                    codec.name(),
+                    blocked
+                );
+            }
+            for threads in THREAD_COUNTS {
+                assert_eq!(
+                    compress(&input, &output, codec, threads, blocked),
+                    reference,
+                    "{} (blocked: {}) differs with RAYON_NUM_THREADS={}",
+                    codec.name(),
+                    blocked,
+                    threads
+                );
+            }
+        }
+    }
+
+    std::fs::remove_dir_all(&dir).ok();
+}
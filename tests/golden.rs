@@ -0,0 +1,253 @@
+use hound::{SampleFormat, WavSpec};
+use smallbrain::brotli_sb::{compress_brotli, decompress_brotli};
+use smallbrain::codec::{decompress, Codec};
+use smallbrain::flac::{compress_flac, decompress_flac};
+use smallbrain::synth::{generate, SynthConfig};
+use smallbrain::wav::{read_wav_file, MAX_SAMPLES};
+use smallbrain::zlib::{compress_zlib, decompress_zlib};
+use smallbrain::zstd::{compress_zstd, decompress_zstd};
+use std::panic::catch_unwind;
+
+/// Checked-in recordings under `tests/data`.
+const FILES: [&str; 3] = ["mono_quantized.wav", "stereo_spikes.wav", "tiny.wav"];
+
+/// Compressed sizes in bytes (codec tag included) of the checked-in files.
+/// Codec changes that move a size by more than `TOLERANCE` must update this
+/// table, so size regressions show up in review.
+const GOLDEN_SIZES: &[(&str, &str, usize)] = &[
+    ("mono_quantized.wav", "zstd", 9290),
+    ("mono_quantized.wav", "zlib", 8531),
+    ("mono_quantized.wav", "flac", 10990),
+    ("mono_quantized.wav", "flac-brotli", 7005),
+    ("mono_quantized.wav", "rice", 10858),
+    ("mono_quantized.wav", "ans", 10030),
+    ("mono_quantized.wav", "ans-adaptive", 10011),
+    ("mono_quantized.wav", "cm", 4781),
+    ("mono_quantized.wav", "lms-rice", 10878),
+    ("mono_quantized.wav", "lms-ans", 10663),
+    ("mono_quantized.wav", "spike", 10865),
+    ("mono_quantized.wav", "realtime", 5724),
+    ("mono_quantized.wav", "embedded", 5331),
+    ("stereo_spikes.wav", "zstd", 10013),
+    ("stereo_spikes.wav", "zlib", 9894),
+    ("stereo_spikes.wav", "flac", 10953),
+    ("stereo_spikes.wav", "flac-brotli", 7936),
+    ("stereo_spikes.wav", "rice", 10858),
+    ("stereo_spikes.wav", "ans", 10116),
+    ("stereo_spikes.wav", "ans-adaptive", 10060),
+    ("stereo_spikes.wav", "cm", 4874),
+    ("stereo_spikes.wav", "lms-rice", 10873),
+    ("stereo_spikes.wav", "lms-ans", 10704),
+    ("stereo_spikes.wav", "spike", 10915),
+    ("stereo_spikes.wav", "realtime", 5351),
+    ("stereo_spikes.wav", "embedded", 5290),
+    ("tiny.wav", "zstd", 59),
+    ("tiny.wav", "zlib", 62),
+    ("tiny.wav", "flac", 666),
+    ("tiny.wav", "flac-brotli", 122),
+    ("tiny.wav", "rice", 19),
+    ("tiny.wav", "ans", 81),
+    ("tiny.wav", "ans-adaptive", 31),
+    ("tiny.wav", "cm", 71),
+    ("tiny.wav", "lms-rice", 23),
+    ("tiny.wav", "lms-ans", 36),
+    ("tiny.wav", "spike", 37),
+    ("tiny.wav", "realtime", 26),
+    ("tiny.wav", "embedded", 22),
+];
+
+/// Allowed relative deviation from a golden size.
+const TOLERANCE: f64 = 0.02;
+
+fn spec(channels: u16) -> WavSpec {
+    WavSpec {
+        channels,
+        sample_rate: 19531,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    }
+}
+
+fn read(name: &str) -> (Vec<i16>, WavSpec) {
+    let path = format!("{}/tests/data/{}", env!("CARGO_MANIFEST_DIR"), name);
+    read_wav_file(&path).unwrap()
+}
+
+/// The checked-in files plus synthetic edge cases.
+fn corpus() -> Vec<(String, Vec<i16>, WavSpec)> {
+    let mut inputs: Vec<_> = FILES
+        .iter()
+        .map(|name| {
+            let (samples, spec) = read(name);
+            (name.to_string(), samples, spec)
+        })
+        .collect();
+
+    let extremes: Vec<i16> = (0..1000)
+        .map(|i| if i % 2 == 0 { i16::MIN } else { i16::MAX })
+        .collect();
+    let ramp: Vec<i16> = (0..999).map(|i| (i * 37 - 18_000) as i16).collect();
+    let (synth, synth_spec) = generate(&SynthConfig {
+        channels: 2,
+        frames: 5000,
+        ..SynthConfig::default()
+    });
+    inputs.extend([
+        ("synth".to_string(), synth, synth_spec),
+        ("empty".to_string(), vec![], spec(1)),
+        ("single".to_string(), vec![-5], spec(1)),
+        ("constant".to_string(), vec![7; 1000], spec(1)),
+        ("extremes".to_string(), extremes, spec(1)),
+        ("three_channels".to_string(), ramp, spec(3)),
+    ]);
+    inputs
+}
+
+#[test]
+fn every_codec_roundtrips_corpus() {
+    for (name, samples, spec) in corpus() {
+        for &codec in Codec::ALL {
+            let compressed = codec
+                .compress(&samples, &spec)
+                .unwrap_or_else(|e| panic!("{} with {}: {}", name, codec.name(), e));
+            assert_eq!(
+                decompress(&compressed).unwrap_or_else(|e| panic!(
+                    "{} with {}: {}",
+                    name,
+                    codec.name(),
+                    e
+                )),
+                (samples.clone(), spec),
+                "{} with {}",
+                name,
+                codec.name()
+            );
+
+            let blocked = codec
+                .compress_blocked(&samples, &spec, 1000)
+                .unwrap_or_else(|e| panic!("{} with blocked {}: {}", name, codec.name(), e));
+            assert_eq!(
+                decompress(&blocked).unwrap(),
+                (samples.clone(), spec),
+                "{} with blocked {}",
+                name,
+                codec.name()
+            );
+        }
+    }
+}
+
+#[test]
+fn module_functions_roundtrip() {
+    let (samples, spec) = read("stereo_spikes.wav");
+
+    let zstd = compress_zstd(&samples, &spec).unwrap();
+    assert_eq!(decompress_zstd(&zstd).unwrap(), (samples.clone(), spec));
+
+    let zlib = compress_zlib(&samples, &spec).unwrap();
+    assert_eq!(decompress_zlib(&zlib).unwrap(), (samples.clone(), spec));
+
+    let flac = compress_flac(&samples, &spec).unwrap();
+    assert_eq!(decompress_flac(&flac).unwrap(), (samples.clone(), spec));
+
+    let brotli = compress_brotli(&flac).unwrap();
+    assert_eq!(decompress_brotli(&brotli).unwrap(), flac);
+}
+
+#[test]
+fn golden_sizes() {
+    let mut failures = Vec::new();
+    for &(name, codec, expected) in GOLDEN_SIZES {
+        let (samples, spec) = read(name);
+        let actual = Codec::from_name(codec)
+            .unwrap()
+            .compress(&samples, &spec)
+            .unwrap()
+            .len();
+        if (actual as f64 - expected as f64).abs() > expected as f64 * TOLERANCE {
+            failures.push(format!("(\"{}\", \"{}\", {}),", name, codec, actual));
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "sizes outside tolerance, actual values:\n{}",
+        failures.join("\n")
+    );
+}
+
+/// Truncations, byte flips and garbage payloads derived from `valid`.
+fn corruptions(valid: &[u8]) -> Vec<Vec<u8>> {
+    let mut inputs = Vec::new();
+    let step = (valid.len() / 16).max(1);
+    for len in (0..valid.len()).step_by(step) {
+        inputs.push(valid[..len].to_vec());
+    }
+    for position in (0..valid.len()).step_by(step) {
+        for mask in [0x01, 0x80, 0xFF] {
+            let mut flipped = valid.to_vec();
+            flipped[position] ^= mask;
+            inputs.push(flipped);
+        }
+    }
+
+    let mut state = 0x9E37_79B9u32;
+    for len in [1, 2, 8, 64, 512] {
+        let mut garbage = vec![valid.first().copied().unwrap_or(0)];
+        garbage.extend((1..len).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }));
+        inputs.push(garbage);
+    }
+    inputs
+}
+
+#[test]
+fn corrupted_input_never_panics() {
+    let (samples, spec) = read("stereo_spikes.wav");
+    for &codec in Codec::ALL {
+        let valid = [
+            codec.compress(&samples, &spec).unwrap(),
+            codec.compress_blocked(&samples, &spec, 1500).unwrap(),
+        ];
+        for input in valid.iter().flat_map(|v| corruptions(v)) {
+            let result = catch_unwind(|| {
+                let _ = decompress(&input);
+            });
+            assert!(
+                result.is_ok(),
+                "{} decoder panicked on a {}-byte input",
+                codec.name(),
+                input.len()
+            );
+        }
+    }
+}
+
+#[test]
+fn oversized_headers_fail_cleanly() {
+    // A rice stream claiming 2^32 - 1 mono samples with no data behind it.
+    let mut header = vec![4];
+    header.extend_from_slice(&19531u32.to_be_bytes());
+    header.extend_from_slice(&1u16.to_be_bytes());
+    header.extend_from_slice(&16u16.to_be_bytes());
+    header.extend_from_slice(&u32::MAX.to_be_bytes());
+    assert!(decompress(&header).is_err());
+}
+
+#[test]
+fn recordings_past_the_stream_limit_roundtrip() {
+    // One sample more than a single stream holds: compress splits it across
+    // the blocked container instead of writing a stream no decoder accepts.
+    let spec = spec(1);
+    let samples: Vec<i16> = (0..MAX_SAMPLES + 1)
+        .map(|i| ((i * 7919) % 401) as i16 - 200)
+        .collect();
+    let compressed = Codec::Zstd.compress(&samples, &spec).unwrap();
+    assert_eq!(decompress(&compressed).unwrap(), (samples.clone(), spec));
+    assert!(Codec::Zstd
+        .compress_blocked(&samples, &spec, samples.len())
+        .is_err());
+}